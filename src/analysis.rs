@@ -0,0 +1,1708 @@
+// Per-block sandwich detection: classifies a block's transactions, tracks their legs into patterns, and enriches
+// completed patterns with their victims, for use as a library as well as by the CLI
+
+use futures::StreamExt;
+use helius::{types::Asset, Helius};
+use std::{collections::HashMap, ops::Range, str::FromStr, time::Duration};
+use tokio::time::sleep;
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::pubsub_client::PubsubClient,
+    rpc_config::{RpcBlockConfig, RpcTransactionConfig},
+    rpc_custom_error::JSON_RPC_SERVER_ERROR_UNSUPPORTED_TRANSACTION_VERSION,
+    rpc_request::RpcError,
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature,
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransactionWithStatusMeta, TransactionDetails, UiConfirmedBlock,
+    UiTransactionEncoding, UiTransactionStatusMeta, UiTransactionTokenBalance,
+};
+
+use crate::balances::sol_equivalent_deltas;
+use crate::classify::{full_account_keys, Classifier};
+use crate::error::{parse_pubkey, SandwichError};
+use crate::metrics::Metrics;
+use crate::source::BlockSource;
+use crate::types::{
+    BlockAnalysis, BlockComposition, ClassifiedTransaction, DecimalsCache, IncompletePattern, MintLayout, Pattern,
+    PatternKind, PatternTracker, RejectReason, SlotSampling, SolAccounting, SwapAttempts, VictimSwap,
+    DEFAULT_MAX_BLOCK_GAP, DEFAULT_MAX_TIME_SPAN_SECS,
+};
+
+// Lamports charged per signature regardless of priority
+const BASE_FEE_PER_SIGNATURE: u64 = 5000;
+
+// Most accounts a single getMultipleAccounts request accepts
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+// Most slots caught up on after a websocket reconnect
+pub const MAX_BACKFILL_SLOTS: u64 = 150;
+// Websocket reconnect backoff bounds
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+// Options controlling how each block is analyzed
+pub struct AnalysisOptions {
+    // Fetch each token's decimals so amounts can be shown in UI units
+    // Skipping this saves one RPC call per mint, leaving amounts in base units
+    pub resolve_decimals: bool,
+    // Where to read decimals from in mint account data
+    pub mint_layout: MintLayout,
+    // Drop round-trips with no victim between the legs
+    pub exclude_arbitrage: bool,
+    // Count vote vs non-vote transactions for block composition analysis
+    pub count_votes: bool,
+    // Print every classified transaction as a JSON line
+    pub emit_classified: bool,
+    // Which balance changes SOL profit is computed from
+    pub sol_accounting: SolAccounting,
+    // Skip transactions paying less than this priority fee (the fee above the base fee), in lamports
+    pub min_priority_fee: u64,
+    // Skip transactions consuming fewer compute units than this
+    pub min_compute_units: u64,
+    // Highest transaction version requested from the RPC; blocks with newer transactions fail to fetch
+    pub max_transaction_version: u8,
+    // Target program, instruction map, and classification gating
+    pub classifier: Classifier,
+    // Reject patterns whose create and swap-out are further apart than this many seconds
+    pub max_time_span_secs: u64,
+    // Most slots a sandwich's swap-out may land after its create
+    pub max_block_gap: u64,
+    // Drop patterns whose token decimals couldn't be fetched rather than report amounts in default decimals
+    pub strict_decimals: bool,
+    // Report the create/swap-in legs left without a matching swap-out at the end of each block
+    pub explain_incomplete: bool,
+    // Also classify failed transactions, counting reverted swap legs instead of ignoring them
+    pub track_failed: bool,
+    // Analyze only a deterministic sample of the slots in a range or of the recent blocks
+    pub sampling: Option<SlotSampling>,
+    // Counters to update as blocks are analyzed and RPC requests fail, if any
+    pub metrics: Option<&'static Metrics>,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            resolve_decimals: true,
+            mint_layout: MintLayout::default(),
+            exclude_arbitrage: false,
+            count_votes: false,
+            emit_classified: false,
+            sol_accounting: SolAccounting::default(),
+            min_priority_fee: 0,
+            min_compute_units: 0,
+            max_transaction_version: 0,
+            classifier: Classifier::default(),
+            max_time_span_secs: DEFAULT_MAX_TIME_SPAN_SECS,
+            max_block_gap: DEFAULT_MAX_BLOCK_GAP,
+            strict_decimals: false,
+            explain_incomplete: false,
+            track_failed: false,
+            sampling: None,
+            metrics: None,
+        }
+    }
+}
+
+// Picks a token's symbol from its DAS asset: fungible tokens carry it in the token info, and otherwise in the
+// metadata content
+// Blank symbols count as missing, so the token is shown by its mint address instead
+pub fn asset_symbol(asset: &Asset) -> Option<String> {
+    asset
+        .token_info
+        .as_ref()
+        .and_then(|token_info| token_info.symbol.as_deref())
+        .or_else(|| {
+            asset
+                .content
+                .as_ref()
+                .and_then(|content| content.metadata.symbol.as_deref())
+        })
+        .map(|symbol: &str| symbol.trim().to_string())
+        .filter(|symbol: &String| !symbol.is_empty())
+}
+
+// Resolves the decimals of a single mint, through the cache
+// Unlike the batch lookup, which reports and skips mints it can't resolve, any failure is returned to the caller
+pub async fn get_token_decimals<B: BlockSource>(
+    source: &B,
+    cache: &DecimalsCache,
+    mint: &str,
+    layout: MintLayout,
+) -> Result<u8, SandwichError> {
+    if let Some(decimals) = cache.get(mint)? {
+        return Ok(decimals);
+    }
+
+    let pubkey: Pubkey = parse_pubkey(mint)?;
+    let account: Account = source
+        .get_multiple_accounts(&[pubkey])?
+        .into_iter()
+        .next()
+        .flatten()
+        .ok_or_else(|| SandwichError::MalformedAccountData(format!("Mint account {} not found", mint)))?;
+
+    let decimals: u8 = layout
+        .for_owner(&account.owner.to_string())?
+        .parse_decimals(&account.data)?;
+    cache.insert(mint, decimals)?;
+
+    Ok(decimals)
+}
+
+// Resolves the decimals of every mint, fetching mints missing from the cache with as few getMultipleAccounts calls
+// as possible and caching them
+// Mints that can't be resolved (invalid addresses, missing accounts, or non-mint data) are reported and left out
+pub async fn get_token_decimals_batch<B: BlockSource>(
+    source: &B,
+    cache: &DecimalsCache,
+    mints: &[String],
+    layout: MintLayout,
+) -> Result<HashMap<String, u8>, SandwichError> {
+    let mut resolved: HashMap<String, u8> = HashMap::new();
+    let mut uncached: Vec<(String, Pubkey)> = Vec::new();
+
+    for mint in mints {
+        if resolved.contains_key(mint) || uncached.iter().any(|(uncached_mint, _)| uncached_mint == mint) {
+            continue;
+        }
+
+        if let Some(decimals) = cache.get(mint)? {
+            resolved.insert(mint.clone(), decimals);
+            continue;
+        }
+
+        match parse_pubkey(mint) {
+            Ok(pubkey) => uncached.push((mint.clone(), pubkey)),
+            Err(e) => eprintln!("Failed to fetch decimals for token {}: {}", mint, e),
+        }
+    }
+
+    for chunk in uncached.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let pubkeys: Vec<Pubkey> = chunk.iter().map(|(_, pubkey)| *pubkey).collect();
+        let accounts: Vec<Option<Account>> = source.get_multiple_accounts(&pubkeys)?;
+
+        for ((mint, _), account) in chunk.iter().zip(accounts) {
+            let account: Account = match account {
+                Some(account) => account,
+                None => {
+                    eprintln!("Failed to fetch decimals for token {}: account not found", mint);
+                    continue;
+                }
+            };
+
+            // The owning program decides whether the mint uses the legacy or Token-2022 layout
+            match layout
+                .for_owner(&account.owner.to_string())
+                .and_then(|layout| layout.parse_decimals(&account.data))
+            {
+                Ok(decimals) => {
+                    cache.insert(mint, decimals)?;
+                    resolved.insert(mint.clone(), decimals);
+                }
+                Err(e) => eprintln!("Failed to fetch decimals for token {}: {}", mint, e),
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+// Block fetch config accepting transactions up to the given version
+pub fn block_config(max_transaction_version: u8, commitment: Option<CommitmentConfig>) -> RpcBlockConfig {
+    RpcBlockConfig {
+        commitment,
+        max_supported_transaction_version: Some(max_transaction_version),
+        transaction_details: Some(TransactionDetails::Full),
+        rewards: Some(true),
+        encoding: Some(UiTransactionEncoding::Base64),
+    }
+}
+
+// Checks whether an RPC error was caused by a transaction newer than max_supported_transaction_version
+pub fn is_unsupported_version_error(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == JSON_RPC_SERVER_ERROR_UNSUPPORTED_TRANSACTION_VERSION
+    )
+}
+
+// Logs a failed block fetch, calling out unsupported transaction versions so they aren't mistaken for other skips
+pub fn report_block_fetch_error(slot: u64, error: &ClientError, options: &AnalysisOptions) {
+    if is_unsupported_version_error(error) {
+        eprintln!(
+            "Skipping block at slot {}: it contains transactions newer than version {} ({}). \
+             Raise --max-transaction-version to analyze it",
+            slot, options.max_transaction_version, error
+        );
+    } else {
+        eprintln!("Failed to fetch block at slot {}: {}", slot, error);
+    }
+
+    if let Some(metrics) = options.metrics {
+        metrics.rpc_errors.inc();
+    }
+}
+
+// Fetches the block at slot, reporting a failed fetch (e.g. a skipped slot) and returning None for it
+pub fn get_block_by_slot<B: BlockSource>(source: &B, slot: u64, options: &AnalysisOptions) -> Option<UiConfirmedBlock> {
+    match source.get_block_with_config(slot, block_config(options.max_transaction_version, None)) {
+        Ok(block) => Some(block),
+        Err(e) => {
+            report_block_fetch_error(slot, &e, options);
+            None
+        }
+    }
+}
+
+// Creates a pattern tracker configured from the analysis options
+// Without a block gap, callers use a fresh tracker per block; with one, a single tracker is fed blocks oldest first so a
+// sandwich's legs can complete in a later block
+pub fn new_tracker(options: &AnalysisOptions) -> PatternTracker {
+    let mut pattern_tracker: PatternTracker = PatternTracker::new();
+    pattern_tracker.set_max_time_span(Some(options.max_time_span_secs));
+    pattern_tracker.set_max_block_gap(options.max_block_gap);
+    pattern_tracker.set_max_pending_slots(options.max_block_gap);
+
+    if options.emit_classified {
+        pattern_tracker.set_on_classified(|tx| match serde_json::to_string(tx) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Failed to serialize classified transaction {}: {}", tx.signature, e),
+        });
+    }
+
+    pattern_tracker
+}
+
+// Returns a transaction's signature and first signer, if it can be decoded
+fn signature_and_signer(tx: &EncodedTransactionWithStatusMeta) -> Option<(String, String)> {
+    let versioned_tx: VersionedTransaction = tx.transaction.decode()?;
+    let signature: String = versioned_tx.signatures.first()?.to_string();
+    let signer: String = versioned_tx.message.static_account_keys().first()?.to_string();
+
+    Some((signature, signer))
+}
+
+// Checks whether a transaction touched the pattern's pool, i.e. moved the pattern's token in or out of a token
+// account the pool owns
+// Trades on other pools of the same token weren't moved by the front-run, so they aren't victims
+// Always true if the pattern's pool is unknown
+fn trades_against_pool(tx: &EncodedTransactionWithStatusMeta, pattern: &Pattern) -> bool {
+    let pool: &str = match &pattern.pool {
+        Some(pool) => pool,
+        None => return true,
+    };
+
+    let meta: &UiTransactionStatusMeta = match &tx.meta {
+        Some(meta) => meta,
+        None => return false,
+    };
+
+    let pre_token_balances: Option<&Vec<UiTransactionTokenBalance>> = meta.pre_token_balances.as_ref().into();
+    let post_token_balances: Option<&Vec<UiTransactionTokenBalance>> = meta.post_token_balances.as_ref().into();
+
+    pre_token_balances
+        .into_iter()
+        .chain(post_token_balances)
+        .flatten()
+        .any(|balance| {
+            let owner: Option<&String> = balance.owner.as_ref().into();
+            balance.mint == pattern.token && owner.is_some_and(|owner| owner == pool)
+        })
+}
+
+// Returns the block positions of the transactions between a pattern's swap-in and swap-out
+// If the swap-in landed in an earlier block, the window runs from the start of the swap-out's block, so only victims
+// in that block are seen
+fn victim_window(block_signers: &[Option<(String, String)>], pattern: &Pattern) -> Option<Range<usize>> {
+    let position = |signature: &str| {
+        block_signers
+            .iter()
+            .position(|entry| entry.as_ref().is_some_and(|(sig, _)| sig == signature))
+    };
+
+    let swap_out_idx: usize = position(&pattern.swap_out_tx().signature)?;
+
+    match position(&pattern.swap_in_tx().signature) {
+        Some(swap_in_idx) if swap_in_idx < swap_out_idx => Some(swap_in_idx + 1..swap_out_idx),
+        None if pattern.swap_in_tx().slot < pattern.swap_out_tx().slot => Some(0..swap_out_idx),
+        _ => None,
+    }
+}
+
+// Finds buys of the pattern's token by other signers between its swap-in and swap-out
+// A buy is a successful transaction that increased one of the signer's token accounts for the mint and traded
+// against the attacker's pool, if it's known; the SOL spent is the signer's combined native SOL and wSOL decrease,
+// less the transaction fee
+// This is the only definition of a victim: patterns without one are round-trips (e.g. arbitrage), not sandwiches
+fn find_victim_swaps(
+    transactions: &[EncodedTransactionWithStatusMeta],
+    block_signers: &[Option<(String, String)>],
+    pattern: &Pattern,
+) -> Vec<VictimSwap> {
+    let window: Range<usize> = match victim_window(block_signers, pattern) {
+        Some(window) => window,
+        None => return Vec::new(),
+    };
+
+    window
+        .filter_map(|idx| {
+            let (signature, signer) = block_signers[idx].as_ref()?;
+            if *signer == pattern.attacker {
+                return None;
+            }
+
+            let meta: &UiTransactionStatusMeta = transactions[idx].meta.as_ref()?;
+            if !is_transaction_successful(meta) || !trades_against_pool(&transactions[idx], pattern) {
+                return None;
+            }
+
+            let versioned_tx: VersionedTransaction = transactions[idx].transaction.decode()?;
+            let num_signers: usize = versioned_tx.message.header().num_required_signatures as usize;
+            let account_keys: Vec<Pubkey> = full_account_keys(&versioned_tx.message, Some(meta)).ok()?;
+            let pre_token_balances: &[UiTransactionTokenBalance] =
+                meta.pre_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]);
+            let post_token_balances: &[UiTransactionTokenBalance] =
+                meta.post_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]);
+
+            let (token_account, tokens_received) = post_token_balances
+                .iter()
+                .filter(|post| {
+                    post.mint == pattern.token && post.owner.as_ref().map(|owner| owner == signer) == Some(true)
+                })
+                .find_map(|post| {
+                    let pre_amount: u64 = pre_token_balances
+                        .iter()
+                        .find(|pre| pre.account_index == post.account_index)
+                        .map_or(0, |pre| pre.ui_token_amount.amount.parse::<u64>().unwrap_or(0));
+                    let post_amount: u64 = post.ui_token_amount.amount.parse::<u64>().unwrap_or(0);
+                    let account: &Pubkey = account_keys.get(post.account_index as usize)?;
+
+                    (post_amount > pre_amount).then(|| (account.to_string(), post_amount - pre_amount))
+                })?;
+
+            let sol_change: i64 = sol_equivalent_deltas(
+                &account_keys,
+                &meta.pre_balances,
+                &meta.post_balances,
+                pre_token_balances,
+                post_token_balances,
+            )
+            .get(signer)
+            .copied()
+            .unwrap_or(0);
+            let sol_in: u64 = (-sol_change - meta.fee as i64).max(0) as u64;
+
+            Some(VictimSwap {
+                signature: signature.clone(),
+                signer: signer.clone(),
+                signers: account_keys
+                    .iter()
+                    .take(num_signers)
+                    .map(|key| key.to_string())
+                    .collect(),
+                token_account,
+                sol_in,
+                tokens_received,
+            })
+        })
+        .collect()
+}
+
+// Checks whether a transaction's logs show it invoking the vote program
+pub fn is_vote_transaction(tx: &EncodedTransactionWithStatusMeta) -> bool {
+    let logs: Option<Vec<String>> = match &tx.meta {
+        Some(meta) => meta.log_messages.clone().into(),
+        None => None,
+    };
+
+    logs.is_some_and(|logs| {
+        logs.iter()
+            .any(|log| log.contains("Vote111111111111111111111111111111111111111"))
+    })
+}
+
+// Checks a transaction paid at least the minimum priority fee and consumed at least the minimum compute units
+// A transaction whose compute units weren't reported isn't skipped for it
+fn meets_cost_thresholds(
+    tx: &EncodedTransactionWithStatusMeta,
+    meta: &UiTransactionStatusMeta,
+    options: &AnalysisOptions,
+) -> bool {
+    if options.min_priority_fee > 0 {
+        let num_signatures: u64 = tx
+            .transaction
+            .decode()
+            .map(|decoded| decoded.signatures.len() as u64)
+            .unwrap_or(1);
+        let priority_fee: u64 = meta.fee.saturating_sub(BASE_FEE_PER_SIGNATURE * num_signatures);
+
+        if priority_fee < options.min_priority_fee {
+            return false;
+        }
+    }
+
+    let compute_units: Option<u64> = meta.compute_units_consumed.clone().into();
+
+    compute_units.is_none_or(|units| units >= options.min_compute_units)
+}
+
+// Checks whether a given transaction was successful
+pub fn is_transaction_successful(meta: &UiTransactionStatusMeta) -> bool {
+    meta.err.is_none()
+}
+
+// Checks non-vote transactions in a block for potential sandwich attacks, returning what was found
+pub async fn analyze_non_vote_transactions<B: BlockSource>(
+    source: &B,
+    decimals_cache: &DecimalsCache,
+    pattern_tracker: &mut PatternTracker,
+    slot: u64,
+    block: &UiConfirmedBlock,
+    options: &AnalysisOptions,
+) -> Result<BlockAnalysis, SandwichError> {
+    if let Some(transactions) = &block.transactions {
+        // Tally votes for composition analysis without classifying them
+        let composition: Option<BlockComposition> = if options.count_votes {
+            let vote_txs: usize = transactions.iter().filter(|tx| is_vote_transaction(tx)).count();
+            let composition: BlockComposition = BlockComposition {
+                vote_txs,
+                non_vote_txs: transactions.len() - vote_txs,
+            };
+            Some(composition)
+        } else {
+            None
+        };
+
+        // Filter for non-vote transactions, keeping each one's index in the block
+        let non_vote_txs: Vec<(usize, &EncodedTransactionWithStatusMeta)> = transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| {
+                if let Some(meta) = &tx.meta {
+                    if (!is_transaction_successful(meta) && !options.track_failed)
+                        || !meets_cost_thresholds(tx, meta, options)
+                    {
+                        return false;
+                    }
+
+                    !is_vote_transaction(tx) && options.classifier.invokes_target_program(tx)
+                } else {
+                    false
+                }
+            })
+            .collect();
+
+        let block_height: u64 = block.block_height.unwrap_or(0);
+        let block_time: Option<i64> = block.block_time;
+
+        let mut unknown_discriminators: HashMap<String, usize> = HashMap::new();
+        let mut classified_txs: Vec<ClassifiedTransaction> = non_vote_txs
+            .into_iter()
+            .flat_map(|(tx_index, tx)| {
+                let mut legs: Vec<ClassifiedTransaction> = options.classifier.classify_transaction_with_unknown(
+                    tx,
+                    slot,
+                    block_height,
+                    block_time,
+                    &mut unknown_discriminators,
+                );
+
+                for leg in &mut legs {
+                    leg.tx_index = tx_index;
+                }
+
+                legs
+            })
+            .collect();
+
+        // Failed legs are only counted; the tracker never sees them, so a reverted swap can't complete a pattern
+        let swap_attempts: Option<SwapAttempts> = options.track_failed.then(|| SwapAttempts::count(&classified_txs));
+        classified_txs.retain(|classified_tx| !classified_tx.failed);
+
+        // Every mint in the block is resolved up front in one batch rather than a round trip per transaction,
+        // including each hop of multi-hop swaps, since a pattern may settle on any of them
+        if options.resolve_decimals {
+            let mints: Vec<String> = classified_txs
+                .iter()
+                .flat_map(|classified_tx| {
+                    std::iter::once(&classified_tx.from_mint)
+                        .chain(classified_tx.mint_changes.iter().map(|change| &change.mint))
+                })
+                .filter(|mint| !mint.is_empty())
+                .cloned()
+                .collect();
+
+            let decimals: HashMap<String, u8> = if mints.is_empty() {
+                HashMap::new()
+            } else {
+                get_token_decimals_batch(source, decimals_cache, &mints, options.mint_layout)
+                    .await
+                    .unwrap_or_else(|e| {
+                        eprintln!("Failed to fetch decimals for {} tokens: {}", mints.len(), e);
+                        if let Some(metrics) = options.metrics {
+                            metrics.rpc_errors.inc();
+                        }
+                        HashMap::new()
+                    })
+            };
+
+            for classified_tx in &mut classified_txs {
+                if let Some(&decimals) = decimals.get(&classified_tx.from_mint) {
+                    classified_tx.decimals = decimals;
+                    classified_tx.decimals_resolved = true;
+                }
+
+                for change in &mut classified_tx.mint_changes {
+                    change.decimals = decimals.get(&change.mint).copied();
+                }
+            }
+        }
+
+        for classified_tx in classified_txs {
+            pattern_tracker.process_transaction(classified_tx);
+        }
+
+        let mut completed_patterns: Vec<Pattern> = pattern_tracker.take_completed();
+        for pattern in &mut completed_patterns {
+            pattern.sol_accounting = options.sol_accounting;
+        }
+
+        if options.strict_decimals {
+            completed_patterns.retain(|pattern| {
+                let resolved: bool = pattern.swap_in_tx().decimals_resolved;
+
+                if !resolved {
+                    eprintln!(
+                        "Dropping pattern {} on token {}: decimals couldn't be resolved",
+                        pattern.create_tx().signature,
+                        pattern.token
+                    );
+                }
+
+                resolved
+            });
+        }
+
+        let mut incomplete: Vec<IncompletePattern> = if options.max_block_gap == 0 {
+            if options.explain_incomplete {
+                pattern_tracker.explain_incomplete()
+            } else {
+                Vec::new()
+            }
+        } else {
+            // Pending legs carry over to later blocks, so they're only explained once they fall too far behind to
+            // complete and are evicted
+            let cutoff: u64 = slot.saturating_sub(options.max_block_gap);
+            let stale: Vec<IncompletePattern> = if options.explain_incomplete {
+                pattern_tracker
+                    .explain_incomplete()
+                    .into_iter()
+                    .filter(|incomplete| incomplete.slot < cutoff)
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            pattern_tracker.prune_older_than(slot);
+
+            stale
+        };
+
+        // Rejected patterns are drained every block so a long-running tracker doesn't accumulate them, and only
+        // kept when they're being explained
+        let rejected: Vec<(Pattern, RejectReason)> = pattern_tracker.take_rejected();
+        if options.explain_incomplete {
+            incomplete.extend(
+                rejected
+                    .iter()
+                    .map(|(pattern, reason)| IncompletePattern::from_rejected(pattern, *reason)),
+            );
+        }
+
+        if !completed_patterns.is_empty() {
+            let block_signers: Vec<Option<(String, String)>> = transactions.iter().map(signature_and_signer).collect();
+
+            for pattern in &mut completed_patterns {
+                pattern.victims = find_victim_swaps(transactions, &block_signers, pattern);
+                if pattern.victims.is_empty() {
+                    pattern.kind = PatternKind::Arbitrage;
+                }
+
+                pattern.likely_wash = pattern.detect_wash();
+            }
+
+            if options.exclude_arbitrage {
+                completed_patterns.retain(|pattern| pattern.kind == PatternKind::Sandwich);
+            }
+        }
+
+        pattern_tracker.notify_complete(&completed_patterns);
+
+        if let Some(metrics) = options.metrics {
+            metrics.record_block(&completed_patterns);
+        }
+
+        return Ok(BlockAnalysis {
+            slot,
+            block_height: block.block_height,
+            patterns: completed_patterns,
+            composition,
+            incomplete,
+            swap_attempts,
+            unknown_discriminators,
+        });
+    }
+
+    if let Some(metrics) = options.metrics {
+        metrics.record_block(&[]);
+    }
+
+    Ok(BlockAnalysis {
+        slot,
+        block_height: block.block_height,
+        patterns: Vec::new(),
+        composition: None,
+        incomplete: Vec::new(),
+        swap_attempts: None,
+        unknown_discriminators: HashMap::new(),
+    })
+}
+
+// Fetches and analyzes every block in the inclusive slot range, in slot order
+// Skipped slots and blocks that fail to fetch are reported and left out, so a historical window can be backtested
+// from exact bounds
+pub async fn analyze_block_range<B: BlockSource>(
+    source: &B,
+    decimals_cache: &DecimalsCache,
+    start_slot: u64,
+    end_slot: u64,
+    options: &AnalysisOptions,
+) -> Result<Vec<BlockAnalysis>, SandwichError> {
+    analyze_block_range_with(source, decimals_cache, start_slot, end_slot, options, |_| {}).await
+}
+
+// Like analyze_block_range, but calls on_block with each block's analysis as soon as it's done, e.g. to checkpoint
+// progress through a long range
+pub async fn analyze_block_range_with<B: BlockSource>(
+    source: &B,
+    decimals_cache: &DecimalsCache,
+    start_slot: u64,
+    end_slot: u64,
+    options: &AnalysisOptions,
+    mut on_block: impl FnMut(&BlockAnalysis),
+) -> Result<Vec<BlockAnalysis>, SandwichError> {
+    if start_slot > end_slot {
+        return Err(SandwichError::InvalidSlotRange {
+            start: start_slot,
+            end: end_slot,
+        });
+    }
+
+    let mut analyses: Vec<BlockAnalysis> = Vec::new();
+    let mut pattern_tracker: PatternTracker = new_tracker(options);
+
+    // Slots left out of the sample aren't fetched at all
+    let slots = (start_slot..=end_slot).filter(|slot| options.sampling.is_none_or(|sampling| sampling.includes(*slot)));
+
+    for slot in slots {
+        if let Some(block) = get_block_by_slot(source, slot, options) {
+            if options.max_block_gap == 0 {
+                pattern_tracker = new_tracker(options);
+            }
+
+            eprintln!("\nAnalyzing Block {}:", slot);
+            let analysis: BlockAnalysis =
+                analyze_non_vote_transactions(source, decimals_cache, &mut pattern_tracker, slot, &block, options)
+                    .await?;
+            on_block(&analysis);
+            analyses.push(analysis);
+        }
+    }
+
+    Ok(analyses)
+}
+
+// Builds the RPC websocket URL for the client's cluster
+pub fn websocket_url(helius: &Helius) -> String {
+    format!(
+        "{}?api-key={}",
+        helius.config.endpoints.rpc.replacen("https://", "wss://", 1),
+        helius.config.api_key
+    )
+}
+
+// Streams newly rooted slots over the RPC websocket and analyzes each block as it's finalized, calling on_pattern
+// with every completed pattern through the tracker's on_complete callback, so it sees each one fully enriched
+// Dropped sockets are reconnected with backoff, and slots rooted while disconnected are backfilled, up to
+// MAX_BACKFILL_SLOTS behind the first root seen after reconnecting. Only returns on an analysis error
+pub async fn subscribe_and_detect<B: BlockSource>(
+    ws_url: &str,
+    source: &B,
+    decimals_cache: &DecimalsCache,
+    options: &AnalysisOptions,
+    on_pattern: impl FnMut(&Pattern) + 'static,
+) -> Result<(), SandwichError> {
+    let mut pattern_tracker: PatternTracker = new_tracker(options);
+    pattern_tracker.set_on_complete(on_pattern);
+    let mut last_slot: Option<u64> = None;
+    let mut reconnect_delay: Duration = MIN_RECONNECT_DELAY;
+
+    loop {
+        let client: PubsubClient = match PubsubClient::new(ws_url).await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!(
+                    "Failed to connect to the websocket, retrying in {:?}: {}",
+                    reconnect_delay, e
+                );
+                sleep(reconnect_delay).await;
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+        };
+
+        let (mut roots, unsubscribe) = match client.root_subscribe().await {
+            Ok(subscription) => subscription,
+            Err(e) => {
+                eprintln!(
+                    "Failed to subscribe to rooted slots, retrying in {:?}: {}",
+                    reconnect_delay, e
+                );
+                sleep(reconnect_delay).await;
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+        };
+        reconnect_delay = MIN_RECONNECT_DELAY;
+        eprintln!("Subscribed to rooted slots");
+
+        // Catch up on slots missed while (re)connecting, then follow each new root
+        let mut backfill: bool = true;
+
+        while let Some(root) = roots.next().await {
+            if last_slot.is_some_and(|last| root <= last) {
+                continue;
+            }
+
+            let start_slot: u64 = match last_slot {
+                Some(last) if backfill => (last + 1).max(root.saturating_sub(MAX_BACKFILL_SLOTS)),
+                _ => root,
+            };
+            backfill = false;
+
+            for slot in start_slot..=root {
+                if let Some(block) = get_block_by_slot(source, slot, options) {
+                    if options.max_block_gap == 0 {
+                        pattern_tracker.reset();
+                    }
+
+                    analyze_non_vote_transactions(source, decimals_cache, &mut pattern_tracker, slot, &block, options)
+                        .await?;
+                }
+            }
+
+            last_slot = Some(root);
+        }
+
+        drop(roots);
+        unsubscribe().await;
+        eprintln!("Websocket subscription dropped, reconnecting");
+    }
+}
+
+// Fetches the transaction with the given signature and analyzes the block it landed in
+pub async fn analyze_transaction_block<B: BlockSource>(
+    source: &B,
+    decimals_cache: &DecimalsCache,
+    signature: &Signature,
+    options: &AnalysisOptions,
+) -> Result<Option<BlockAnalysis>, SandwichError> {
+    let slot: u64 = get_transaction(source, signature, options.max_transaction_version)?.slot;
+    eprintln!("Transaction {} landed in slot {}", signature, slot);
+
+    if let Some(block) = get_block_by_slot(source, slot, options) {
+        eprintln!("\nAnalyzing Block {}:", slot);
+        Ok(Some(
+            analyze_non_vote_transactions(source, decimals_cache, &mut new_tracker(options), slot, &block, options)
+                .await?,
+        ))
+    } else {
+        eprintln!("Block {} not found or failed to fetch.", slot);
+        Ok(None)
+    }
+}
+
+// Fetches a single transaction and classifies it without pulling its whole block, for investigating one signature
+// getTransaction doesn't report block heights, so the legs carry a block height of 0 alongside the real slot
+pub fn analyze_signature<B: BlockSource>(
+    source: &B,
+    signature: &Signature,
+    options: &AnalysisOptions,
+) -> Result<Vec<ClassifiedTransaction>, SandwichError> {
+    let tx: EncodedConfirmedTransactionWithStatusMeta =
+        get_transaction(source, signature, options.max_transaction_version)?;
+
+    Ok(options
+        .classifier
+        .classify_transaction(&tx.transaction, tx.slot, 0, tx.block_time))
+}
+
+// Classifies the transaction a signature or transaction URL points to, as --classify-only does, so one transaction
+// can be investigated from a link without pulling its block
+pub fn classify_signature<B: BlockSource>(
+    source: &B,
+    input: &str,
+    options: &AnalysisOptions,
+) -> Result<Vec<ClassifiedTransaction>, SandwichError> {
+    let signature: Signature =
+        parse_signature(input).ok_or_else(|| SandwichError::InvalidSignature(input.trim().to_string()))?;
+
+    analyze_signature(source, &signature, options)
+}
+
+// Extracts a transaction signature from a Solana Explorer, Solscan, or similar transaction URL,
+// or from a bare base58 signature
+pub fn parse_signature(input: &str) -> Option<Signature> {
+    let input: &str = input.trim();
+
+    let candidate: &str = match input.find("/tx/") {
+        Some(pos) => input[pos + "/tx/".len()..]
+            .split(['?', '#', '/'])
+            .next()
+            .unwrap_or_default(),
+        None => input,
+    };
+
+    Signature::from_str(candidate).ok()
+}
+
+// Fetches a transaction by signature, with a hint to raise the supported version if it's too new
+pub fn get_transaction<B: BlockSource>(
+    source: &B,
+    signature: &Signature,
+    max_transaction_version: u8,
+) -> Result<EncodedConfirmedTransactionWithStatusMeta, SandwichError> {
+    let config: RpcTransactionConfig = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: None,
+        max_supported_transaction_version: Some(max_transaction_version),
+    };
+
+    match source.get_transaction_with_config(signature, config) {
+        Ok(tx) => Ok(tx),
+        Err(e) if is_unsupported_version_error(&e) => Err(SandwichError::UnsupportedTransactionVersion {
+            signature: signature.to_string(),
+            max_transaction_version,
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        block, create, key, sandwich_pattern, sandwich_transactions, token_balance, CannedTransaction, StubSource,
+        Swap, BOT, MINT, VICTIM,
+    };
+    use crate::types::{MINT_BASE_LEN, TOKEN_PROGRAM};
+    use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+    use solana_transaction_status::option_serializer::OptionSerializer;
+    use std::{cell::RefCell, rc::Rc, sync::atomic::Ordering};
+
+    const SIGNATURE: &str = "5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW";
+
+    async fn analyze(source: &StubSource, slot: u64, options: &AnalysisOptions) -> BlockAnalysis {
+        let block: &UiConfirmedBlock = &source.blocks[&slot];
+
+        analyze_non_vote_transactions(
+            source,
+            &DecimalsCache::new(),
+            &mut new_tracker(options),
+            slot,
+            block,
+            options,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn analyze_signature_classifies_a_single_transaction() {
+        let bot: Pubkey = key(1);
+        let leg = Swap::leg(
+            7,
+            "AutoSwapOut",
+            bot,
+            "Mint111111111111111111111111111111111111111",
+            -40_000,
+            2_000_000,
+        );
+        let signature: Signature = leg.build().signature;
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(300, block(300, vec![leg.build().encode()]));
+
+        let classified: Vec<ClassifiedTransaction> =
+            analyze_signature(&source, &signature, &AnalysisOptions::default()).unwrap();
+
+        assert_eq!(classified.len(), 1);
+        assert_eq!(classified[0].signature, signature.to_string());
+        assert_eq!(classified[0].instruction_type, "AutoSwapOut");
+        assert_eq!(classified[0].slot, 300);
+        assert_eq!(classified[0].sandwich_acc, key(201).to_string());
+        assert_eq!(classified[0].swapper, bot.to_string());
+        assert_eq!(classified[0].from_amount, 40_000);
+    }
+
+    #[test]
+    fn parse_signature_accepts_explorer_and_solscan_urls() {
+        let expected: Signature = Signature::from_str(SIGNATURE).unwrap();
+
+        for input in [
+            SIGNATURE.to_string(),
+            format!("  {}\n", SIGNATURE),
+            format!("https://explorer.solana.com/tx/{}", SIGNATURE),
+            format!("https://explorer.solana.com/tx/{}?cluster=devnet", SIGNATURE),
+            format!("https://solscan.io/tx/{}#balanceChanges", SIGNATURE),
+            format!("https://orb.helius.dev/tx/{}/history", SIGNATURE),
+        ] {
+            assert_eq!(parse_signature(&input), Some(expected), "failed to parse {}", input);
+        }
+    }
+
+    #[test]
+    fn parse_signature_rejects_anything_else() {
+        for input in [
+            "",
+            "not-a-signature",
+            "https://explorer.solana.com/address/vpeNALD89BZ4KxNUFjdLmFXBCwtyqBDQ85ouNoax38b",
+            "https://solscan.io/tx/",
+        ] {
+            assert_eq!(parse_signature(input), None, "parsed {}", input);
+        }
+    }
+
+    #[test]
+    fn classify_signature_takes_a_transaction_url() {
+        let leg: CannedTransaction = Swap::leg(7, "AutoSwapIn", key(BOT), MINT, 40_000, -2_000_000).build();
+        let mut source: StubSource = StubSource::default();
+        source.add_block(300, block(300, vec![leg.encode()]));
+
+        let url: String = format!("https://solscan.io/tx/{}#balanceChanges", leg.signature);
+        let classified: Vec<ClassifiedTransaction> =
+            classify_signature(&source, &url, &AnalysisOptions::default()).unwrap();
+        assert_eq!(classified.len(), 1);
+        assert_eq!(classified[0].signature, leg.signature.to_string());
+        assert_eq!(classified[0].instruction_type, "AutoSwapIn");
+
+        let result = classify_signature(&source, "https://solscan.io/tx/", &AnalysisOptions::default());
+        assert!(matches!(result, Err(SandwichError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn analyze_signature_reports_a_missing_transaction() {
+        let result = analyze_signature(
+            &StubSource::default(),
+            &Signature::from([3; 64]),
+            &AnalysisOptions::default(),
+        );
+
+        assert!(matches!(result, Err(SandwichError::RpcError(_))));
+    }
+
+    #[tokio::test]
+    async fn no_decimals_mode_issues_no_decimals_requests() {
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, sandwich_transactions()));
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        let analysis: BlockAnalysis = analyze(&source, 500, &options).await;
+
+        assert_eq!(analysis.patterns.len(), 1);
+        assert_eq!(analysis.patterns[0].token, MINT);
+        assert!(!analysis.patterns[0].swap_in_tx().decimals_resolved);
+        assert_eq!(source.multiple_accounts_requests.load(Ordering::SeqCst), 0);
+
+        // Resolving decimals batches the block's mints into a single lookup
+        analyze(&source, 500, &AnalysisOptions::default()).await;
+        assert_eq!(source.multiple_accounts_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unmapped_target_program_discriminators_are_counted() {
+        // The bot calls an instruction the map doesn't know, twice in the block, around a complete sandwich
+        let unmapped = |seed: u8| {
+            Swap {
+                data: vec![0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 1],
+                ..Swap::leg(seed, "AutoSwapIn", key(BOT), MINT, 0, 0)
+            }
+            .build()
+            .encode()
+        };
+        let mut transactions: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        transactions.insert(0, unmapped(20));
+        transactions.push(unmapped(21));
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, transactions));
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        let analysis: BlockAnalysis = analyze(&source, 500, &options).await;
+
+        assert_eq!(analysis.patterns.len(), 1);
+        assert_eq!(
+            analysis.unknown_discriminators,
+            HashMap::from([("abcdef0123456789".to_string(), 2)])
+        );
+    }
+
+    #[tokio::test]
+    async fn a_reverted_swap_in_is_counted_but_never_completes_a_pattern() {
+        let revert = |tx: &mut EncodedTransactionWithStatusMeta| {
+            let error: TransactionError = TransactionError::InstructionError(0, InstructionError::Custom(6001));
+            let meta: &mut UiTransactionStatusMeta = tx.meta.as_mut().unwrap();
+            meta.err = Some(error.clone());
+            meta.status = Err(error);
+        };
+
+        // The bot's front-run reverted, so the back-run has nothing to close
+        let mut reverted: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        revert(&mut reverted[1]);
+
+        // The bot retried the front-run after it reverted, and the retry landed
+        let mut retried: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        let mut failed_attempt: EncodedTransactionWithStatusMeta =
+            Swap::leg(14, "AutoSwapIn", key(BOT), MINT, 50_000, -2_000_000_000)
+                .build()
+                .encode();
+        revert(&mut failed_attempt);
+        retried.insert(1, failed_attempt);
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, reverted));
+        source.add_block(501, block(501, retried));
+
+        let success_only: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        let analysis: BlockAnalysis = analyze(&source, 500, &success_only).await;
+        assert!(analysis.patterns.is_empty());
+        assert!(analysis.swap_attempts.is_none());
+
+        let track_failed: AnalysisOptions = AnalysisOptions {
+            track_failed: true,
+            ..success_only
+        };
+        let analysis: BlockAnalysis = analyze(&source, 500, &track_failed).await;
+        assert!(analysis.patterns.is_empty());
+        let attempts: &SwapAttempts = analysis.swap_attempts.as_ref().unwrap();
+        assert_eq!((attempts.attempted, attempts.failed), (2, 1));
+
+        let analysis: BlockAnalysis = analyze(&source, 501, &track_failed).await;
+        assert_eq!(analysis.patterns.len(), 1);
+        assert_eq!(analysis.patterns[0].swap_in_tx().tx_index, 2);
+        assert!(!analysis.patterns[0].swap_in_tx().failed);
+        let attempts: &SwapAttempts = analysis.swap_attempts.as_ref().unwrap();
+        assert_eq!((attempts.attempted, attempts.failed), (3, 1));
+    }
+
+    // A legacy SPL Token mint account with the given decimals
+    fn mint_account(decimals: u8) -> Account {
+        let mut data: Vec<u8> = vec![0; MINT_BASE_LEN];
+        data[44] = decimals;
+
+        Account {
+            lamports: 1_461_600,
+            data,
+            owner: TOKEN_PROGRAM.parse().unwrap(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn one_batch_fills_the_cache_for_every_input_mint() {
+        let mut source: StubSource = StubSource::default();
+        for (seed, decimals) in [(31, 6), (32, 9), (33, 0)] {
+            source.accounts.insert(key(seed), mint_account(decimals));
+        }
+        let mints: Vec<String> = [31, 32, 33, 31].map(|seed| key(seed).to_string()).to_vec();
+        let cache: DecimalsCache = DecimalsCache::new();
+
+        let resolved: HashMap<String, u8> = get_token_decimals_batch(&source, &cache, &mints, MintLayout::default())
+            .await
+            .unwrap();
+        assert_eq!(source.multiple_accounts_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(resolved.len(), 3);
+        assert_eq!(cache.len().unwrap(), 3);
+        for (seed, decimals) in [(31, 6), (32, 9), (33, 0)] {
+            assert_eq!(cache.get(&key(seed).to_string()).unwrap(), Some(decimals));
+            assert_eq!(resolved[&key(seed).to_string()], decimals);
+        }
+
+        // Everything is cached now, so asking again doesn't go back to the source
+        get_token_decimals_batch(&source, &cache, &mints, MintLayout::default())
+            .await
+            .unwrap();
+        assert_eq!(source.multiple_accounts_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_block_is_fetched_and_analyzed_entirely_from_a_fake_source() {
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, sandwich_transactions()));
+        source.accounts.insert(MINT.parse().unwrap(), mint_account(6));
+
+        let options: AnalysisOptions = AnalysisOptions::default();
+        let block: UiConfirmedBlock = get_block_by_slot(&source, 500, &options).unwrap();
+        let analysis: BlockAnalysis = analyze_non_vote_transactions(
+            &source,
+            &DecimalsCache::new(),
+            &mut new_tracker(&options),
+            500,
+            &block,
+            &options,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(analysis.patterns.len(), 1);
+        let swap_in: &ClassifiedTransaction = analysis.patterns[0].swap_in_tx();
+        assert_eq!(swap_in.signer, key(1).to_string());
+        assert!(swap_in.decimals_resolved);
+        assert_eq!(swap_in.decimals, 6);
+
+        // Nothing that isn't in the source can be fetched
+        assert!(get_block_by_slot(&source, 501, &options).is_none());
+    }
+
+    #[tokio::test]
+    async fn a_two_hop_sandwich_takes_the_decimals_of_the_shared_hop() {
+        // The swap-in routes SOL through another mint into MINT, moving more of the intermediate mint than of MINT,
+        // so the leg starts out on the intermediate mint until the pattern aligns it with the swap-out
+        let hop_mint: String = key(212).to_string();
+        let mut swap_in: CannedTransaction =
+            Swap::leg(11, "AutoSwapIn", key(BOT), MINT, 50_000, -2_000_000_000).build();
+        swap_in.account_keys.extend([key(205), key(206)]);
+        swap_in.pre_balances.extend([2_039_280; 2]);
+        swap_in.post_balances.extend([2_039_280; 2]);
+        swap_in.instructions[0].accounts.extend([7, 8]);
+        swap_in.pre_token_balances.extend([
+            token_balance(7, &hop_mint, &key(207), 1_000_000_000_000),
+            token_balance(8, &hop_mint, &key(200), 1_000_000_000_000),
+        ]);
+        swap_in.post_token_balances.extend([
+            token_balance(7, &hop_mint, &key(207), 995_000_000_000),
+            token_balance(8, &hop_mint, &key(200), 1_005_000_000_000),
+        ]);
+
+        let mut transactions: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        transactions[1] = swap_in.encode();
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, transactions));
+        source.accounts.insert(MINT.parse().unwrap(), mint_account(6));
+        source.accounts.insert(key(212), mint_account(9));
+
+        let analysis: BlockAnalysis = analyze(&source, 500, &AnalysisOptions::default()).await;
+
+        let pattern: &Pattern = &analysis.patterns[0];
+        assert_eq!(pattern.token, MINT);
+        for leg in [pattern.swap_in_tx(), pattern.swap_out_tx()] {
+            assert_eq!(leg.from_mint, MINT);
+            assert_eq!(leg.from_amount, 50_000);
+            assert_eq!((leg.decimals, leg.decimals_resolved), (6, true));
+        }
+        // Both mints were resolved in the block's single batch
+        assert_eq!(source.multiple_accounts_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_garbage_mint_is_an_error_rather_than_a_panic() {
+        let mut source: StubSource = StubSource::default();
+        source.accounts.insert(key(31), mint_account(6));
+        let cache: DecimalsCache = DecimalsCache::new();
+
+        for garbage in ["", "not a mint", "0OIl0OIl0OIl0OIl0OIl0OIl0OIl0OIl0OIl0OIl0OI"] {
+            assert!(matches!(
+                get_token_decimals(&source, &cache, garbage, MintLayout::default()).await,
+                Err(SandwichError::ParsePubkey { .. })
+            ));
+        }
+
+        // A well-formed address with no mint behind it fails too, rather than defaulting
+        assert!(matches!(
+            get_token_decimals(&source, &cache, &key(32).to_string(), MintLayout::default()).await,
+            Err(SandwichError::MalformedAccountData(_))
+        ));
+        assert_eq!(cache.len().unwrap(), 0);
+
+        let mint: String = key(31).to_string();
+        assert_eq!(
+            get_token_decimals(&source, &cache, &mint, MintLayout::default())
+                .await
+                .unwrap(),
+            6
+        );
+        assert_eq!(cache.get(&mint).unwrap(), Some(6));
+
+        // The batch lookup leaves garbage out instead of failing the mints around it
+        let resolved: HashMap<String, u8> = get_token_decimals_batch(
+            &source,
+            &DecimalsCache::new(),
+            &["not a mint".to_string(), mint.clone()],
+            MintLayout::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resolved, HashMap::from([(mint, 6)]));
+    }
+
+    #[tokio::test]
+    async fn caches_for_different_clusters_keep_their_own_decimals() {
+        // The same mint address holds a different mint on each cluster
+        let mint: String = key(31).to_string();
+        let cluster = |decimals: u8| {
+            let mut source: StubSource = StubSource::default();
+            source.accounts.insert(key(31), mint_account(decimals));
+            source
+        };
+        let (mainnet, devnet) = (cluster(6), cluster(9));
+        let (mainnet_cache, devnet_cache) = (DecimalsCache::new(), DecimalsCache::new());
+
+        let mints: &[String] = std::slice::from_ref(&mint);
+
+        get_token_decimals_batch(&mainnet, &mainnet_cache, mints, MintLayout::default())
+            .await
+            .unwrap();
+        assert_eq!(devnet_cache.get(&mint).unwrap(), None);
+
+        get_token_decimals_batch(&devnet, &devnet_cache, mints, MintLayout::default())
+            .await
+            .unwrap();
+        assert_eq!(mainnet_cache.get(&mint).unwrap(), Some(6));
+        assert_eq!(devnet_cache.get(&mint).unwrap(), Some(9));
+
+        devnet_cache.insert_symbol(&mint, Some("DEV".to_string())).unwrap();
+        assert_eq!(mainnet_cache.get_symbol(&mint).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn only_a_buy_between_the_legs_makes_a_victim() {
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, sandwich_transactions()));
+        let analysis: BlockAnalysis = analyze(&source, 500, &options).await;
+
+        let pattern: &Pattern = &analysis.patterns[0];
+        assert_eq!(pattern.kind, PatternKind::Sandwich);
+        assert_eq!(pattern.victims.len(), 1);
+        assert_eq!(pattern.victims[0].signature, Signature::from([12; 64]).to_string());
+        assert_eq!(pattern.victims[0].signer, key(VICTIM).to_string());
+        assert_eq!(pattern.victims[0].tokens_received, 20_000);
+        assert_eq!(pattern.victims[0].sol_in, 1_000_000_000);
+
+        // Someone selling into the pool between the legs moved its balances too, but wasn't front-run
+        let mut transactions: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        transactions[2] = Swap::victim(12, key(VICTIM), MINT, -20_000, 1_000_000_000)
+            .build()
+            .encode();
+        source.add_block(501, block(501, transactions));
+        let analysis: BlockAnalysis = analyze(&source, 501, &options).await;
+
+        assert_eq!(analysis.patterns[0].kind, PatternKind::Arbitrage);
+        assert!(analysis.patterns[0].victims.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_round_trip_with_nothing_between_the_legs_is_arbitrage() {
+        let mut transactions: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        transactions.remove(2);
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, transactions));
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        let analysis: BlockAnalysis = analyze(&source, 500, &options).await;
+        assert_eq!(analysis.patterns.len(), 1);
+        assert_eq!(analysis.patterns[0].kind, PatternKind::Arbitrage);
+
+        let excluding: AnalysisOptions = AnalysisOptions {
+            exclude_arbitrage: true,
+            ..options
+        };
+        assert!(analyze(&source, 500, &excluding).await.patterns.is_empty());
+
+        // A real sandwich survives the exclusion
+        source.add_block(501, block(501, sandwich_transactions()));
+        let analysis: BlockAnalysis = analyze(&source, 501, &excluding).await;
+        assert_eq!(analysis.patterns.len(), 1);
+        assert_eq!(analysis.patterns[0].kind, PatternKind::Sandwich);
+    }
+
+    #[tokio::test]
+    async fn votes_are_tallied_without_changing_detection() {
+        let mut transactions: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        for seed in [20, 21, 22] {
+            let mut vote: EncodedTransactionWithStatusMeta = create(seed, key(seed)).encode();
+            vote.meta.as_mut().unwrap().log_messages = OptionSerializer::Some(vec![
+                "Program Vote111111111111111111111111111111111111111 invoke [1]".to_string(),
+            ]);
+            transactions.insert(1, vote);
+        }
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, transactions));
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        let uncounted: BlockAnalysis = analyze(&source, 500, &options).await;
+        assert!(uncounted.composition.is_none());
+
+        let counting: AnalysisOptions = AnalysisOptions {
+            count_votes: true,
+            ..options
+        };
+        let counted: BlockAnalysis = analyze(&source, 500, &counting).await;
+        let composition: BlockComposition = counted.composition.unwrap();
+        assert_eq!((composition.vote_txs, composition.non_vote_txs), (3, 4));
+        assert_eq!(counted.patterns.len(), uncounted.patterns.len());
+        assert_eq!(counted.patterns.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_classified_feed_sees_every_leg_before_the_tracker_pairs_them() {
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, sandwich_transactions()));
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+
+        let feed: Rc<RefCell<Vec<(String, String)>>> = Rc::new(RefCell::new(Vec::new()));
+        let captured: Rc<RefCell<Vec<(String, String)>>> = feed.clone();
+        let mut tracker: PatternTracker = new_tracker(&options);
+        tracker.set_on_classified(move |tx| {
+            captured
+                .borrow_mut()
+                .push((tx.instruction_type.clone(), tx.signature.clone()))
+        });
+
+        let analysis: BlockAnalysis = analyze_non_vote_transactions(
+            &source,
+            &DecimalsCache::new(),
+            &mut tracker,
+            500,
+            &source.blocks[&500],
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(analysis.patterns.len(), 1);
+
+        // The victim's swap isn't the target program's, so it never reaches the feed
+        let expected: Vec<(String, String)> = [(10, "CreateSandwichV2"), (11, "AutoSwapIn"), (13, "AutoSwapOut")]
+            .into_iter()
+            .map(|(seed, instruction_type)| (instruction_type.to_string(), Signature::from([seed; 64]).to_string()))
+            .collect();
+        assert_eq!(*feed.borrow(), expected);
+    }
+
+    #[tokio::test]
+    async fn a_sampled_range_scan_only_analyzes_the_sampled_slots() {
+        let mut source: StubSource = StubSource::default();
+        for slot in 500..600 {
+            source.add_block(slot, block(slot, Vec::new()));
+        }
+
+        let sampling: SlotSampling = SlotSampling::new(0.25, 7).unwrap();
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            sampling: Some(sampling),
+            ..AnalysisOptions::default()
+        };
+        let analyzed: Vec<u64> = analyze_block_range(&source, &DecimalsCache::new(), 500, 599, &options)
+            .await
+            .unwrap()
+            .iter()
+            .map(|analysis| analysis.slot)
+            .collect();
+
+        let sampled: Vec<u64> = (500..600).filter(|slot| sampling.includes(*slot)).collect();
+        assert_eq!(analyzed, sampled);
+        assert!(!sampled.is_empty() && sampled.len() < 100);
+    }
+
+    #[tokio::test]
+    async fn a_create_with_no_swap_in_is_explained_as_incomplete() {
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, vec![create(10, key(BOT)).encode()]));
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            explain_incomplete: true,
+            ..AnalysisOptions::default()
+        };
+        let analysis: BlockAnalysis = analyze(&source, 500, &options).await;
+        assert!(analysis.patterns.is_empty());
+        assert_eq!(analysis.incomplete.len(), 1);
+
+        let incomplete: &IncompletePattern = &analysis.incomplete[0];
+        assert_eq!(incomplete.sandwich_acc, key(201).to_string());
+        assert_eq!(incomplete.last_instruction_type, "CreateSandwichV2");
+        assert_eq!(incomplete.last_signature, Signature::from([10; 64]).to_string());
+        assert_eq!((incomplete.slot, incomplete.swap_ins_seen), (500, 0));
+        assert_eq!(incomplete.reason, "Create seen, but no swap-in followed");
+
+        // Only explained on request
+        let quiet: AnalysisOptions = AnalysisOptions {
+            explain_incomplete: false,
+            ..options
+        };
+        assert!(analyze(&source, 500, &quiet).await.incomplete.is_empty());
+    }
+
+    #[tokio::test]
+    async fn strict_decimals_drops_patterns_whose_decimals_are_unresolved() {
+        // The mint's account isn't served, so its decimals can't be fetched
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, sandwich_transactions()));
+
+        let lenient: BlockAnalysis = analyze(&source, 500, &AnalysisOptions::default()).await;
+        assert_eq!(lenient.patterns.len(), 1);
+        assert!(!lenient.patterns[0].swap_in_tx().decimals_resolved);
+
+        let strict: AnalysisOptions = AnalysisOptions {
+            strict_decimals: true,
+            ..AnalysisOptions::default()
+        };
+        assert!(analyze(&source, 500, &strict).await.patterns.is_empty());
+
+        // Once the mint resolves, strict mode keeps the pattern
+        source.accounts.insert(MINT.parse().unwrap(), mint_account(6));
+        assert_eq!(analyze(&source, 500, &strict).await.patterns.len(), 1);
+    }
+
+    // Fails every block and transaction fetch the way an RPC does for transactions newer than the requested version
+    struct TooNewSource;
+
+    impl TooNewSource {
+        fn error() -> ClientError {
+            RpcError::RpcResponseError {
+                code: JSON_RPC_SERVER_ERROR_UNSUPPORTED_TRANSACTION_VERSION,
+                message: "Transaction version (1) is not supported by the requesting client".to_string(),
+                data: solana_client::rpc_request::RpcResponseErrorData::Empty,
+            }
+            .into()
+        }
+    }
+
+    impl BlockSource for TooNewSource {
+        fn get_slot(&self, _commitment: CommitmentConfig) -> Result<u64, ClientError> {
+            Ok(0)
+        }
+
+        fn get_block_with_config(&self, _slot: u64, _config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError> {
+            Err(Self::error())
+        }
+
+        fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+            Ok(vec![None; pubkeys.len()])
+        }
+
+        fn get_transaction_with_config(
+            &self,
+            _signature: &Signature,
+            _config: RpcTransactionConfig,
+        ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+            Err(Self::error())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_version_too_high_is_surfaced_rather_than_treated_as_a_skip() {
+        let signature: Signature = SIGNATURE.parse().unwrap();
+        let options: AnalysisOptions = AnalysisOptions {
+            max_transaction_version: 0,
+            ..AnalysisOptions::default()
+        };
+
+        let error: SandwichError =
+            match analyze_transaction_block(&TooNewSource, &DecimalsCache::new(), &signature, &options).await {
+                Ok(_) => panic!("analyzed a block the RPC couldn't return"),
+                Err(e) => e,
+            };
+        assert!(matches!(
+            &error,
+            SandwichError::UnsupportedTransactionVersion { signature, max_transaction_version: 0 }
+                if *signature == SIGNATURE
+        ));
+        assert!(
+            error.to_string().contains("raise --max-transaction-version"),
+            "{}",
+            error
+        );
+
+        // Block fetches tell the version error apart from a skipped slot
+        assert!(is_unsupported_version_error(&TooNewSource::error()));
+        let missing: ClientError = StubSource::default()
+            .get_block_with_config(1, block_config(0, None))
+            .unwrap_err();
+        assert!(!is_unsupported_version_error(&missing));
+        assert!(get_block_by_slot(&TooNewSource, 1, &options).is_none());
+    }
+
+    #[tokio::test]
+    async fn transactions_below_the_fee_or_compute_thresholds_are_skipped() {
+        let paying = |priority_fee: u64, compute_units: Option<u64>| -> Vec<EncodedTransactionWithStatusMeta> {
+            let mut transactions: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+            for tx in &mut transactions {
+                let meta: &mut UiTransactionStatusMeta = tx.meta.as_mut().unwrap();
+                meta.fee = BASE_FEE_PER_SIGNATURE + priority_fee;
+                meta.compute_units_consumed = compute_units.into();
+            }
+            transactions
+        };
+
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, block(500, paying(0, Some(150_000))));
+        source.add_block(501, block(501, paying(10_000, Some(150_000))));
+        source.add_block(502, block(502, paying(10_000, Some(20_000))));
+        source.add_block(503, block(503, paying(10_000, None)));
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            min_priority_fee: 5_000,
+            min_compute_units: 100_000,
+            ..AnalysisOptions::default()
+        };
+        let patterns_at = |slot: u64| analyze(&source, slot, &options);
+
+        // Paying only the base fee, the legs are skipped before classification
+        assert!(patterns_at(500).await.patterns.is_empty());
+        assert_eq!(patterns_at(501).await.patterns.len(), 1);
+        assert!(patterns_at(502).await.patterns.is_empty());
+        // Unreported compute units don't count against a transaction
+        assert_eq!(patterns_at(503).await.patterns.len(), 1);
+    }
+
+    // A block without a reported height, as some RPCs return for old or partially indexed slots
+    fn heightless_block(slot: u64, transactions: Vec<EncodedTransactionWithStatusMeta>) -> UiConfirmedBlock {
+        UiConfirmedBlock {
+            block_height: None,
+            ..block(slot, transactions)
+        }
+    }
+
+    #[tokio::test]
+    async fn legs_in_blocks_without_a_height_are_gapped_and_pruned_by_slot() {
+        // The create and swap-in land two slots before the victim and swap-out, in blocks with no height
+        let mut transactions: Vec<EncodedTransactionWithStatusMeta> = sandwich_transactions();
+        let later: Vec<EncodedTransactionWithStatusMeta> = transactions.split_off(2);
+        let mut source: StubSource = StubSource::default();
+        source.add_block(500, heightless_block(500, transactions));
+        source.add_block(502, heightless_block(502, later));
+
+        let options = |max_block_gap: u64| AnalysisOptions {
+            resolve_decimals: false,
+            max_block_gap,
+            explain_incomplete: true,
+            ..AnalysisOptions::default()
+        };
+
+        let analyses: Vec<BlockAnalysis> = analyze_block_range(&source, &DecimalsCache::new(), 500, 502, &options(2))
+            .await
+            .unwrap();
+        let patterns: Vec<&Pattern> = analyses.iter().flat_map(|analysis| &analysis.patterns).collect();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].kind, PatternKind::Sandwich);
+        assert_eq!(patterns[0].victims[0].signer, key(VICTIM).to_string());
+        assert!(analyses.iter().all(|analysis| analysis.incomplete.is_empty()));
+
+        // A gap of one slot is too short, so the legs match but the pattern is rejected
+        let analyses: Vec<BlockAnalysis> = analyze_block_range(&source, &DecimalsCache::new(), 500, 502, &options(1))
+            .await
+            .unwrap();
+        assert!(analyses.iter().all(|analysis| analysis.patterns.is_empty()));
+        assert_eq!(
+            analyses[1].incomplete[0].reason,
+            RejectReason::BlockGapExceeded.description()
+        );
+
+        // Without a swap-out, a block more than the gap later evicts the pending swap-in, explained at its slot
+        source.blocks.remove(&502);
+        source.add_block(503, heightless_block(503, Vec::new()));
+        let analyses: Vec<BlockAnalysis> = analyze_block_range(&source, &DecimalsCache::new(), 500, 503, &options(2))
+            .await
+            .unwrap();
+        let incomplete: &IncompletePattern = &analyses[1].incomplete[0];
+        assert_eq!(incomplete.last_instruction_type, "AutoSwapIn");
+        assert_eq!(incomplete.slot, 500);
+    }
+
+    // A fungible asset as getAssetBatch returns it, with only the fields the symbol lookup reads filled in
+    fn asset(token_info_symbol: Option<&str>, metadata_symbol: Option<&str>) -> Asset {
+        serde_json::from_value(serde_json::json!({
+            "interface": "FungibleToken",
+            "id": MINT,
+            "content": {
+                "json_uri": "",
+                "metadata": { "symbol": metadata_symbol },
+            },
+            "ownership": {
+                "frozen": false,
+                "delegated": false,
+                "ownership_model": "token",
+                "owner": "",
+            },
+            "mutable": true,
+            "burnt": false,
+            "token_info": { "symbol": token_info_symbol },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn asset_symbol_falls_back_from_the_token_info_to_the_metadata_to_the_mint() {
+        assert_eq!(
+            asset_symbol(&asset(Some("BONK"), Some("META"))),
+            Some("BONK".to_string())
+        );
+        assert_eq!(asset_symbol(&asset(None, Some(" META "))), Some("META".to_string()));
+        assert_eq!(asset_symbol(&asset(Some("  "), None)), None);
+
+        let mut no_token_info: Asset = asset(None, Some("META"));
+        no_token_info.token_info = None;
+        assert_eq!(asset_symbol(&no_token_info), Some("META".to_string()));
+
+        let mut pattern: Pattern = sandwich_pattern("sandwich");
+        pattern.token = MINT.to_string();
+        pattern.token_symbol = asset_symbol(&asset(None, None));
+        assert_eq!(pattern.get_token_label(), MINT);
+
+        pattern.token_symbol = asset_symbol(&asset(Some("BONK"), None));
+        assert_eq!(pattern.get_token_label(), format!("BONK ({})", MINT));
+    }
+}
@@ -0,0 +1,275 @@
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::{
+    EncodedTransactionWithStatusMeta, UiConfirmedBlock, UiTransactionStatusMeta, UiTransactionTokenBalance,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::types::WSOL_MINT;
+
+// Returns each owner's SOL-equivalent balance delta in lamports, combining native lamport changes with wSOL changes
+// Attackers move freely between native SOL and wSOL (wrapping, unwrapping, closing wSOL accounts), so neither
+// channel alone reflects what they actually gained or spent
+//
+// Token accounts are excluded from the native side, since a wSOL account's lamports mirror its token amount and
+// would otherwise be counted twice. Their wSOL deltas are attributed to the token account's owner instead
+// A wSOL account opened or closed in the transaction has a missing pre or post balance, which counts as zero
+pub fn sol_equivalent_deltas(
+    account_keys: &[Pubkey],
+    pre_balances: &[u64],
+    post_balances: &[u64],
+    pre_token_balances: &[UiTransactionTokenBalance],
+    post_token_balances: &[UiTransactionTokenBalance],
+) -> HashMap<String, i64> {
+    let mut deltas: HashMap<String, i64> = HashMap::new();
+
+    let token_accounts: HashSet<u8> = pre_token_balances
+        .iter()
+        .chain(post_token_balances)
+        .map(|balance| balance.account_index)
+        .collect();
+
+    for (i, key) in account_keys.iter().enumerate() {
+        if token_accounts.contains(&(i as u8)) {
+            continue;
+        }
+
+        if let (Some(&pre), Some(&post)) = (pre_balances.get(i), post_balances.get(i)) {
+            if pre != post {
+                *deltas.entry(key.to_string()).or_default() += post as i64 - pre as i64;
+            }
+        }
+    }
+
+    for (balances, sign) in [(pre_token_balances, -1), (post_token_balances, 1)] {
+        for balance in balances.iter().filter(|balance| balance.mint == WSOL_MINT) {
+            let owner: Option<&String> = balance.owner.as_ref().into();
+            let amount: i64 = balance.ui_token_amount.amount.parse::<i64>().unwrap_or(0);
+
+            if let Some(owner) = owner {
+                *deltas.entry(owner.clone()).or_default() += sign * amount;
+            }
+        }
+    }
+
+    deltas.retain(|_, delta| *delta != 0);
+
+    deltas
+}
+
+// A token account's balance before and after a transaction, in base units
+// A side is None if the account didn't exist then, i.e. it was opened or closed by the transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountBalanceDiff {
+    pub account_index: u8,
+    pub mint: String,
+    // The pre-balance owner, or the post-balance one if the former wasn't recorded
+    pub owner: Option<String>,
+    pub pre: Option<u64>,
+    pub post: Option<u64>,
+}
+
+impl AccountBalanceDiff {
+    // The change in base units, counting a missing side as zero
+    pub fn change(&self) -> i128 {
+        self.post.unwrap_or(0) as i128 - self.pre.unwrap_or(0) as i128
+    }
+}
+
+// Pairs up each token account's pre- and post-balance, in account order, including accounts that didn't change
+pub fn account_balance_diffs(
+    pre_token_balances: &[UiTransactionTokenBalance],
+    post_token_balances: &[UiTransactionTokenBalance],
+) -> Vec<AccountBalanceDiff> {
+    let mut diffs: Vec<AccountBalanceDiff> = Vec::new();
+
+    for (balances, is_post) in [(pre_token_balances, false), (post_token_balances, true)] {
+        for balance in balances {
+            let owner: Option<&String> = balance.owner.as_ref().into();
+            let owner: Option<String> = owner.filter(|owner| !owner.is_empty()).cloned();
+            let amount: u64 = balance.ui_token_amount.amount.parse::<u64>().unwrap_or(0);
+
+            let diff: &mut AccountBalanceDiff = match diffs
+                .iter()
+                .position(|diff| diff.account_index == balance.account_index)
+            {
+                Some(i) => &mut diffs[i],
+                None => {
+                    diffs.push(AccountBalanceDiff {
+                        account_index: balance.account_index,
+                        mint: balance.mint.clone(),
+                        owner: None,
+                        pre: None,
+                        post: None,
+                    });
+                    diffs.last_mut().unwrap()
+                }
+            };
+
+            diff.owner = diff.owner.take().or(owner);
+            if is_post {
+                diff.post = Some(amount);
+            } else {
+                diff.pre = Some(amount);
+            }
+        }
+    }
+
+    diffs.sort_by_key(|diff| diff.account_index);
+
+    diffs
+}
+
+// A token balance change for one owner and mint within a transaction, in base units
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenBalanceDiff {
+    pub signature: String,
+    pub mint: String,
+    pub owner: String,
+    pub pre: u64,
+    pub post: u64,
+    pub change: i128,
+}
+
+// Returns each owner's token balance changes in a transaction, as raw base-unit amounts
+// Balances of an owner's accounts for the same mint are summed, so accounts opened or closed in the transaction
+// count as zero on the missing side, and owners whose balance didn't change are omitted
+pub fn token_balance_diffs(tx: &EncodedTransactionWithStatusMeta) -> Vec<TokenBalanceDiff> {
+    let meta: &UiTransactionStatusMeta = match &tx.meta {
+        Some(meta) => meta,
+        None => return Vec::new(),
+    };
+
+    let signature: String = tx
+        .transaction
+        .decode()
+        .and_then(|decoded| decoded.signatures.first().map(|signature| signature.to_string()))
+        .unwrap_or_default();
+
+    let pre_token_balances: &[UiTransactionTokenBalance] =
+        meta.pre_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]);
+    let post_token_balances: &[UiTransactionTokenBalance] =
+        meta.post_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]);
+
+    // (owner, mint) -> (pre, post), in account order so output is stable
+    let mut totals: Vec<((String, String), (u64, u64))> = Vec::new();
+
+    for diff in account_balance_diffs(pre_token_balances, post_token_balances) {
+        let key: (String, String) = (diff.owner.unwrap_or_default(), diff.mint);
+
+        let i: usize = match totals.iter().position(|(existing, _)| existing == &key) {
+            Some(i) => i,
+            None => {
+                totals.push((key, (0, 0)));
+                totals.len() - 1
+            }
+        };
+        let entry: &mut (u64, u64) = &mut totals[i].1;
+
+        entry.0 += diff.pre.unwrap_or(0);
+        entry.1 += diff.post.unwrap_or(0);
+    }
+
+    totals
+        .into_iter()
+        .filter(|(_, (pre, post))| pre != post)
+        .map(|((owner, mint), (pre, post))| TokenBalanceDiff {
+            signature: signature.clone(),
+            mint,
+            owner,
+            pre,
+            post,
+            change: post as i128 - pre as i128,
+        })
+        .collect()
+}
+
+// Returns the per-owner token balance changes of every transaction in a block, in block order
+pub fn block_token_balance_diffs(block: &UiConfirmedBlock) -> Vec<TokenBalanceDiff> {
+    block
+        .transactions
+        .iter()
+        .flatten()
+        .flat_map(token_balance_diffs)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{key, token_balance, Swap, MINT};
+    use solana_sdk::signature::Signature;
+
+    #[test]
+    fn token_balance_diffs_report_each_owners_change() {
+        let tx: EncodedTransactionWithStatusMeta = Swap::leg(4, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000_000)
+            .build()
+            .encode();
+        let signature: String = Signature::from([4; 64]).to_string();
+        let diff = |mint: &str, owner: Pubkey, pre: u64, post: u64| TokenBalanceDiff {
+            signature: signature.clone(),
+            mint: mint.to_string(),
+            owner: owner.to_string(),
+            pre,
+            post,
+            change: post as i128 - pre as i128,
+        };
+
+        assert_eq!(
+            token_balance_diffs(&tx),
+            [
+                diff(MINT, key(1), 1_000_000_000, 1_000_050_000),
+                diff(WSOL_MINT, key(1), 100_000_000_000, 98_000_000_000),
+                diff(MINT, key(200), 1_000_000_000_000, 999_999_950_000),
+                diff(WSOL_MINT, key(200), 500_000_000_000, 502_000_000_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn native_and_wsol_movement_combine_into_one_delta_per_owner() {
+        // The signer wraps 0.5 SOL into its wSOL account and pays the pool 2 SOL of wSOL out of it, with a 5,000
+        // lamport fee; the wSOL account's own lamports follow its token amount and aren't counted again
+        let account_keys: Vec<Pubkey> = vec![key(1), key(150), key(204)];
+        let pre_balances: [u64; 3] = [10_000_000_000, 1_502_039_280, 500_002_039_280];
+        let post_balances: [u64; 3] = [9_499_995_000, 2_039_280, 502_002_039_280];
+        let pre: Vec<UiTransactionTokenBalance> = vec![
+            token_balance(1, WSOL_MINT, &key(1), 1_500_000_000),
+            token_balance(2, WSOL_MINT, &key(200), 500_000_000_000),
+        ];
+        let post: Vec<UiTransactionTokenBalance> = vec![
+            token_balance(1, WSOL_MINT, &key(1), 0),
+            token_balance(2, WSOL_MINT, &key(200), 502_000_000_000),
+        ];
+
+        let deltas: HashMap<String, i64> =
+            sol_equivalent_deltas(&account_keys, &pre_balances, &post_balances, &pre, &post);
+        assert_eq!(deltas[&key(1).to_string()], -2_000_005_000);
+        assert_eq!(deltas[&key(200).to_string()], 2_000_000_000);
+        assert_eq!(deltas.len(), 2);
+
+        // Closing the wSOL account unwraps it into native SOL, which only nets the returned rent
+        let post_balances: [u64; 3] = [11_502_034_280, 0, 500_002_039_280];
+        let deltas: HashMap<String, i64> =
+            sol_equivalent_deltas(&account_keys, &pre_balances, &post_balances, &pre[..1], &[]);
+        assert_eq!(deltas[&key(1).to_string()], 2_039_280 - 5_000);
+    }
+
+    #[test]
+    fn accounts_opened_or_closed_count_as_zero_on_the_missing_side() {
+        let pre: Vec<UiTransactionTokenBalance> = vec![token_balance(3, MINT, &key(1), 700)];
+        let post: Vec<UiTransactionTokenBalance> = vec![
+            token_balance(2, MINT, &key(1), 500),
+            token_balance(3, MINT, &key(1), 700),
+        ];
+
+        let diffs: Vec<AccountBalanceDiff> = account_balance_diffs(&pre, &post);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(
+            (diffs[0].account_index, diffs[0].pre, diffs[0].post),
+            (2, None, Some(500))
+        );
+        assert_eq!(diffs[0].change(), 500);
+        assert_eq!(diffs[1].change(), 0);
+    }
+}
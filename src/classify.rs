@@ -0,0 +1,1569 @@
+use hex::encode;
+use solana_sdk::{
+    bs58, instruction::CompiledInstruction, message::VersionedMessage, pubkey::Pubkey,
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status::{
+    EncodedTransactionWithStatusMeta, UiInnerInstructions, UiInstruction, UiLoadedAddresses, UiTransactionStatusMeta,
+    UiTransactionTokenBalance,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::balances::{account_balance_diffs, sol_equivalent_deltas, AccountBalanceDiff};
+use crate::error::{parse_pubkey, SandwichError};
+use crate::types::{
+    get_instruction_map, ClassifiedTransaction, JitoTipConfig, MintChange, PoolReserves, SwapInfo, DEFAULT_DECIMALS,
+    HOLDING_ACCOUNT, TARGET_PROGRAM, WSOL_MINT,
+};
+
+// The System Program, and the index of its Transfer instruction, which is followed by the lamports as a u64
+const SYSTEM_PROGRAM: &str = "11111111111111111111111111111111";
+const SYSTEM_TRANSFER: u32 = 2;
+
+// Instruction names the classifier and pattern tracker act on
+const LIFECYCLE_INSTRUCTIONS: [&str; 5] = ["CreateSandwichV2", "AutoSwapIn", "AutoSwapOut", "Cashout", "Exit"];
+
+// Whether a discriminator is 8 hex-encoded bytes in lowercase, the form instruction data is encoded to for lookup
+fn is_valid_discriminator(discriminator: &str) -> bool {
+    discriminator.len() == 16 && discriminator.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+// Turns raw transactions into classified sandwich lifecycle instructions for a given program
+// The defaults target the known sandwich program and its instruction discriminators
+pub struct Classifier {
+    // Program whose instructions are classified
+    pub target_program: String,
+    // Map of hex-encoded 8-byte discriminators to instruction names
+    pub instruction_map: HashMap<String, String>,
+    // Only classify transactions containing at least this many distinct known instructions
+    pub min_known_instructions: usize,
+    // Skip swap legs whose transaction shows no token balance changes for the swapped mint
+    pub require_token_movement: bool,
+    // Accounts and threshold used to detect Jito tips
+    pub jito_tips: JitoTipConfig,
+    // Swappers whose swaps aren't attacks, e.g. the bot's holding account, so their legs get no token accounting
+    pub ignored_swappers: HashSet<String>,
+}
+
+impl Default for Classifier {
+    fn default() -> Self {
+        Self::new(TARGET_PROGRAM, get_instruction_map())
+    }
+}
+
+impl Classifier {
+    pub fn new(target_program: &str, instruction_map: HashMap<&str, &str>) -> Self {
+        Self {
+            target_program: target_program.to_string(),
+            instruction_map: instruction_map
+                .into_iter()
+                .map(|(discriminator, name)| (discriminator.to_string(), name.to_string()))
+                .collect(),
+            min_known_instructions: 1,
+            require_token_movement: false,
+            jito_tips: JitoTipConfig::default(),
+            ignored_swappers: HashSet::from([HOLDING_ACCOUNT.to_string()]),
+        }
+    }
+
+    // Maps a hex-encoded 8-byte discriminator to one of the lifecycle instructions the tracker understands,
+    // replacing any existing mapping, so a redeployed or forked bot can be detected without recompiling
+    pub fn add_instruction(&mut self, discriminator: &str, name: &str) -> Result<(), String> {
+        let discriminator: String = discriminator.to_lowercase();
+
+        if !is_valid_discriminator(&discriminator) {
+            return Err(format!(
+                "Invalid discriminator '{}', expected 8 hex-encoded bytes",
+                discriminator
+            ));
+        }
+
+        if !LIFECYCLE_INSTRUCTIONS.contains(&name) {
+            return Err(format!(
+                "Unknown instruction '{}', expected one of {}",
+                name,
+                LIFECYCLE_INSTRUCTIONS.join(", ")
+            ));
+        }
+
+        self.instruction_map.insert(discriminator, name.to_string());
+
+        Ok(())
+    }
+
+    // Checks that every key in the instruction map can match, since a malformed discriminator never would and
+    // its instruction would go silently undetected
+    // The error lists every bad entry
+    pub fn validate_instruction_map(&self) -> Result<(), String> {
+        let mut invalid: Vec<String> = self
+            .instruction_map
+            .iter()
+            .filter(|(discriminator, _)| !is_valid_discriminator(discriminator))
+            .map(|(discriminator, name)| format!("'{}' ({})", discriminator, name))
+            .collect();
+
+        if invalid.is_empty() {
+            return Ok(());
+        }
+
+        invalid.sort();
+
+        Err(format!(
+            "Invalid discriminators, expected 8 lowercase hex-encoded bytes: {}",
+            invalid.join(", ")
+        ))
+    }
+
+    // Checks whether a transaction invokes the target program, directly or through CPI
+    // The program id appearing in logs or instruction data isn't enough, since it can be mentioned without being called
+    pub fn invokes_target_program(&self, tx_with_meta: &EncodedTransactionWithStatusMeta) -> bool {
+        let versioned_tx: VersionedTransaction = match tx_with_meta.transaction.decode() {
+            Some(tx) => tx,
+            None => return false,
+        };
+
+        let account_keys: Vec<Pubkey> = full_account_keys(&versioned_tx.message, tx_with_meta.meta.as_ref())
+            .unwrap_or_else(|_| versioned_tx.message.static_account_keys().to_vec());
+
+        let target_program_idx: usize = match account_keys
+            .iter()
+            .position(|key| key.to_string() == self.target_program)
+        {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        let top_level_instructions: &[CompiledInstruction] = versioned_tx.message.instructions();
+
+        with_inner_instructions(top_level_instructions, tx_with_meta.meta.as_ref())
+            .iter()
+            .any(|ix| ix.program_id_index as usize == target_program_idx)
+    }
+
+    // Classifies each known target-program instruction in a transaction, independent of any block iteration
+    pub fn classify_transaction(
+        &self,
+        tx_with_meta: &EncodedTransactionWithStatusMeta,
+        slot: u64,
+        block_height: u64,
+        block_time: Option<i64>,
+    ) -> Vec<ClassifiedTransaction> {
+        self.classify_transaction_with_unknown(tx_with_meta, slot, block_height, block_time, &mut HashMap::new())
+    }
+
+    // Like classify_transaction, but also counts each target-program instruction whose discriminator isn't in the
+    // instruction map into unknown_discriminators, keyed by the hex-encoded discriminator
+    pub fn classify_transaction_with_unknown(
+        &self,
+        tx_with_meta: &EncodedTransactionWithStatusMeta,
+        slot: u64,
+        block_height: u64,
+        block_time: Option<i64>,
+        unknown_discriminators: &mut HashMap<String, usize>,
+    ) -> Vec<ClassifiedTransaction> {
+        let versioned_tx: VersionedTransaction = match tx_with_meta.transaction.decode() {
+            Some(tx) => tx,
+            None => return vec![],
+        };
+
+        let mut found_txs: Vec<ClassifiedTransaction> = Vec::new();
+        let mut processed_types: HashSet<String> = HashSet::new();
+        // Repeats of an already classified instruction type, which share one set of transaction-wide balances and
+        // so can't be told apart
+        let mut dropped: Vec<&str> = Vec::new();
+
+        let top_level_instructions: &[CompiledInstruction] = versioned_tx.message.instructions();
+
+        // Instruction accounts and balance indices of V0 transactions can point past the static keys into addresses
+        // loaded from lookup tables
+        let account_keys: Vec<Pubkey> = full_account_keys(&versioned_tx.message, tx_with_meta.meta.as_ref())
+            .unwrap_or_else(|_| versioned_tx.message.static_account_keys().to_vec());
+
+        // The program may be invoked directly or through CPI from a router or aggregator
+        let instructions: Vec<CompiledInstruction> =
+            with_inner_instructions(top_level_instructions, tx_with_meta.meta.as_ref());
+
+        let signature: String = if !versioned_tx.signatures.is_empty() {
+            versioned_tx.signatures[0].to_string()
+        } else {
+            "".to_string()
+        };
+
+        let num_signers: usize = versioned_tx.message.header().num_required_signatures as usize;
+
+        let signers: Vec<String> = if account_keys.len() >= num_signers {
+            account_keys[..num_signers].iter().map(|key| key.to_string()).collect()
+        } else {
+            Vec::new()
+        };
+
+        // The fee payer is always the first account key and the first signer, so it's tracked as the signer
+        // whose balances are measured, while the remaining signers are kept alongside it
+        let fee_payer: String = account_keys.first().map(|key| key.to_string()).unwrap_or_default();
+        let signer: String = signers.first().cloned().unwrap_or_else(|| fee_payer.clone());
+        let signer_index: usize = if account_keys.is_empty() { usize::MAX } else { 0 };
+
+        let target_program_idx: Option<usize> = account_keys
+            .iter()
+            .position(|key| key.to_string() == self.target_program);
+
+        let pre_token_balances: &[UiTransactionTokenBalance] = match &tx_with_meta.meta {
+            Some(meta) => meta.pre_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]),
+            None => &[],
+        };
+
+        let post_token_balances: &[UiTransactionTokenBalance] = match &tx_with_meta.meta {
+            Some(meta) => meta.post_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]),
+            None => &[],
+        };
+
+        let jito_tip_amount: u64 = match &tx_with_meta.meta {
+            Some(meta) => detect_jito_tip(
+                &self.jito_tips,
+                &account_keys,
+                &instructions,
+                &meta.pre_balances,
+                &meta.post_balances,
+            ),
+            None => 0,
+        };
+
+        let fee: u64 = tx_with_meta.meta.as_ref().map_or(0, |meta| meta.fee);
+        let failed: bool = tx_with_meta.meta.as_ref().is_some_and(|meta| meta.err.is_some());
+
+        // Some meta records carry token balances but empty lamport balances, so treat missing entries as no change
+        let lamport_change: i64 = match &tx_with_meta.meta {
+            Some(meta) => match (
+                meta.pre_balances.get(signer_index),
+                meta.post_balances.get(signer_index),
+            ) {
+                (Some(&pre), Some(&post)) => post as i64 - pre as i64,
+                _ => 0,
+            },
+            None => 0,
+        };
+
+        let sol_equivalent_change: Option<i64> = tx_with_meta.meta.as_ref().map(|meta| {
+            sol_equivalent_deltas(
+                &account_keys,
+                &meta.pre_balances,
+                &meta.post_balances,
+                pre_token_balances,
+                post_token_balances,
+            )
+            .get(&signer)
+            .copied()
+            .unwrap_or(0)
+        });
+
+        // The tip is part of the signer's lamport delta if the signer lost at least the tip amount
+        let tip_paid_by_signer: bool = jito_tip_amount > 0 && lamport_change <= -(jito_tip_amount as i64);
+
+        for ix in &instructions {
+            if Some(ix.program_id_index as usize) == target_program_idx {
+                // Ensure the instruction data is at least 8 bytes so we can extract the discriminator
+                if ix.data.len() < 8 {
+                    continue;
+                }
+
+                let discriminator_bytes: &[u8] = &ix.data[0..8];
+                let hex_data: String = encode(discriminator_bytes);
+
+                // Check if we've already processed this instruction type
+                if processed_types.contains(&hex_data) {
+                    if let Some(name) = self.instruction_map.get(&hex_data) {
+                        dropped.push(name);
+                    }
+                    continue;
+                }
+
+                // Check if the discriminator matches any known instruction
+                if let Some(name) = self.instruction_map.get(hex_data.as_str()) {
+                    let name: &str = name.as_str();
+                    processed_types.insert(hex_data);
+
+                    let sandwich_acc: Result<String, SandwichError> = match name {
+                        // Cashout and Exit are assumed to take the sandwich account in the same position as the create
+                        "CreateSandwichV2" | "Cashout" | "Exit" => {
+                            instruction_account(ix, 2, &account_keys, &signature).map(|key| key.to_string())
+                        }
+                        // Swaps pass it sixth, or else seventh
+                        "AutoSwapIn" | "AutoSwapOut" => instruction_account(ix, 6, &account_keys, &signature)
+                            .or_else(|_| instruction_account(ix, 7, &account_keys, &signature))
+                            .map(|key| key.to_string()),
+                        _ => Ok(String::new()),
+                    };
+
+                    // A malformed instruction can't be tied to a sandwich account, so it's skipped rather than indexed
+                    let sandwich_acc: String = match sandwich_acc {
+                        Ok(sandwich_acc) => sandwich_acc,
+                        Err(e) => {
+                            eprintln!("Skipping {} instruction: {}", name, e);
+                            continue;
+                        }
+                    };
+
+                    // Token balances are recorded per transaction, not per instruction, so only swap legs get token
+                    // accounting; otherwise a create bundled with a swap would inherit the swap's mints and amounts
+                    let swap_info: Option<SwapInfo> = match name {
+                        "AutoSwapIn" | "AutoSwapOut" => find_token_accounts(
+                            ix.clone(),
+                            &account_keys,
+                            num_signers,
+                            pre_token_balances,
+                            post_token_balances,
+                        ),
+                        _ => None,
+                    }
+                    .filter(|swap_info| {
+                        let ignored: bool = self.ignored_swappers.contains(&swap_info.swapper);
+
+                        if ignored {
+                            eprintln!("Filtered out swap involving ignored swapper: {}", swap_info.swapper);
+                        }
+
+                        !ignored
+                    });
+
+                    // A swap leg that moved no tokens is more likely an unrelated call into the program than a real swap
+                    if self.require_token_movement
+                        && swap_info.is_none()
+                        && matches!(name, "AutoSwapIn" | "AutoSwapOut")
+                    {
+                        continue;
+                    }
+
+                    let mut classified_tx: ClassifiedTransaction = ClassifiedTransaction {
+                        signature: signature.clone(),
+                        signer: signer.clone(),
+                        signers: signers.clone(),
+                        fee_payer: fee_payer.clone(),
+                        slot,
+                        block_height,
+                        block_time,
+                        instruction_type: name.to_string(),
+                        sandwich_acc,
+                        jito_tip_amount,
+                        tip_paid_by_signer,
+                        lamport_change,
+                        fee,
+                        failed,
+                        sol_equivalent_change,
+                        ..ClassifiedTransaction::new()
+                    };
+
+                    if let Some(swap_info) = swap_info {
+                        classified_tx.apply_swap_info(swap_info);
+                    }
+
+                    found_txs.push(classified_tx);
+                } else {
+                    *unknown_discriminators.entry(hex_data).or_insert(0) += 1;
+                }
+            }
+        }
+
+        if !dropped.is_empty() {
+            eprintln!(
+                "Warning: transaction {} repeats {} known instructions ({}); only the first of each type was classified",
+                signature,
+                dropped.len(),
+                dropped.join(", ")
+            );
+        }
+
+        if found_txs.len() < self.min_known_instructions {
+            return vec![];
+        }
+
+        found_txs
+    }
+}
+
+// Resolves the account an instruction passes at position, failing instead of panicking if either the position or
+// the account index it holds is out of range
+fn instruction_account(
+    ix: &CompiledInstruction,
+    position: usize,
+    account_keys: &[Pubkey],
+    signature: &str,
+) -> Result<Pubkey, SandwichError> {
+    ix.accounts
+        .get(position)
+        .and_then(|&idx| account_keys.get(idx as usize))
+        .copied()
+        .ok_or_else(|| SandwichError::InvalidInstructionAccount {
+            signature: signature.to_string(),
+            position,
+        })
+}
+
+// Returns a message's static account keys followed by any keys loaded from address lookup tables, writable before
+// readonly, which is the order instruction account indices and balance indices refer to
+// Fails if a loaded address can't be parsed, since every index after it would resolve to the wrong key
+pub fn full_account_keys(
+    message: &VersionedMessage,
+    meta: Option<&UiTransactionStatusMeta>,
+) -> Result<Vec<Pubkey>, SandwichError> {
+    let mut keys: Vec<Pubkey> = message.static_account_keys().to_vec();
+
+    let loaded: Option<&UiLoadedAddresses> = meta.and_then(|meta| meta.loaded_addresses.as_ref().into());
+    if let Some(loaded) = loaded {
+        for address in loaded.writable.iter().chain(&loaded.readonly) {
+            keys.push(parse_pubkey(address)?);
+        }
+    }
+
+    Ok(keys)
+}
+
+// Interleaves each top-level instruction with the inner instructions it invoked, in execution order
+// Inner instructions index into the same account key list as top-level ones, so they're returned in the same form
+// Parsed (non-compiled) inner instructions and undecodable data are skipped
+fn with_inner_instructions(
+    instructions: &[CompiledInstruction],
+    meta: Option<&UiTransactionStatusMeta>,
+) -> Vec<CompiledInstruction> {
+    let inner_instructions: &[UiInnerInstructions] = match meta {
+        Some(meta) => meta.inner_instructions.as_ref().map(|v| v.as_slice()).unwrap_or(&[]),
+        None => &[],
+    };
+
+    let mut all: Vec<CompiledInstruction> = Vec::with_capacity(instructions.len());
+
+    for (i, ix) in instructions.iter().enumerate() {
+        all.push(ix.clone());
+
+        for inner in inner_instructions.iter().filter(|inner| inner.index as usize == i) {
+            for instruction in &inner.instructions {
+                if let UiInstruction::Compiled(compiled) = instruction {
+                    if let Ok(data) = bs58::decode(&compiled.data).into_vec() {
+                        all.push(CompiledInstruction::new_from_raw_parts(
+                            compiled.program_id_index,
+                            data,
+                            compiled.accounts.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    all
+}
+
+/// Classifies a transaction against the known sandwich program with the default instruction map and gating
+///
+/// Use a `Classifier` to target another program or instruction map. A bot opening a sandwich account:
+///
+/// ```
+/// use base64::{engine::general_purpose::STANDARD, Engine};
+/// use sandwich_detector::classify::classify_transaction;
+/// use sandwich_detector::types::{get_instruction_map, TARGET_PROGRAM};
+/// use solana_sdk::{
+///     instruction::{AccountMeta, Instruction},
+///     pubkey::Pubkey,
+///     transaction::Transaction,
+/// };
+/// use solana_transaction_status::{EncodedTransaction, EncodedTransactionWithStatusMeta, TransactionBinaryEncoding};
+///
+/// let (bot, sandwich): (Pubkey, Pubkey) = (Pubkey::new_unique(), Pubkey::new_unique());
+/// let (discriminator, _) = get_instruction_map()
+///     .into_iter()
+///     .find(|(_, name)| *name == "CreateSandwichV2")
+///     .unwrap();
+/// let create: Instruction = Instruction::new_with_bytes(
+///     TARGET_PROGRAM.parse().unwrap(),
+///     &hex::decode(discriminator).unwrap(),
+///     vec![AccountMeta::new(bot, true), AccountMeta::new(bot, true), AccountMeta::new(sandwich, false)],
+/// );
+/// let tx: Transaction = Transaction::new_with_payer(&[create], Some(&bot));
+///
+/// let tx_with_meta = EncodedTransactionWithStatusMeta {
+///     transaction: EncodedTransaction::Binary(
+///         STANDARD.encode(bincode::serialize(&tx).unwrap()),
+///         TransactionBinaryEncoding::Base64,
+///     ),
+///     meta: Some(
+///         serde_json::from_value(serde_json::json!({
+///             "err": null,
+///             "status": { "Ok": null },
+///             "fee": 5000,
+///             "preBalances": [10_000_000_000u64, 1, 1],
+///             "postBalances": [9_999_995_000u64, 1, 1],
+///         }))
+///         .unwrap(),
+///     ),
+///     version: None,
+/// };
+///
+/// let classified = classify_transaction(&tx_with_meta, 100, 90, Some(1_700_000_100));
+/// assert_eq!(classified.len(), 1);
+/// assert_eq!(classified[0].instruction_type, "CreateSandwichV2");
+/// assert_eq!(classified[0].signer, bot.to_string());
+/// assert_eq!(classified[0].sandwich_acc, sandwich.to_string());
+/// ```
+pub fn classify_transaction(
+    tx_with_meta: &EncodedTransactionWithStatusMeta,
+    slot: u64,
+    block_height: u64,
+    block_time: Option<i64>,
+) -> Vec<ClassifiedTransaction> {
+    Classifier::default().classify_transaction(tx_with_meta, slot, block_height, block_time)
+}
+
+fn find_token_accounts(
+    ix: CompiledInstruction,
+    account_keys: &[Pubkey],
+    num_signers: usize,
+    pre_token_balances: &[UiTransactionTokenBalance],
+    post_token_balances: &[UiTransactionTokenBalance],
+) -> Option<SwapInfo> {
+    let mut swap_info: SwapInfo = SwapInfo::new();
+
+    // Get the indices of accounts involved in the instruction
+    let relevant_accounts: HashSet<usize> = ix
+        .accounts
+        .iter()
+        .filter_map(|&idx| {
+            let account_idx = idx as usize;
+            if account_idx < account_keys.len() {
+                Some(account_idx)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    // Create maps for pre and post balances
+    let pre_map: HashMap<usize, &UiTransactionTokenBalance> = pre_token_balances
+        .iter()
+        .filter(|b| relevant_accounts.contains(&(b.account_index as usize)))
+        .map(|b| (b.account_index as usize, b))
+        .collect();
+
+    let post_map: HashMap<usize, &UiTransactionTokenBalance> = post_token_balances
+        .iter()
+        .filter(|b| relevant_accounts.contains(&(b.account_index as usize)))
+        .map(|b| (b.account_index as usize, b))
+        .collect();
+
+    // Track changes for each mint, in base units
+    let mut other_mint_changes: HashMap<String, Vec<(i128, usize)>> = HashMap::new();
+    let mut primary_mint = String::new();
+    let mut max_abs_change: u128 = 0;
+
+    // Accounts opened or closed by the instruction have nothing to compare, so only those with both sides count
+    let diffs: Vec<AccountBalanceDiff> = account_balance_diffs(pre_token_balances, post_token_balances)
+        .into_iter()
+        .filter(|diff| relevant_accounts.contains(&(diff.account_index as usize)))
+        .filter(|diff| diff.pre.is_some() && diff.post.is_some())
+        .collect();
+
+    // Identify the primary token being swapped (the one with the largest absolute change)
+    for diff in &diffs {
+        let change: i128 = diff.change();
+
+        if change != 0 && diff.mint != WSOL_MINT {
+            if change.unsigned_abs() > max_abs_change {
+                max_abs_change = change.unsigned_abs();
+                primary_mint = diff.mint.clone();
+            }
+            other_mint_changes
+                .entry(diff.mint.clone())
+                .or_default()
+                .push((change, diff.account_index as usize));
+        }
+    }
+
+    // The bot's wSOL account is the one owned by a signer, taking the first signer's lowest-indexed account if there
+    // are several, so the pool's side of the same transfer is never picked up instead
+    // Its signed change is what the bot paid (negative) or received (positive), kept in lamports since converting to
+    // SOL here would lose precision
+    let wsol_change: Option<i128> = account_keys.iter().take(num_signers).find_map(|signer| {
+        let signer: String = signer.to_string();
+        diffs
+            .iter()
+            .find(|diff| diff.mint == WSOL_MINT && diff.owner.as_ref() == Some(&signer))
+            .map(AccountBalanceDiff::change)
+    });
+
+    // Process the Primary token canges
+    if let Some(token_changes) = other_mint_changes.get(&primary_mint) {
+        let (decrease, increase): (Vec<_>, Vec<_>) = token_changes.iter().partition(|&&(change, _)| change < 0);
+
+        if let (Some(&(dec_change, dec_idx)), Some(&(inc_change, inc_idx))) = (decrease.first(), increase.first()) {
+            // Only used to display amounts until the real decimals are fetched; amounts are already in base units
+            let decimals: u8 = DEFAULT_DECIMALS;
+
+            let decrease_amount: u64 = dec_change.unsigned_abs() as u64;
+            let increase_amount: u64 = inc_change as u64;
+
+            swap_info.from_mint = primary_mint.clone();
+            swap_info.from_amount = decrease_amount;
+            swap_info.to_mint = primary_mint.clone();
+            swap_info.to_amount = increase_amount;
+            swap_info.wsol_change = wsol_change;
+            swap_info.decimals = decimals;
+
+            if let Some(swapper) = find_swapper(&ix, account_keys, num_signers, &pre_map, &post_map, dec_idx, inc_idx) {
+                swap_info.swapper = swapper;
+            }
+
+            swap_info.mint_changes = significant_mint_changes(&other_mint_changes);
+            // The swapper is whichever side's token account shrank, so on a buy it's the pool; the signers' own
+            // accounts are never the pool's vaults either way
+            let mut not_pool: HashSet<String> = account_keys
+                .iter()
+                .take(num_signers)
+                .map(|key| key.to_string())
+                .collect();
+            not_pool.insert(swap_info.swapper.clone());
+
+            swap_info.reserves = reconstruct_pool_reserves(&pre_map, &primary_mint, &not_pool);
+            swap_info.pool = find_pool_owner(&pre_map, &primary_mint, &not_pool);
+
+            return Some(swap_info);
+        }
+    }
+
+    None
+}
+
+// Returns every mint that both left one of the instruction's token accounts and entered another, largest first
+// A mint that only moved one way wasn't swapped through, e.g. a fee transfer
+fn significant_mint_changes(mint_changes: &HashMap<String, Vec<(i128, usize)>>) -> Vec<MintChange> {
+    let mut significant: Vec<MintChange> = mint_changes
+        .iter()
+        .filter_map(|(mint, changes)| {
+            let from_amount: u64 = changes
+                .iter()
+                .filter(|(change, _)| *change < 0)
+                .map(|(change, _)| change.unsigned_abs() as u64)
+                .max()?;
+            let to_amount: u64 = changes
+                .iter()
+                .filter(|(change, _)| *change > 0)
+                .map(|(change, _)| *change as u64)
+                .max()?;
+
+            Some(MintChange {
+                mint: mint.clone(),
+                from_amount,
+                to_amount,
+                decimals: None,
+            })
+        })
+        .collect();
+
+    significant.sort_by(|a, b| b.from_amount.cmp(&a.from_amount).then_with(|| a.mint.cmp(&b.mint)));
+
+    significant
+}
+
+// Finds who swapped: a signer owning the decreasing or increasing token account, since on a buy the tokens leave
+// the pool and enter the signer's account; failing that, whoever owns the decreasing token account
+// The pre-balance owner is preferred, then the post-balance owner, since some RPC responses omit the owner on one
+// side; if neither is recorded, the instruction's first account that signed the transaction is used
+fn find_swapper(
+    ix: &CompiledInstruction,
+    account_keys: &[Pubkey],
+    num_signers: usize,
+    pre_map: &HashMap<usize, &UiTransactionTokenBalance>,
+    post_map: &HashMap<usize, &UiTransactionTokenBalance>,
+    dec_idx: usize,
+    inc_idx: usize,
+) -> Option<String> {
+    let owner_of = |map: &HashMap<usize, &UiTransactionTokenBalance>, idx: usize| {
+        let owner: Option<&String> = map.get(&idx)?.owner.as_ref().into();
+        owner.filter(|owner| !owner.is_empty()).cloned()
+    };
+    let either_owner_of = |idx: usize| owner_of(pre_map, idx).or_else(|| owner_of(post_map, idx));
+    let is_signer = |owner: &String| {
+        account_keys
+            .iter()
+            .take(num_signers)
+            .any(|key| key.to_string() == *owner)
+    };
+
+    let signing_owner: Option<String> = [dec_idx, inc_idx]
+        .into_iter()
+        .filter_map(either_owner_of)
+        .find(is_signer);
+
+    signing_owner.or_else(|| either_owner_of(dec_idx)).or_else(|| {
+        ix.accounts
+            .iter()
+            .map(|&idx| idx as usize)
+            .find(|&idx| idx < num_signers)
+            .and_then(|idx| account_keys.get(idx))
+            .map(|key| key.to_string())
+    })
+}
+
+// Reads a token balance's raw integer amount in base units
+fn parse_base_units(balance: &UiTransactionTokenBalance) -> u64 {
+    balance.ui_token_amount.amount.parse::<u64>().unwrap_or(0)
+}
+
+// Reconstructs the pool's reserves from the pre-balances of the instruction's token accounts
+// The pool's vaults are taken to be the largest primary token and wSOL accounts not owned by any of not_pool
+fn reconstruct_pool_reserves(
+    pre_map: &HashMap<usize, &UiTransactionTokenBalance>,
+    primary_mint: &str,
+    not_pool: &HashSet<String>,
+) -> Option<PoolReserves> {
+    let mut token_reserve: u64 = 0;
+    let mut sol_reserve: u64 = 0;
+
+    for balance in pre_map.values() {
+        let owner: Option<&String> = balance.owner.as_ref().into();
+        if owner.is_some_and(|owner| not_pool.contains(owner)) {
+            continue;
+        }
+
+        let amount: u64 = parse_base_units(balance);
+
+        if balance.mint == primary_mint {
+            token_reserve = token_reserve.max(amount);
+        } else if balance.mint == WSOL_MINT {
+            sol_reserve = sol_reserve.max(amount);
+        }
+    }
+
+    if token_reserve == 0 || sol_reserve == 0 {
+        return None;
+    }
+
+    Some(PoolReserves {
+        token_reserve,
+        sol_reserve,
+    })
+}
+
+// Identifies the pool as the owner of the largest token account for the primary mint not owned by any of not_pool
+fn find_pool_owner(
+    pre_map: &HashMap<usize, &UiTransactionTokenBalance>,
+    primary_mint: &str,
+    not_pool: &HashSet<String>,
+) -> Option<String> {
+    pre_map
+        .values()
+        .filter(|balance| balance.mint == primary_mint)
+        .filter_map(|balance| {
+            let owner: Option<&String> = balance.owner.as_ref().into();
+            let amount: u64 = parse_base_units(balance);
+
+            owner
+                .filter(|owner| !not_pool.contains(owner.as_str()))
+                .map(|owner| (amount, owner))
+        })
+        .max_by_key(|(amount, _)| *amount)
+        .map(|(_, owner)| owner.clone())
+}
+
+// Checks if an address is one of the configured Jito tip addresses
+pub fn is_jito_tip_address(config: &JitoTipConfig, addr: &str) -> bool {
+    config.is_tip_address(addr)
+}
+
+// Checks Jito tips: the lamports System Program transfers send to each tip address, capped at how much that
+// address's balance actually rose
+// A tip account's balance alone can't tell a tip from anything else crediting it in the same transaction, e.g. an
+// unrelated program paying out to it or a closed account's rent, so only explicit transfers are counted
+pub fn detect_jito_tip(
+    config: &JitoTipConfig,
+    account_keys: &[Pubkey],
+    instructions: &[CompiledInstruction],
+    pre_balances: &[u64],
+    post_balances: &[u64],
+) -> u64 {
+    let mut transferred: HashMap<usize, u64> = HashMap::new();
+
+    for ix in instructions {
+        let (destination, lamports) = match system_transfer(ix, account_keys) {
+            Some(transfer) => transfer,
+            None => continue,
+        };
+
+        if is_jito_tip_address(config, &account_keys[destination].to_string()) {
+            let total: &mut u64 = transferred.entry(destination).or_default();
+            *total = total.saturating_add(lamports);
+        }
+    }
+
+    // Balances can be shorter than the account keys (or empty), e.g. when lookup tables expand the account set,
+    // so only accounts with both a pre- and post-balance are checked
+    transferred
+        .into_iter()
+        .filter_map(|(index, lamports)| {
+            let increase: u64 = post_balances.get(index)?.saturating_sub(*pre_balances.get(index)?);
+            Some(lamports.min(increase))
+        })
+        .filter(|tip| *tip >= config.min_tip)
+        .sum()
+}
+
+// Returns the destination account index and lamports of a System Program transfer, or None for any other instruction
+fn system_transfer(ix: &CompiledInstruction, account_keys: &[Pubkey]) -> Option<(usize, u64)> {
+    let program: &Pubkey = account_keys.get(ix.program_id_index as usize)?;
+    if program.to_string() != SYSTEM_PROGRAM || ix.data.len() != 12 {
+        return None;
+    }
+
+    let (index, lamports) = ix.data.split_at(4);
+    if u32::from_le_bytes(index.try_into().ok()?) != SYSTEM_TRANSFER {
+        return None;
+    }
+
+    let destination: usize = *ix.accounts.get(1)? as usize;
+    if destination >= account_keys.len() {
+        return None;
+    }
+
+    Some((destination, u64::from_le_bytes(lamports.try_into().ok()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        create, discriminator, key, system_program, token_balance, transfer, CannedTransaction, Swap,
+    };
+    use crate::types::{Pattern, PatternTracker};
+
+    const MINT: &str = "Mint111111111111111111111111111111111111111";
+
+    fn classify(swap: &Swap) -> ClassifiedTransaction {
+        let mut classified: Vec<ClassifiedTransaction> =
+            Classifier::default().classify_transaction(&swap.build().encode(), 100, 90, None);
+        assert_eq!(classified.len(), 1);
+        classified.remove(0)
+    }
+
+    #[test]
+    fn reserves_are_reconstructed_from_the_pool_vaults_on_a_buy() {
+        let leg: ClassifiedTransaction = classify(&Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000));
+
+        // The tokens left the pool's vault, but the swapper is still the signer they went to
+        assert_eq!(leg.swapper, key(1).to_string());
+        assert_eq!(
+            leg.reserves,
+            Some(PoolReserves {
+                token_reserve: 1_000_000_000_000,
+                sol_reserve: 500_000_000_000,
+            })
+        );
+        assert_eq!(leg.pool, Some(key(200).to_string()));
+    }
+
+    #[test]
+    fn reserves_are_reconstructed_from_the_pool_vaults_on_a_sell() {
+        let leg: ClassifiedTransaction = classify(&Swap::leg(2, "AutoSwapOut", key(1), MINT, -50_000, 2_000_000));
+
+        assert_eq!(leg.swapper, key(1).to_string());
+        assert_eq!(
+            leg.reserves,
+            Some(PoolReserves {
+                token_reserve: 1_000_000_000_000,
+                sol_reserve: 500_000_000_000,
+            })
+        );
+    }
+
+    #[test]
+    fn the_pool_is_the_owner_of_the_vaults_on_both_legs() {
+        let through = |pool: Pubkey, name: &str, token_change: i64, sol_change: i64| -> ClassifiedTransaction {
+            classify(&Swap {
+                pool,
+                ..Swap::leg(1, name, key(1), MINT, token_change, sol_change)
+            })
+        };
+
+        // On the buy the pool's token vault shrinks, and on the sell its wSOL vault does
+        let buy: ClassifiedTransaction = through(key(210), "AutoSwapIn", 50_000, -2_000_000_000);
+        let sell: ClassifiedTransaction = through(key(210), "AutoSwapOut", -50_000, 2_100_000_000);
+        assert_eq!(buy.pool, Some(key(210).to_string()));
+        assert_eq!(sell.pool, Some(key(210).to_string()));
+        assert_eq!(
+            through(key(211), "AutoSwapIn", 50_000, -2_000_000_000).pool,
+            Some(key(211).to_string())
+        );
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(ClassifiedTransaction {
+            tx_index: 0,
+            ..Classifier::default()
+                .classify_transaction(&create(10, key(1)).encode(), 100, 90, None)
+                .remove(0)
+        });
+        tracker.process_transaction(ClassifiedTransaction { tx_index: 1, ..buy });
+        tracker.process_transaction(ClassifiedTransaction { tx_index: 3, ..sell });
+        assert_eq!(tracker.take_completed()[0].pool, Some(key(210).to_string()));
+    }
+
+    #[test]
+    fn reserves_need_both_a_token_and_a_wsol_vault() {
+        let pool_token: UiTransactionTokenBalance = token_balance(3, MINT, &key(200), 1_000_000);
+        let bot_wsol: UiTransactionTokenBalance = token_balance(2, WSOL_MINT, &key(1), 5_000_000);
+        let pre_map: HashMap<usize, &UiTransactionTokenBalance> = HashMap::from([(3, &pool_token), (2, &bot_wsol)]);
+
+        assert_eq!(
+            reconstruct_pool_reserves(&pre_map, MINT, &HashSet::from([key(1).to_string()])),
+            None
+        );
+    }
+
+    #[test]
+    fn the_swapper_falls_back_to_the_post_balance_owner() {
+        let ownerless = |account_index: u8, amount: u64| {
+            let mut balance: UiTransactionTokenBalance = token_balance(account_index, MINT, &key(1), amount);
+            balance.owner = None.into();
+            balance
+        };
+        let account_keys: Vec<Pubkey> = vec![key(1), key(101), key(203)];
+        // The pool's vault at 2 shrank and the signer's account at 1 grew, with no owner on the pre side
+        let pre: Vec<UiTransactionTokenBalance> = vec![ownerless(1, 0), ownerless(2, 50_000)];
+        let post: Vec<UiTransactionTokenBalance> = vec![ownerless(1, 50_000), token_balance(2, MINT, &key(200), 0)];
+        fn map(balances: &[UiTransactionTokenBalance]) -> HashMap<usize, &UiTransactionTokenBalance> {
+            balances
+                .iter()
+                .map(|balance| (balance.account_index as usize, balance))
+                .collect()
+        }
+        let (pre_map, post_map) = (map(&pre), map(&post));
+        let unsigned: CompiledInstruction = CompiledInstruction::new_from_raw_parts(2, vec![], vec![1, 2]);
+
+        assert_eq!(
+            find_swapper(&unsigned, &account_keys, 1, &pre_map, &post_map, 2, 1),
+            Some(key(200).to_string())
+        );
+
+        // With no owner on either side, the instruction's signing account is the swapper, and without one there's none
+        let no_owners: Vec<UiTransactionTokenBalance> = vec![ownerless(1, 50_000), ownerless(2, 0)];
+        let post_map: HashMap<usize, &UiTransactionTokenBalance> = map(&no_owners);
+        assert_eq!(
+            find_swapper(&unsigned, &account_keys, 1, &pre_map, &post_map, 2, 1),
+            None
+        );
+        let signed: CompiledInstruction = CompiledInstruction::new_from_raw_parts(2, vec![], vec![0, 1, 2]);
+        assert_eq!(
+            find_swapper(&signed, &account_keys, 1, &pre_map, &post_map, 2, 1),
+            Some(key(1).to_string())
+        );
+    }
+
+    #[test]
+    fn priority_fees_are_carried_into_the_net_profit() {
+        // Each leg pays a 5,000 lamport base fee, plus the given priority fee
+        let sandwich = |priority_fee: u64| -> Pattern {
+            let mut tracker: PatternTracker = PatternTracker::new();
+            let legs: [CannedTransaction; 3] = [
+                create(10, key(1)),
+                Swap::leg(11, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000_000).build(),
+                Swap::leg(13, "AutoSwapOut", key(1), MINT, -50_000, 2_100_000_000).build(),
+            ];
+
+            for (tx_index, mut tx) in legs.into_iter().enumerate() {
+                tx.fee += priority_fee;
+                tx.post_balances[0] -= priority_fee;
+
+                for mut classified in Classifier::default().classify_transaction(&tx.encode(), 100, 90, None) {
+                    assert_eq!(classified.fee, 5_000 + priority_fee);
+                    classified.tx_index = tx_index;
+                    tracker.process_transaction(classified);
+                }
+            }
+
+            tracker.take_completed().remove(0)
+        };
+
+        let (base, prioritized) = (sandwich(0), sandwich(95_000));
+        assert_eq!(base.get_total_fees(), 15_000);
+        assert_eq!(prioritized.get_total_fees(), 300_000);
+        assert_eq!(
+            base.get_sol_profit_lamports() - prioritized.get_sol_profit_lamports(),
+            3 * 95_000
+        );
+        assert!(prioritized.to_summary().contains("Total Fees Paid: 300000"));
+    }
+
+    #[test]
+    fn amounts_past_f64_precision_stay_exact() {
+        // 2^53 + 1 base units can't be represented as an f64
+        const AMOUNT: u64 = 9_007_199_254_740_993;
+        const BALANCE: u64 = 18_000_000_000_000_000_001;
+
+        // The signer buys AMOUNT tokens for AMOUNT lamports of wSOL out of very deep accounts
+        let swap = |seed: u8, name: &str, tokens: i128, wsol: i128| -> ClassifiedTransaction {
+            let mut tx: CannedTransaction = Swap::leg(seed, name, key(1), MINT, 0, 0).build();
+            let shift = |balance: u64, change: i128| (balance as i128 + change) as u64;
+            let owners: [(u8, &str, Pubkey, i128); 4] = [
+                (1, MINT, key(1), tokens),
+                (2, WSOL_MINT, key(1), wsol),
+                (3, MINT, key(200), -tokens),
+                (4, WSOL_MINT, key(200), -wsol),
+            ];
+            tx.pre_token_balances = owners
+                .iter()
+                .map(|(index, mint, owner, _)| token_balance(*index, mint, owner, BALANCE))
+                .collect();
+            tx.post_token_balances = owners
+                .iter()
+                .map(|(index, mint, owner, change)| token_balance(*index, mint, owner, shift(BALANCE, *change)))
+                .collect();
+
+            let mut classified: Vec<ClassifiedTransaction> =
+                Classifier::default().classify_transaction(&tx.encode(), 100, 90, None);
+            classified.remove(0)
+        };
+
+        let swap_in: ClassifiedTransaction = swap(11, "AutoSwapIn", AMOUNT as i128, -(AMOUNT as i128));
+        assert_eq!(swap_in.from_amount, AMOUNT);
+        assert_eq!(swap_in.wsol_change, Some(-(AMOUNT as i128)));
+
+        let swap_out: ClassifiedTransaction = swap(13, "AutoSwapOut", -(AMOUNT as i128), AMOUNT as i128 + 2);
+        assert_eq!(swap_out.wsol_change, Some(AMOUNT as i128 + 2));
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(
+            Classifier::default()
+                .classify_transaction(&create(10, key(1)).encode(), 100, 90, None)
+                .remove(0),
+        );
+        tracker.process_transaction(ClassifiedTransaction { tx_index: 1, ..swap_in });
+        tracker.process_transaction(ClassifiedTransaction {
+            tx_index: 2,
+            ..swap_out
+        });
+        let pattern: Pattern = tracker.take_completed().remove(0);
+
+        // Two lamports of gross profit survive, less each leg's 5,000 lamport fee
+        assert_eq!(pattern.get_sol_profit_lamports(), 2 - 15_000);
+    }
+
+    #[test]
+    fn amounts_of_a_six_decimal_mint_stay_in_on_chain_base_units() {
+        // test_support's token balances report 6 decimals; a 9-decimal assumption would scale these by 1,000
+        let leg: ClassifiedTransaction = classify(&Swap::leg(1, "AutoSwapIn", key(1), MINT, 123_456_789, -2_000_000));
+
+        assert_eq!(leg.from_amount, 123_456_789);
+        assert_eq!(leg.to_amount, 123_456_789);
+    }
+
+    #[test]
+    fn the_wsol_change_is_the_signed_delta_of_the_signers_account() {
+        assert_eq!(
+            classify(&Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000)).wsol_change,
+            Some(-2_000_000)
+        );
+        assert_eq!(
+            classify(&Swap::leg(2, "AutoSwapOut", key(1), MINT, -50_000, 2_000_000)).wsol_change,
+            Some(2_000_000)
+        );
+
+        // The pool's wSOL vault comes first and, with a fee taken elsewhere, moves less than the bot's account; the
+        // bot's account is still the one read, with its own sign even though the leg is named a swap-in
+        let mut tx: CannedTransaction = Swap::leg(3, "AutoSwapIn", key(1), MINT, -50_000, 2_000_000).build();
+        for balances in [&mut tx.pre_token_balances, &mut tx.post_token_balances] {
+            balances[1].account_index = 4;
+            balances[3].account_index = 2;
+        }
+        tx.post_token_balances[3] = token_balance(2, WSOL_MINT, &key(200), 500_000_000_000 - 1_990_000);
+
+        let classified: Vec<ClassifiedTransaction> =
+            Classifier::default().classify_transaction(&tx.encode(), 100, 90, None);
+        assert_eq!(classified[0].wsol_change, Some(2_000_000));
+    }
+
+    // The bot's create, co-signed by a second key listed right after it
+    fn co_signed_create(co_signer: Pubkey) -> CannedTransaction {
+        let mut tx: CannedTransaction = create(1, key(1));
+        tx.account_keys.insert(1, co_signer);
+        tx.num_signers = 2;
+        tx.instructions[0].program_id_index = 2;
+        tx.instructions[0].accounts = vec![0, 0, 3];
+        tx.pre_balances.insert(1, 1_000_000);
+        tx.post_balances.insert(1, 1_000_000);
+        tx
+    }
+
+    #[test]
+    fn every_signer_of_a_two_signer_transaction_is_recorded() {
+        let classified: Vec<ClassifiedTransaction> =
+            Classifier::default().classify_transaction(&co_signed_create(key(9)).encode(), 100, 90, None);
+
+        assert_eq!(classified.len(), 1);
+        assert_eq!(classified[0].signers, vec![key(1).to_string(), key(9).to_string()]);
+        assert_eq!(classified[0].signer, key(1).to_string());
+        assert_eq!(classified[0].fee_payer, key(1).to_string());
+    }
+
+    #[test]
+    fn a_combined_create_and_buy_classifies_each_instruction_on_its_own_accounts() {
+        // The swap leg's layout, with the sandwich program's create run first on the same sandwich account
+        let mut tx: CannedTransaction = Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000).build();
+        tx.instructions.insert(
+            0,
+            CompiledInstruction::new_from_raw_parts(5, discriminator("CreateSandwichV2"), vec![0, 0, 6]),
+        );
+
+        let classified: Vec<ClassifiedTransaction> =
+            Classifier::default().classify_transaction(&tx.encode(), 100, 90, None);
+        assert_eq!(classified.len(), 2);
+
+        let (create, buy) = (&classified[0], &classified[1]);
+        assert_eq!(create.instruction_type, "CreateSandwichV2");
+        assert_eq!(buy.instruction_type, "AutoSwapIn");
+        assert_eq!(create.sandwich_acc, buy.sandwich_acc);
+        assert_eq!(create.signature, buy.signature);
+
+        // The create touches none of the token accounts, so the buy's movement isn't attributed to it
+        assert_eq!((create.from_amount, create.to_amount), (0, 0));
+        assert_eq!((buy.from_mint.as_str(), buy.to_mint.as_str()), (MINT, MINT));
+        assert_eq!((buy.from_amount, buy.to_amount), (50_000, 50_000));
+    }
+
+    #[test]
+    fn a_swap_the_program_ran_through_cpi_is_classified_like_a_top_level_one() {
+        // A router at index 7 is the only top-level instruction, and it invokes the swap leg's instruction
+        let mut tx: CannedTransaction = Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000).build();
+        let swap: CompiledInstruction = tx.instructions.remove(0);
+        tx.account_keys.push(key(205));
+        tx.pre_balances.push(1);
+        tx.post_balances.push(1);
+        tx.instructions = vec![CompiledInstruction::new_from_raw_parts(
+            7,
+            vec![1],
+            vec![0, 1, 2, 3, 4, 5, 6],
+        )];
+
+        let mut encoded: EncodedTransactionWithStatusMeta = tx.encode();
+        let direct: Vec<ClassifiedTransaction> = Classifier::default().classify_transaction(&encoded, 100, 90, None);
+        assert!(direct.is_empty());
+
+        let inner: UiInnerInstructions = serde_json::from_value(serde_json::json!({
+            "index": 0,
+            "instructions": [{
+                "programIdIndex": swap.program_id_index,
+                "accounts": swap.accounts,
+                "data": bs58::encode(&swap.data).into_string(),
+                "stackHeight": 2,
+            }],
+        }))
+        .unwrap();
+        encoded.meta.as_mut().unwrap().inner_instructions = Some(vec![inner]).into();
+
+        let classifier: Classifier = Classifier::default();
+        assert!(classifier.invokes_target_program(&encoded));
+        let classified: Vec<ClassifiedTransaction> = classifier.classify_transaction(&encoded, 100, 90, None);
+        assert_eq!(classified.len(), 1);
+
+        // The inner instruction's account indices resolve against the same keys as a top-level one would
+        assert_eq!(classified[0].instruction_type, "AutoSwapIn");
+        assert_eq!(classified[0].sandwich_acc, key(201).to_string());
+        assert_eq!(classified[0].swapper, key(1).to_string());
+        assert_eq!((classified[0].from_amount, classified[0].to_amount), (50_000, 50_000));
+    }
+
+    #[test]
+    fn accounts_past_the_static_keys_resolve_to_lookup_table_addresses() {
+        // The sandwich account is loaded read-only, after one writable loaded address
+        let mut tx: CannedTransaction = Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000).build();
+        assert_eq!(tx.account_keys.pop(), Some(key(201)));
+        tx.instructions[0].accounts = vec![0, 1, 2, 3, 4, 0, 7, 7];
+        tx.pre_balances.push(1);
+        tx.post_balances.push(1);
+
+        let encoded: EncodedTransactionWithStatusMeta = tx.encode_with_lookup_table(&[key(205)], &[key(201)]);
+        let versioned_tx: VersionedTransaction = encoded.transaction.decode().unwrap();
+        assert_eq!(
+            full_account_keys(&versioned_tx.message, encoded.meta.as_ref()).unwrap()[6..],
+            [key(205), key(201)]
+        );
+
+        let classified: Vec<ClassifiedTransaction> =
+            Classifier::default().classify_transaction(&encoded, 100, 90, None);
+        assert_eq!(classified.len(), 1);
+        assert_eq!(classified[0].sandwich_acc, key(201).to_string());
+        assert_eq!(classified[0].swapper, key(1).to_string());
+        assert_eq!((classified[0].from_amount, classified[0].to_amount), (50_000, 50_000));
+    }
+
+    #[test]
+    fn gating_skips_target_program_calls_that_move_no_tokens_or_too_few_instructions() {
+        // A swap leg whose token balances didn't change, e.g. an unrelated call into the program
+        let mut tx: CannedTransaction = Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000).build();
+        tx.post_token_balances = tx.pre_token_balances.clone();
+        let tx: EncodedTransactionWithStatusMeta = tx.encode();
+
+        assert_eq!(Classifier::default().classify_transaction(&tx, 100, 90, None).len(), 1);
+        let requiring_movement: Classifier = Classifier {
+            require_token_movement: true,
+            ..Classifier::default()
+        };
+        assert!(requiring_movement.classify_transaction(&tx, 100, 90, None).is_empty());
+
+        // A create moves no tokens either way, so it's still classified
+        let create_tx: EncodedTransactionWithStatusMeta = create(2, key(1)).encode();
+        assert_eq!(
+            requiring_movement.classify_transaction(&create_tx, 100, 90, None).len(),
+            1
+        );
+
+        // With two known instructions required, a lone create doesn't count
+        let requiring_two: Classifier = Classifier {
+            min_known_instructions: 2,
+            ..Classifier::default()
+        };
+        assert!(requiring_two.classify_transaction(&create_tx, 100, 90, None).is_empty());
+    }
+
+    fn jito_tip_account() -> Pubkey {
+        crate::types::JITO_TIP_ADDRESSES[0].parse().unwrap()
+    }
+
+    #[test]
+    fn instructions_with_out_of_range_accounts_are_skipped_instead_of_panicking() {
+        let ix: CompiledInstruction = CompiledInstruction::new_from_raw_parts(1, vec![], vec![0, 9]);
+        let account_keys: Vec<Pubkey> = vec![key(1), key(2)];
+
+        assert_eq!(instruction_account(&ix, 0, &account_keys, "sig").unwrap(), key(1));
+        // Past the instruction's accounts, and an index past the account keys
+        for position in [2, 1] {
+            assert!(matches!(
+                instruction_account(&ix, position, &account_keys, "sig"),
+                Err(SandwichError::InvalidInstructionAccount { position: p, .. }) if p == position
+            ));
+        }
+
+        // A swap leg whose sandwich account indices point past the account keys
+        let mut tx = Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000).build();
+        tx.instructions[0].accounts[6] = 40;
+        tx.instructions[0].accounts[7] = 41;
+        assert!(Classifier::default()
+            .classify_transaction(&tx.encode(), 100, 90, None)
+            .is_empty());
+
+        // A create passing too few accounts
+        let mut tx = create(2, key(1));
+        tx.instructions[0].accounts.truncate(2);
+        assert!(Classifier::default()
+            .classify_transaction(&tx.encode(), 100, 90, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn only_transfers_to_tip_accounts_count_as_tips() {
+        let tip_account = |i: usize| -> Pubkey { crate::types::JITO_TIP_ADDRESSES[i].parse().unwrap() };
+        let account_keys: Vec<Pubkey> = vec![
+            key(1),
+            tip_account(0),
+            tip_account(1),
+            key(4),
+            system_program(),
+            tip_account(2),
+        ];
+        let instructions: Vec<CompiledInstruction> = vec![
+            transfer(0, 1, 4, 10_000),
+            transfer(0, 2, 4, 20_000),
+            // A transfer to an account that isn't a tip account
+            transfer(0, 3, 4, 50_000),
+        ];
+        // The second tip account was also credited 5 SOL by something other than a transfer, and the third only
+        // that way
+        let pre_balances: [u64; 6] = [10_000_000, 1_000_000, 1_000_000, 0, 1, 1_000_000];
+        let post_balances: [u64; 6] = [9_920_000, 1_010_000, 6_020_000, 50_000, 1, 6_000_000];
+
+        assert_eq!(
+            detect_jito_tip(
+                &JitoTipConfig::default(),
+                &account_keys,
+                &instructions,
+                &pre_balances,
+                &post_balances
+            ),
+            30_000
+        );
+    }
+
+    #[test]
+    fn a_tip_to_a_configured_address_is_recognized() {
+        let path = std::env::temp_dir().join(format!("sandwich-detector-tips-{}.json", std::process::id()));
+        std::fs::write(&path, format!(r#"{{"tip_addresses": ["{}"], "min_tip": 500}}"#, key(7))).unwrap();
+        let config: JitoTipConfig = JitoTipConfig::from_json_file(&path).unwrap();
+
+        // The configured list replaces the defaults
+        assert!(config.is_tip_address(&key(7).to_string()));
+        assert!(!config.is_tip_address(&jito_tip_account().to_string()));
+        assert_eq!(config.min_tip, 500);
+
+        let swap: Swap = Swap {
+            tip: Some((key(7), 600)),
+            ..Swap::leg(1, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000)
+        };
+        let configured: Classifier = Classifier {
+            jito_tips: config,
+            ..Classifier::default()
+        };
+        let classified: Vec<ClassifiedTransaction> =
+            configured.classify_transaction(&swap.build().encode(), 100, 90, None);
+        assert_eq!(classified[0].jito_tip_amount, 600);
+        assert_eq!(classify(&swap).jito_tip_amount, 0);
+
+        std::fs::write(&path, r#"{"tip_addresses": ["not an address"]}"#).unwrap();
+        assert!(JitoTipConfig::from_json_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tips_are_found_when_account_keys_outnumber_the_balances() {
+        // Lookup-table accounts past the end of the balances are skipped instead of indexed
+        let account_keys: Vec<Pubkey> = vec![key(1), jito_tip_account(), key(3), key(4), system_program()];
+        let pre_balances: [u64; 3] = [10_000_000, 1_000_000, 7];
+        let post_balances: [u64; 3] = [9_900_000, 1_100_000, 7];
+
+        assert_eq!(
+            detect_jito_tip(
+                &JitoTipConfig::default(),
+                &account_keys,
+                &[transfer(0, 1, 4, 100_000)],
+                &pre_balances,
+                &post_balances
+            ),
+            100_000
+        );
+    }
+
+    #[test]
+    fn empty_balance_arrays_mean_no_tip_and_no_lamport_change() {
+        let account_keys: Vec<Pubkey> = vec![key(1), jito_tip_account(), system_program()];
+        let tip: [CompiledInstruction; 1] = [transfer(0, 1, 2, 100_000)];
+        assert_eq!(
+            detect_jito_tip(&JitoTipConfig::default(), &account_keys, &tip, &[], &[]),
+            0
+        );
+
+        // Meta with token balances but no lamport balances still classifies, with no lamport change or tip
+        let mut tx = Swap::leg(3, "AutoSwapOut", key(1), MINT, -50_000, 2_000_000).build();
+        tx.pre_balances.clear();
+        tx.post_balances.clear();
+
+        let classified: Vec<ClassifiedTransaction> =
+            Classifier::default().classify_transaction(&tx.encode(), 100, 90, None);
+        assert_eq!(classified.len(), 1);
+        assert_eq!(classified[0].lamport_change, 0);
+        assert_eq!(classified[0].jito_tip_amount, 0);
+        assert_eq!(classified[0].from_amount, 50_000);
+    }
+
+    #[test]
+    fn malformed_or_short_discriminators_fail_validation_and_are_all_listed() {
+        assert_eq!(Classifier::default().validate_instruction_map(), Ok(()));
+
+        let mut instruction_map: HashMap<&str, &str> = get_instruction_map();
+        instruction_map.insert("5bb527f9eccb5e", "AutoSwapIn");
+        instruction_map.insert("b024faebda2bdezz", "AutoSwapOut");
+        let classifier: Classifier = Classifier::new(TARGET_PROGRAM, instruction_map);
+
+        let error: String = classifier.validate_instruction_map().unwrap_err();
+        assert!(error.contains("'5bb527f9eccb5e' (AutoSwapIn)"), "{}", error);
+        assert!(error.contains("'b024faebda2bdezz' (AutoSwapOut)"), "{}", error);
+        assert!(!error.contains("b3ecc1a00df8fe9a"), "{}", error);
+
+        let mut classifier: Classifier = Classifier::default();
+        assert!(classifier.add_instruction("5bb527f9", "AutoSwapIn").is_err());
+        assert!(classifier.add_instruction("not hex at all!!", "AutoSwapIn").is_err());
+        assert_eq!(classifier.validate_instruction_map(), Ok(()));
+    }
+
+    // Classifies a canned transaction alongside the struct the classifier used to spell out in full for each branch,
+    // comparing them field for field through their serialized form
+    fn assert_classified_as_written_out(canned: &CannedTransaction, swap_info: Option<SwapInfo>, sol_change: i64) {
+        let classified: Vec<ClassifiedTransaction> =
+            Classifier::default().classify_transaction(&canned.encode(), 100, 90, Some(1_700_000_000));
+        assert_eq!(classified.len(), 1);
+
+        let signer: String = canned.account_keys[0].to_string();
+        let instruction_type: String = classified[0].instruction_type.clone();
+        let sandwich_acc: String = key(201).to_string();
+        let written_out: ClassifiedTransaction = match swap_info {
+            Some(swap_info) => ClassifiedTransaction {
+                signature: canned.signature.to_string(),
+                signer: signer.clone(),
+                signers: vec![signer.clone()],
+                fee_payer: signer,
+                slot: 100,
+                tx_index: 0,
+                block_height: 90,
+                block_time: Some(1_700_000_000),
+                instruction_type,
+                sandwich_acc,
+                swapper: swap_info.swapper,
+                from_mint: swap_info.from_mint,
+                to_mint: swap_info.to_mint,
+                from_amount: swap_info.from_amount,
+                to_amount: swap_info.to_amount,
+                jito_tip_amount: 0,
+                tip_paid_by_signer: false,
+                wsol_change: swap_info.wsol_change,
+                lamport_change: -5000,
+                fee: 5000,
+                failed: false,
+                sol_equivalent_change: Some(sol_change - 5000),
+                decimals: swap_info.decimals,
+                decimals_resolved: false,
+                reserves: swap_info.reserves,
+                pool: swap_info.pool,
+                mint_changes: swap_info.mint_changes,
+            },
+            None => ClassifiedTransaction {
+                signature: canned.signature.to_string(),
+                signer: signer.clone(),
+                signers: vec![signer.clone()],
+                fee_payer: signer,
+                slot: 100,
+                tx_index: 0,
+                block_height: 90,
+                block_time: Some(1_700_000_000),
+                instruction_type,
+                sandwich_acc,
+                swapper: String::new(),
+                from_mint: String::new(),
+                to_mint: String::new(),
+                from_amount: 0,
+                to_amount: 0,
+                jito_tip_amount: 0,
+                tip_paid_by_signer: false,
+                wsol_change: None,
+                lamport_change: -5000,
+                fee: 5000,
+                failed: false,
+                sol_equivalent_change: Some(sol_change - 5000),
+                decimals: DEFAULT_DECIMALS,
+                decimals_resolved: false,
+                reserves: None,
+                pool: None,
+                mint_changes: Vec::new(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&classified[0]).unwrap(),
+            serde_json::to_value(&written_out).unwrap()
+        );
+    }
+
+    #[test]
+    fn shared_fields_and_swap_fields_build_the_same_legs_as_the_written_out_structs() {
+        assert_classified_as_written_out(&create(3, key(1)), None, 0);
+
+        let swap_in: CannedTransaction = Swap::leg(4, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000).build();
+        let swap_info: Option<SwapInfo> = find_token_accounts(
+            swap_in.instructions[0].clone(),
+            &swap_in.account_keys,
+            1,
+            &swap_in.pre_token_balances,
+            &swap_in.post_token_balances,
+        );
+        assert!(swap_info.is_some());
+        assert_classified_as_written_out(&swap_in, swap_info, -2_000_000);
+    }
+
+    #[test]
+    fn a_program_only_mentioned_in_logs_accounts_or_data_is_not_invoked() {
+        let target: Pubkey = TARGET_PROGRAM.parse().unwrap();
+
+        // Another program is passed the target program as an account and its id as data, and logs it
+        let mut tx: CannedTransaction = Swap {
+            data: target.to_bytes().to_vec(),
+            ..Swap::victim(1, key(2), MINT, 20_000, -1_000_000_000)
+        }
+        .build();
+        tx.account_keys.push(target);
+        tx.pre_balances.push(1);
+        tx.post_balances.push(1);
+        tx.instructions[0].accounts.push(7);
+
+        let mut encoded: EncodedTransactionWithStatusMeta = tx.encode();
+        encoded.meta.as_mut().unwrap().log_messages = Some(vec![
+            format!("Program {} invoke [1]", key(202)),
+            format!("Program log: routing through {}", TARGET_PROGRAM),
+            format!("Program {} success", key(202)),
+        ])
+        .into();
+
+        let classifier: Classifier = Classifier::default();
+        assert!(!classifier.invokes_target_program(&encoded));
+        assert!(classifier.classify_transaction(&encoded, 100, 90, None).is_empty());
+
+        let leg: EncodedTransactionWithStatusMeta = Swap::leg(2, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000)
+            .build()
+            .encode();
+        assert!(classifier.invokes_target_program(&leg));
+    }
+
+    #[test]
+    fn swaps_by_ignored_swappers_get_no_token_accounting() {
+        let swap = |classifier: &Classifier, signer: Pubkey| -> ClassifiedTransaction {
+            let encoded: EncodedTransactionWithStatusMeta =
+                Swap::leg(1, "AutoSwapIn", signer, MINT, 50_000, -2_000_000)
+                    .build()
+                    .encode();
+            let mut classified: Vec<ClassifiedTransaction> = classifier.classify_transaction(&encoded, 100, 90, None);
+            assert_eq!(classified.len(), 1);
+            classified.remove(0)
+        };
+
+        // The default list holds the known bot's holding account
+        let holding_account: Pubkey = HOLDING_ACCOUNT.parse().unwrap();
+        let classifier: Classifier = Classifier::default();
+        assert_eq!(swap(&classifier, holding_account).swapper, "");
+        assert_eq!(swap(&classifier, key(1)).swapper, key(1).to_string());
+
+        let classifier: Classifier = Classifier {
+            ignored_swappers: HashSet::from([key(7).to_string()]),
+            ..Classifier::default()
+        };
+
+        let ignored: ClassifiedTransaction = swap(&classifier, key(7));
+        assert_eq!(ignored.swapper, "");
+        assert_eq!(ignored.from_amount, 0);
+        assert_eq!(ignored.wsol_change, None);
+
+        assert_eq!(swap(&classifier, key(1)).swapper, key(1).to_string());
+        assert_eq!(swap(&classifier, key(1)).from_amount, 50_000);
+        assert_eq!(swap(&classifier, holding_account).swapper, HOLDING_ACCOUNT);
+    }
+
+    #[test]
+    fn a_second_swap_in_on_the_same_sandwich_account_is_dropped() {
+        let mut tx: CannedTransaction = Swap::leg(11, "AutoSwapIn", key(1), MINT, 50_000, -2_000_000).build();
+        tx.instructions.push(tx.instructions[0].clone());
+        assert_eq!(tx.instructions.len(), 2);
+
+        let classifier: Classifier = Classifier::default();
+        let swap_in: Vec<ClassifiedTransaction> = classifier.classify_transaction(&tx.encode(), 100, 90, None);
+        assert_eq!(swap_in.len(), 1);
+        assert_eq!(swap_in[0].instruction_type, "AutoSwapIn");
+        assert_eq!(swap_in[0].sandwich_acc, key(201).to_string());
+        assert_eq!(swap_in[0].from_amount, 50_000);
+
+        // Only the one leg reaches the tracker, so the sandwich completes with a single swap-in
+        let classify = |tx: CannedTransaction| classifier.classify_transaction(&tx.encode(), 100, 90, None).remove(0);
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(classify(create(10, key(1))));
+        for leg in swap_in {
+            tracker.process_transaction(ClassifiedTransaction { tx_index: 1, ..leg });
+        }
+        tracker.process_transaction(ClassifiedTransaction {
+            tx_index: 2,
+            ..classify(Swap::leg(13, "AutoSwapOut", key(1), MINT, -50_000, 2_100_000).build())
+        });
+
+        let completed: Vec<Pattern> = tracker.take_completed();
+        assert_eq!(completed.len(), 1);
+        assert!(completed[0].additional_swap_ins.is_empty());
+        assert_eq!(completed[0].get_token_profit(), 0);
+    }
+}
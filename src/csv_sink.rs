@@ -0,0 +1,119 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::types::{Pattern, Retraction};
+
+// A pattern flattened into spreadsheet-friendly columns
+// Lamport amounts are converted to SOL and token profit is in base units
+#[derive(Serialize)]
+struct PatternCsvRow<'a> {
+    token: &'a str,
+    attacker: &'a str,
+    victim: Option<&'a str>,
+    block_height: u64,
+    block_time: Option<i64>,
+    token_profit: i128,
+    sol_profit: f64,
+    jito_tip: f64,
+    create_signature: &'a str,
+    swap_in_signature: &'a str,
+    swap_out_signature: &'a str,
+}
+
+impl<'a> From<&'a Pattern> for PatternCsvRow<'a> {
+    fn from(pattern: &'a Pattern) -> Self {
+        Self {
+            token: &pattern.token,
+            attacker: &pattern.attacker,
+            victim: pattern.victims.first().map(|victim| victim.signer.as_str()),
+            block_height: pattern.create_tx().block_height,
+            block_time: pattern.create_tx().block_time,
+            token_profit: pattern.get_token_profit(),
+            sol_profit: pattern.get_sol_profit(),
+            jito_tip: pattern.get_total_jito_tips() as f64 / 1e9,
+            create_signature: &pattern.create_tx().signature,
+            swap_in_signature: &pattern.swap_in_tx().signature,
+            swap_out_signature: &pattern.swap_out_tx().signature,
+        }
+    }
+}
+
+// Writes one row per pattern to a CSV file
+// When appending, rows are added to the end of an existing file and the header is only written if the file was
+// empty, so a long run can keep adding to the same file; otherwise the file is overwritten
+pub fn write_patterns_csv(patterns: &[Pattern], path: &Path, append: bool) -> io::Result<()> {
+    let (file, write_header) = open_csv(path, append)?;
+
+    write_pattern_rows(patterns, file, write_header)
+}
+
+// Opens a CSV file for appending or overwriting, along with whether it still needs a header
+fn open_csv(path: &Path, append: bool) -> io::Result<(File, bool)> {
+    let file: File = if append {
+        OpenOptions::new().create(true).append(true).open(path)?
+    } else {
+        File::create(path)?
+    };
+    let write_header: bool = file.metadata()?.len() == 0;
+
+    Ok((file, write_header))
+}
+
+// Writes one row per pattern to any writer, e.g. stdout, optionally preceded by the header
+pub fn write_pattern_rows<'a, W: Write>(
+    patterns: impl IntoIterator<Item = &'a Pattern>,
+    writer: W,
+    header: bool,
+) -> io::Result<()> {
+    let mut writer: csv::Writer<W> = csv::WriterBuilder::new().has_headers(header).from_writer(writer);
+
+    for pattern in patterns {
+        writer.serialize(PatternCsvRow::from(pattern))?;
+    }
+
+    writer.flush()
+}
+
+// Writes one row per retraction to a CSV file, appending or overwriting like write_patterns_csv
+// Retractions have their own columns, so they go to a file of their own next to the patterns
+pub fn write_retractions_csv(retractions: &[Retraction], path: &Path, append: bool) -> io::Result<()> {
+    let (file, write_header) = open_csv(path, append)?;
+
+    let mut writer: csv::Writer<File> = csv::WriterBuilder::new().has_headers(write_header).from_writer(file);
+    for retraction in retractions {
+        writer.serialize(retraction)?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandwich_pattern;
+
+    #[test]
+    fn retractions_are_appended_under_a_single_header() {
+        let path = std::env::temp_dir().join(format!("sandwich-detector-{}.retractions.csv", std::process::id()));
+        let retraction: Retraction = Retraction::new(&sandwich_pattern("a"), 500, "reorged-hash");
+
+        write_retractions_csv(std::slice::from_ref(&retraction), &path, false).unwrap();
+        write_retractions_csv(std::slice::from_ref(&retraction), &path, true).unwrap();
+        let contents: String = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let row: &str = "500,reorged-hash,a-sig0,a-sig1,a-sig3";
+        assert_eq!(
+            contents,
+            format!(
+                "slot,blockhash,create_signature,swap_in_signature,swap_out_signature\n{}\n{}\n",
+                row, row
+            )
+        );
+    }
+}
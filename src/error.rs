@@ -0,0 +1,117 @@
+use solana_client::client_error::ClientError;
+use solana_sdk::pubkey::{ParsePubkeyError, Pubkey};
+use std::{error::Error, fmt, str::FromStr};
+
+use helius::error::HeliusError;
+
+// Errors from resolving and decoding the on-chain data the detector works with
+#[derive(Debug)]
+pub enum SandwichError {
+    // Boxed, since client errors are much larger than the other variants
+    RpcError(Box<ClientError>),
+    // An address that isn't a valid base58 public key
+    ParsePubkey {
+        address: String,
+        source: ParsePubkeyError,
+    },
+    DecodeTransaction(String),
+    // A shared lock was poisoned by a panic in another thread
+    PoisonedLock(String),
+    // Account data that doesn't have the expected layout, e.g. a mint that's too short or owned by another program
+    MalformedAccountData(String),
+    // A transaction newer than the highest transaction version that was requested
+    UnsupportedTransactionVersion {
+        signature: String,
+        max_transaction_version: u8,
+    },
+    // A checkpointed run made against a different target program than the one configured
+    ResumeTargetMismatch {
+        stored: String,
+        configured: String,
+    },
+    // An instruction without a valid account at a position it's expected to pass one, e.g. a truncated account list
+    // or an index past the transaction's account keys
+    InvalidInstructionAccount {
+        signature: String,
+        position: usize,
+    },
+    // Input that's neither a transaction signature nor a transaction URL containing one
+    InvalidSignature(String),
+    // A slot range whose start is after its end
+    InvalidSlotRange {
+        start: u64,
+        end: u64,
+    },
+}
+
+impl fmt::Display for SandwichError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandwichError::RpcError(e) => write!(f, "RPC error: {}", e),
+            SandwichError::ParsePubkey { address, source } => write!(f, "Invalid public key {}: {}", address, source),
+            SandwichError::DecodeTransaction(reason) => write!(f, "Failed to decode transaction: {}", reason),
+            SandwichError::PoisonedLock(name) => write!(f, "Lock poisoned: {}", name),
+            SandwichError::MalformedAccountData(reason) => write!(f, "Malformed account data: {}", reason),
+            SandwichError::UnsupportedTransactionVersion {
+                signature,
+                max_transaction_version,
+            } => write!(
+                f,
+                "Transaction {} is newer than version {}; raise --max-transaction-version to analyze it",
+                signature, max_transaction_version
+            ),
+            SandwichError::ResumeTargetMismatch { stored, configured } => write!(
+                f,
+                "Can't resume a run against program {} while targeting {}",
+                stored, configured
+            ),
+            SandwichError::InvalidInstructionAccount { signature, position } => write!(
+                f,
+                "Transaction {} has no valid instruction account at position {}",
+                signature, position
+            ),
+            SandwichError::InvalidSignature(input) => {
+                write!(f, "Could not find a valid transaction signature in {}", input)
+            }
+            SandwichError::InvalidSlotRange { start, end } => write!(
+                f,
+                "Invalid slot range {}..={}: the start slot is after the end slot",
+                start, end
+            ),
+        }
+    }
+}
+
+impl Error for SandwichError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SandwichError::RpcError(e) => Some(e.as_ref()),
+            SandwichError::ParsePubkey { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ClientError> for SandwichError {
+    fn from(e: ClientError) -> Self {
+        SandwichError::RpcError(Box::new(e))
+    }
+}
+
+// Lets code returning Helius results propagate detector errors with ?
+impl From<SandwichError> for HeliusError {
+    fn from(e: SandwichError) -> Self {
+        match e {
+            SandwichError::RpcError(e) => HeliusError::ClientError(*e),
+            e => HeliusError::InvalidInput(e.to_string()),
+        }
+    }
+}
+
+// Parses a base58 public key, keeping the offending address in the error
+pub fn parse_pubkey(address: &str) -> Result<Pubkey, SandwichError> {
+    Pubkey::from_str(address).map_err(|source| SandwichError::ParsePubkey {
+        address: address.to_string(),
+        source,
+    })
+}
@@ -1 +1,14 @@
+pub mod analysis;
+pub mod balances;
+pub mod classify;
+pub mod csv_sink;
+pub mod error;
+pub mod metrics;
+#[cfg(feature = "parquet")]
+pub mod parquet_sink;
+pub mod source;
+#[cfg(feature = "sqlite")]
+pub mod storage;
+#[cfg(test)]
+mod test_support;
 pub mod types;
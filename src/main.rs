@@ -1,33 +1,71 @@
+// helius::error::HeliusError is large, but it's the error type every RPC call here returns
+#![allow(clippy::result_large_err)]
+
+mod replay;
+mod selftest;
+
+use clap::Parser;
 use dotenv::dotenv;
+use futures::stream::{self, StreamExt};
 use lazy_static::lazy_static;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     env,
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process,
     str::FromStr,
-    sync::Mutex,
+    sync::Arc,
+    time::{Duration, Instant},
 };
+use tokio::time::sleep;
 
 use helius::error::Result;
-use helius::types::Cluster;
+use helius::types::{Asset, Cluster, GetAssetBatch};
 use helius::Helius;
 
-use hex::encode;
-use solana_client::rpc_config::RpcBlockConfig;
-use solana_sdk::{
-    instruction::CompiledInstruction, message::VersionedMessage, pubkey::Pubkey, transaction::VersionedTransaction,
-};
-use solana_transaction_status::{
-    EncodedTransactionWithStatusMeta, TransactionDetails, UiConfirmedBlock, UiTransactionEncoding,
-    UiTransactionStatusMeta, UiTransactionTokenBalance,
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_client::RpcClient,
+    rpc_config::RpcBlockConfig,
+    rpc_custom_error::{JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED, JSON_RPC_SERVER_ERROR_SLOT_SKIPPED},
+    rpc_request::RpcError,
 };
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{TransactionDetails, UiConfirmedBlock};
 
+use sandwich_detector::analysis::{
+    analyze_block_range_with, analyze_non_vote_transactions, analyze_transaction_block, asset_symbol, block_config,
+    classify_signature, new_tracker, parse_signature, report_block_fetch_error, subscribe_and_detect, websocket_url,
+    AnalysisOptions, MAX_BACKFILL_SLOTS, MAX_MULTIPLE_ACCOUNTS,
+};
+use sandwich_detector::classify::Classifier;
+use sandwich_detector::csv_sink::{write_pattern_rows, write_patterns_csv, write_retractions_csv};
+use sandwich_detector::metrics::{self, Metrics};
+use sandwich_detector::source::{BlockSource, RateLimitedClient, DEFAULT_REQUESTS_PER_SECOND};
 use sandwich_detector::types::{
-    get_instruction_map, ClassifiedTransaction, Pattern, PatternTracker, SwapInfo, JITO_TIP_ADDRESSES, MIN_JITO_TIP,
-    TARGET_PROGRAM, WSOL_MINT,
+    aggregate_attackers, aggregate_campaigns, rank_by_tip_efficiency, top_attackers_by_profit, top_by_sol_profit,
+    AmountUnit, AttackerStats, BlockAnalysis, BlockComposition, BotRegistry, Campaign, DecimalsCache, FixedSolPrice,
+    IncompletePattern, JitoTipConfig, MintLayout, OutputThrottle, Pattern, PatternTracker, Retraction, SlotSampling,
+    SolAccounting, SolPriceSource, SwapAttempts,
 };
 
+// Recent blocks analyzed when no slot, range, or transaction is given
+const DEFAULT_RECENT_BLOCKS: u64 = 5;
+// Mints assumed per block when estimating a scan's requests without --avg-mints
+// Any count up to MAX_MULTIPLE_ACCOUNTS costs the same single batched lookup
+const DEFAULT_ESTIMATE_MINTS_PER_BLOCK: u64 = 1;
+// Most mints looked up in one getAssetBatch call
+const MAX_ASSET_BATCH: usize = 1000;
+// Recent blocks fetched at once
+const DEFAULT_FETCH_CONCURRENCY: usize = 8;
+
 lazy_static! {
-    static ref DECIMALS_CACHE: Mutex<HashMap<String, u8>> = Mutex::new(HashMap::new());
+    // Process-wide decimals cache used by the CLI
+    static ref DECIMALS_CACHE: DecimalsCache = DecimalsCache::new();
+    // Process-wide counters, served over HTTP if --metrics-addr is given
+    static ref METRICS: Metrics = Metrics::new();
 }
 
 // EXAMPLE CALL FOR AN IDENTIFIED SANDWICH
@@ -47,491 +85,2212 @@ lazy_static! {
 async fn main() -> Result<()> {
     dotenv().ok();
 
-    let api_key: String = env::var("HELIUS_API_KEY").expect("HELIUS_API_KEY not found");
-    let cluster: Cluster = Cluster::MainnetBeta;
+    // clap prints --help and --version, and exits on malformed arguments, by itself
+    let mut options: CliOptions = match cli_options(Args::parse()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    options.analysis.metrics = Some(&METRICS);
+
+    // Estimates need no client, so they work before an API key is set up
+    if options.estimate {
+        match estimate_scan_requests(&options) {
+            Ok(estimate) => {
+                println!("{}", estimate.requests);
+                for line in estimate.notes(&options) {
+                    eprintln!("{}", line);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
 
-    let helius: Helius = Helius::new(&api_key, cluster).unwrap();
-    println!("Successfully created a Helius client");
+        return Ok(());
+    }
 
-    let recent_blocks: Vec<UiConfirmedBlock> = get_recent_blocks(&helius, 5).await?;
-    println!("Analyzing {} blocks", recent_blocks.len());
+    let bot_registry: Option<BotRegistry> = match &options.bot_registry {
+        Some(path) => match BotRegistry::from_json_file(path) {
+            Ok(registry) => Some(registry),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
 
-    for (i, block) in recent_blocks.iter().enumerate() {
-        println!("\nAnalyzing Block {}:", i + 1);
-        analyze_non_vote_transactions(&helius, block).await?;
-    }
+    // The self-test runs offline from archived blocks, so it needs no client either
+    if options.selftest {
+        let fixtures: selftest::Fixtures = match &options.fixtures_dir {
+            Some(dir) => selftest::Fixtures::Dir(dir.clone()),
+            None => selftest::Fixtures::Bundled,
+        };
 
-    Ok(())
-}
+        if !selftest::run(&fixtures, &options.analysis).await? {
+            process::exit(1);
+        }
 
-pub async fn get_token_decimals(helius: &Helius, mint_address: &str) -> Result<u8> {
-    // Check cache first
-    if let Some(decimals) = DECIMALS_CACHE.lock().unwrap().get(mint_address) {
-        return Ok(*decimals);
+        return Ok(());
     }
 
-    let mint_pubkey: Pubkey = Pubkey::from_str(mint_address).unwrap();
-    let account_data: Vec<u8> = helius.connection().get_account_data(&mint_pubkey)?;
+    // Replays need no client, since the block is read from disk and nothing is looked up
+    if let Some(path) = &options.replay_path {
+        let slot: Option<u64> = options.slot_range.map(|(slot, _)| slot);
 
-    // Token mint data has decimals at offset 44
-    let decimals = account_data[44];
+        let mut summary: RunSummary = RunSummary::default();
+        let analysis: BlockAnalysis = replay::analyze_block_file(path, slot, &options.analysis).await?;
+        report_block(&analysis, &options);
+        let mut patterns: Vec<Pattern> = summary.record_block(analysis);
 
-    // Cache the result
-    DECIMALS_CACHE
-        .lock()
-        .unwrap()
-        .insert(mint_address.to_string(), decimals);
+        label_attackers(&mut patterns, bot_registry.as_ref());
+        price_patterns(&mut patterns, options.sol_price.as_deref());
+        report_patterns(&patterns, &summary, &options);
+        report_incomplete(&summary, &options);
+        report_campaigns(&patterns, &options);
+        report_tip_efficiency(&patterns, &options);
+        report_leaderboard(&patterns, &options);
 
-    Ok(decimals)
-}
+        write_sinks(&patterns, &options);
 
-#[allow(dead_code)]
-fn get_block_by_slot(helius: &Helius, slot: u64) -> Result<Option<UiConfirmedBlock>> {
-    let config: RpcBlockConfig = RpcBlockConfig {
-        commitment: None,
-        max_supported_transaction_version: Some(0),
-        transaction_details: Some(TransactionDetails::Full),
-        rewards: Some(true),
-        encoding: Some(UiTransactionEncoding::Base64),
+        return Ok(());
+    }
+
+    // --cluster takes precedence over HELIUS_CLUSTER
+    let cluster: Cluster = match (&options.cluster, env::var("HELIUS_CLUSTER")) {
+        (Some(cluster), _) => cluster.clone(),
+        (None, Ok(name)) => match parse_cluster(&name) {
+            Ok(cluster) => cluster,
+            Err(e) => {
+                eprintln!("Invalid HELIUS_CLUSTER: {}", e);
+                process::exit(1);
+            }
+        },
+        (None, Err(_)) => Cluster::MainnetBeta,
     };
 
-    match helius.connection().get_block_with_config(slot, config) {
-        Ok(block) => Ok(Some(block)),
+    let helius: Helius = match create_helius_client(cluster) {
+        Ok(helius) => helius,
         Err(e) => {
-            eprintln!("Failed to fetch block at slot {}: {}", slot, e);
-            Ok(None)
+            eprintln!("{}", e);
+            process::exit(1);
         }
+    };
+    eprintln!("Successfully created a Helius client");
+
+    // Every RPC call goes through one rate limit, shared by the parallel block fetches
+    let source: Arc<RateLimitedClient<Arc<RpcClient>>> = Arc::new(RateLimitedClient::new(
+        helius.connection(),
+        options.requests_per_second.unwrap_or(DEFAULT_REQUESTS_PER_SECOND),
+    ));
+
+    if let Some(addr) = options.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(&METRICS, addr).await {
+                eprintln!("Metrics server on {} stopped: {}", addr, e);
+            }
+        });
+        eprintln!("Serving metrics on http://{}/metrics", addr);
     }
-}
 
-// Checks if a given transaction contains a known instructions
-fn find_known_instruction(
-    tx_with_meta: &EncodedTransactionWithStatusMeta,
-    block_height: u64,
-    block_time: Option<u64>,
-) -> Vec<ClassifiedTransaction> {
-    let versioned_tx: VersionedTransaction = match tx_with_meta.transaction.decode() {
-        Some(tx) => tx,
-        None => return vec![],
-    };
+    if let Some(path) = &options.dump_block_path {
+        // cli_options ensures a single slot was given
+        let slot: u64 = options.slot_range.map_or(0, |(slot, _)| slot);
 
-    let instruction_map: HashMap<&str, &str> = get_instruction_map();
-    let mut found_txs: Vec<ClassifiedTransaction> = Vec::new();
-    let mut processed_types: HashSet<String> = HashSet::new();
+        if !replay::dump_block(&source, slot, path, &options.analysis)? {
+            process::exit(1);
+        }
+        eprintln!("Saved block {} to {}", slot, path.display());
 
-    let (account_keys, instructions) = match &versioned_tx.message {
-        VersionedMessage::Legacy(msg) => (msg.account_keys.clone(), msg.instructions.clone()),
-        VersionedMessage::V0(msg) => (msg.account_keys.clone(), msg.instructions.clone()),
-    };
+        return Ok(());
+    }
 
-    let signature: String = if !versioned_tx.signatures.is_empty() {
-        versioned_tx.signatures[0].to_string()
-    } else {
-        "".to_string()
-    };
+    if options.subscribe {
+        let ws_url: String = websocket_url(&helius);
+        // The callback lives on in the tracker, so it takes ownership of the options it prints with
+        let analysis_options: AnalysisOptions = std::mem::take(&mut options.analysis);
+        let mut throttle: Option<OutputThrottle> = options
+            .throttle
+            .map(|interval| OutputThrottle::new(interval, analysis_options.sol_accounting));
+        let mut first: bool = true;
+
+        subscribe_and_detect(&ws_url, &source, &DECIMALS_CACHE, &analysis_options, move |pattern| {
+            let mut pattern: Pattern = pattern.clone();
+            if let Some(registry) = &bot_registry {
+                registry.label_pattern(&mut pattern);
+            }
+            pattern.sol_price_usd = options.sol_price.as_ref().and_then(|source| source.sol_price_usd());
 
-    let signer: String = {
-        let num_signers = versioned_tx.message.header().num_required_signatures as usize;
+            // The stream never ends, so a throttled window is only reported once a later pattern closes it
+            if let Some(throttle) = throttle.as_mut() {
+                if let Some(line) = throttle.record(&pattern, Instant::now()) {
+                    println!("{}", line);
+                }
+                return;
+            }
 
-        if num_signers > 0 && account_keys.len() >= num_signers {
-            account_keys[0].to_string()
-        } else {
-            "".to_string()
+            print_pattern(&pattern, &options, first);
+            first = false;
+        })
+        .await?;
+
+        return Ok(());
+    }
+
+    // A transaction URL or signature can be passed to analyze the block it landed in
+    if let Some(input) = &options.target {
+        let signature: Signature = match parse_signature(input) {
+            Some(signature) => signature,
+            None => {
+                eprintln!("Could not find a valid transaction signature in {}", input);
+                process::exit(1);
+            }
+        };
+
+        if options.classify_only {
+            for classified_tx in classify_signature(&source, input, &options.analysis)? {
+                match serde_json::to_string(&classified_tx) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => eprintln!(
+                        "Failed to serialize classified transaction {}: {}",
+                        classified_tx.signature, e
+                    ),
+                }
+            }
+
+            return Ok(());
         }
-    };
-    let signer_pubkey: Pubkey = match Pubkey::from_str(&signer) {
-        Ok(pk) => pk,
-        Err(_) => return vec![], // Invalid signer public key, but this shouldn't happen
-    };
-    let signer_index: usize = account_keys
-        .iter()
-        .position(|key| key == &signer_pubkey)
-        .unwrap_or(usize::MAX);
 
-    let target_program_idx: Option<usize> = account_keys.iter().position(|key| key.to_string() == TARGET_PROGRAM);
+        let mut summary: RunSummary = RunSummary::default();
+        let mut patterns: Vec<Pattern> =
+            match analyze_transaction_block(&source, &DECIMALS_CACHE, &signature, &options.analysis).await? {
+                Some(analysis) => {
+                    report_block(&analysis, &options);
+                    summary.record_block(analysis)
+                }
+                None => Vec::new(),
+            };
+        label_attackers(&mut patterns, bot_registry.as_ref());
+        price_patterns(&mut patterns, options.sol_price.as_deref());
+        if !options.no_symbols {
+            name_tokens(&helius, &DECIMALS_CACHE, &mut patterns).await;
+        }
+        report_patterns(&patterns, &summary, &options);
+        report_incomplete(&summary, &options);
+        report_campaigns(&patterns, &options);
+        report_tip_efficiency(&patterns, &options);
+        report_leaderboard(&patterns, &options);
 
-    let pre_token_balances: &[UiTransactionTokenBalance] = match &tx_with_meta.meta {
-        Some(meta) => meta.pre_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]),
-        None => &[],
-    };
+        write_sinks(&patterns, &options);
 
-    let post_token_balances: &[UiTransactionTokenBalance] = match &tx_with_meta.meta {
-        Some(meta) => meta.post_token_balances.as_ref().map(|v| v.as_slice()).unwrap_or(&[]),
-        None => &[],
-    };
+        return Ok(());
+    }
 
-    let jito_tip_amount: u64 = match &tx_with_meta.meta {
-        Some(meta) => detect_jito_tip(&account_keys, &meta.pre_balances, &meta.post_balances),
-        None => 0,
+    // With a database, range scans checkpoint each block into it, and --resume picks up the latest one
+    #[cfg(feature = "sqlite")]
+    let mut checkpoint: Option<Checkpoint> = match open_checkpoint(&mut options) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
     };
 
-    let lamport_change: i64 = if signer_index < tx_with_meta.meta.as_ref().map_or(0, |m| m.pre_balances.len())
-        && signer_index < tx_with_meta.meta.as_ref().map_or(0, |m| m.post_balances.len())
-    {
-        (tx_with_meta.meta.as_ref().unwrap().post_balances[signer_index] as i64)
-            - (tx_with_meta.meta.as_ref().unwrap().pre_balances[signer_index] as i64)
+    if let Some((start_slot, end_slot)) = options.slot_range {
+        let mut summary: RunSummary = RunSummary {
+            sampling: options.analysis.sampling,
+            ..RunSummary::default()
+        };
+        let mut patterns: Vec<Pattern> = Vec::new();
+
+        #[cfg(feature = "sqlite")]
+        let on_block = |analysis: &BlockAnalysis| checkpoint_block(checkpoint.as_mut(), analysis);
+        #[cfg(not(feature = "sqlite"))]
+        let on_block = |_: &BlockAnalysis| {};
+
+        for analysis in analyze_block_range_with(
+            &source,
+            &DECIMALS_CACHE,
+            start_slot,
+            end_slot,
+            &options.analysis,
+            on_block,
+        )
+        .await?
+        {
+            report_block(&analysis, &options);
+            patterns.extend(summary.record_block(analysis));
+        }
+
+        label_attackers(&mut patterns, bot_registry.as_ref());
+        price_patterns(&mut patterns, options.sol_price.as_deref());
+        if !options.no_symbols {
+            name_tokens(&helius, &DECIMALS_CACHE, &mut patterns).await;
+        }
+        report_patterns(&patterns, &summary, &options);
+        report_incomplete(&summary, &options);
+        report_campaigns(&patterns, &options);
+        report_tip_efficiency(&patterns, &options);
+        report_leaderboard(&patterns, &options);
+
+        write_sinks(&patterns, &options);
+
+        return Ok(());
+    }
+
+    let commitment: CommitmentConfig = if options.reorg_check.is_some() {
+        CommitmentConfig::confirmed()
     } else {
-        0
+        CommitmentConfig::finalized()
     };
 
-    for ix in &instructions {
-        if ix.program_id_index as usize == target_program_idx.unwrap_or_default() {
-            // Ensure the instruction data is at least 8 bytes so we can extract the discriminator
-            if ix.data.len() < 8 {
-                continue;
-            }
+    let recent_blocks: Vec<(u64, UiConfirmedBlock)> = get_recent_blocks(
+        source.clone(),
+        options.recent_blocks.unwrap_or(DEFAULT_RECENT_BLOCKS),
+        &options.empty_block_retry,
+        &options.analysis,
+        commitment,
+        options.fetch_concurrency.unwrap_or(DEFAULT_FETCH_CONCURRENCY),
+    )
+    .await?;
+    eprintln!("Analyzing {} blocks", recent_blocks.len());
+
+    let mut patterns: Vec<Pattern> = Vec::new();
+    let mut summary: RunSummary = RunSummary {
+        sampling: options.analysis.sampling,
+        ..RunSummary::default()
+    };
 
-            let discriminator_bytes: &[u8] = &ix.data[0..8];
-            let hex_data: String = encode(discriminator_bytes);
+    let mut throttle: Option<OutputThrottle> = options
+        .throttle
+        .map(|interval| OutputThrottle::new(interval, options.analysis.sol_accounting));
 
-            // Check if we've already processed this instruction type
-            if processed_types.contains(&hex_data) {
-                continue;
+    // Patterns from confirmed blocks, held until their block is checked against the finalized chain
+    let mut unverified: Vec<(u64, String, Vec<Pattern>)> = Vec::new();
+
+    let mut pattern_tracker: PatternTracker = new_tracker(&options.analysis);
+
+    for (i, (slot, block)) in recent_blocks.iter().enumerate() {
+        if options.analysis.max_block_gap == 0 {
+            pattern_tracker = new_tracker(&options.analysis);
+        }
+
+        eprintln!("\nAnalyzing Block {}:", i + 1);
+        let analysis: BlockAnalysis = analyze_non_vote_transactions(
+            &source,
+            &DECIMALS_CACHE,
+            &mut pattern_tracker,
+            *slot,
+            block,
+            &options.analysis,
+        )
+        .await?;
+        report_block(&analysis, &options);
+        let block_patterns: Vec<Pattern> = summary.record_block(analysis);
+
+        if let Some(throttle) = throttle.as_mut() {
+            for pattern in &block_patterns {
+                if let Some(line) = throttle.record(pattern, Instant::now()) {
+                    println!("{}", line);
+                }
             }
+        }
 
-            // Check if the discriminator matches any known instruction
-            if let Some(name) = instruction_map.get(hex_data.as_str()) {
-                processed_types.insert(hex_data);
+        if options.reorg_check.is_some() && !block_patterns.is_empty() {
+            unverified.push((*slot, block.blockhash.clone(), block_patterns));
+        } else {
+            patterns.extend(block_patterns);
+        }
+    }
 
-                let mut sandwich_acc: String = String::new();
+    if let Some(line) = throttle.as_mut().and_then(|throttle| throttle.flush(Instant::now())) {
+        println!("{}", line);
+    }
 
-                match *name {
-                    "CreateSandwichV2" => {
-                        if ix.accounts.len() > 2 {
-                            sandwich_acc = account_keys[ix.accounts[2] as usize].to_string();
-                        }
-                    }
-                    "AutoSwapIn" | "AutoSwapOut" => {
-                        let sandwich_acc_indices: [usize; 2] = [6, 7];
-
-                        for &idx in &sandwich_acc_indices {
-                            if idx < ix.accounts.len() {
-                                let account_idx: usize = ix.accounts[idx] as usize;
-
-                                if account_idx < account_keys.len() {
-                                    // Additional check for the actual program account pattern
-                                    let account: &Pubkey = &account_keys[account_idx];
-                                    sandwich_acc = account.to_string();
-                                    break; // Take the first valid match
-                                }
-                            }
+    let mut retractions: Vec<Retraction> = Vec::new();
+
+    if let Some(reorg_check) = &options.reorg_check {
+        for (slot, blockhash, block_patterns) in unverified {
+            let status: BlockStatus = verify_canonical(
+                &source,
+                slot,
+                &blockhash,
+                reorg_check,
+                options.analysis.max_transaction_version,
+            )
+            .await?;
+
+            match status {
+                BlockStatus::Canonical => patterns.extend(block_patterns),
+                BlockStatus::Reorged => {
+                    // Retraction events for anything that already acted on the confirmed patterns
+                    // Machine-readable output only ever printed verified patterns, so nothing there needs retracting
+                    for pattern in &block_patterns {
+                        let retraction: Retraction = Retraction::new(pattern, slot, &blockhash);
+
+                        if options.output.is_machine_readable() {
+                            eprintln!("{}", retraction.to_summary());
+                        } else {
+                            println!("{}", retraction.to_summary());
                         }
+                        retractions.push(retraction);
                     }
-                    _ => {}
+                    summary.patterns_retracted += block_patterns.len();
                 }
+                BlockStatus::Unfinalized => {
+                    eprintln!(
+                        "Slot {} wasn't finalized within {:?}, keeping its patterns unverified",
+                        slot, reorg_check.timeout
+                    );
+                    patterns.extend(block_patterns);
+                }
+            }
+        }
+    }
 
-                let classified_tx: ClassifiedTransaction = if let Some(swap_info) =
-                    find_token_accounts(ix.clone(), &account_keys, pre_token_balances, post_token_balances, name)
-                {
-                    ClassifiedTransaction {
-                        signature: signature.clone(),
-                        signer: signer.clone(),
-                        block_height,
-                        block_time,
-                        instruction_type: name.to_string(),
-                        sandwich_acc,
-                        swapper: swap_info.swapper,
-                        from_mint: swap_info.from_mint,
-                        to_mint: swap_info.to_mint,
-                        from_amount: swap_info.from_amount,
-                        to_amount: swap_info.to_amount,
-                        jito_tip_amount,
-                        wsol_change: swap_info.wsol_change,
-                        lamport_change,
-                        decimals: swap_info.decimals,
-                    }
-                } else {
-                    ClassifiedTransaction {
-                        signature: signature.clone(),
-                        signer: signer.clone(),
-                        block_height,
-                        block_time,
-                        instruction_type: name.to_string(),
-                        sandwich_acc,
-                        swapper: String::new(),
-                        from_mint: String::new(),
-                        to_mint: String::new(),
-                        from_amount: 0,
-                        to_amount: 0,
-                        jito_tip_amount,
-                        wsol_change: None,
-                        lamport_change,
-                        decimals: 9,
-                    }
-                };
+    label_attackers(&mut patterns, bot_registry.as_ref());
+    price_patterns(&mut patterns, options.sol_price.as_deref());
+    if !options.no_symbols {
+        name_tokens(&helius, &DECIMALS_CACHE, &mut patterns).await;
+    }
+    report_patterns(&patterns, &summary, &options);
+    report_incomplete(&summary, &options);
+    report_campaigns(&patterns, &options);
+    report_tip_efficiency(&patterns, &options);
+    report_leaderboard(&patterns, &options);
 
-                found_txs.push(classified_tx);
-            }
+    write_sinks(&patterns, &options);
+    write_retraction_sinks(&retractions, &options);
+
+    Ok(())
+}
+
+// Writes the detected patterns to whichever output files were requested
+fn write_sinks(patterns: &[Pattern], options: &CliOptions) {
+    if let Some(path) = &options.csv_path {
+        match write_patterns_csv(patterns, path, options.csv_append) {
+            Ok(()) => eprintln!("Wrote {} patterns to {}", patterns.len(), path.display()),
+            Err(e) => eprintln!("Failed to write CSV file {}: {}", path.display(), e),
         }
     }
 
-    found_txs
+    #[cfg(feature = "parquet")]
+    if let Some(path) = &options.parquet_path {
+        write_parquet(patterns, path);
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Ok(path) = env::var("SANDWICH_DB_PATH") {
+        write_sqlite(patterns, std::path::Path::new(&path));
+    }
 }
 
-fn find_token_accounts(
-    ix: CompiledInstruction,
-    account_keys: &[Pubkey],
-    pre_token_balances: &[UiTransactionTokenBalance],
-    post_token_balances: &[UiTransactionTokenBalance],
-    instruction_type: &str,
-) -> Option<SwapInfo> {
-    let mut swap_info: SwapInfo = SwapInfo::new();
+// Records retracted patterns in whichever output files were requested
+// Retractions have their own columns, so CSV and Parquet ones go to a file next to the patterns' file
+fn write_retraction_sinks(retractions: &[Retraction], options: &CliOptions) {
+    if retractions.is_empty() {
+        return;
+    }
+
+    if let Some(path) = &options.csv_path {
+        let path: PathBuf = retractions_path(path);
+        match write_retractions_csv(retractions, &path, options.csv_append) {
+            Ok(()) => eprintln!("Wrote {} retractions to {}", retractions.len(), path.display()),
+            Err(e) => eprintln!("Failed to write CSV file {}: {}", path.display(), e),
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    if let Some(path) = &options.parquet_path {
+        let path: PathBuf = retractions_path(path);
+        match sandwich_detector::parquet_sink::write_retractions(retractions, &path) {
+            Ok(()) => eprintln!("Wrote {} retractions to {}", retractions.len(), path.display()),
+            Err(e) => eprintln!("Failed to write Parquet file {}: {}", path.display(), e),
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Ok(path) = env::var("SANDWICH_DB_PATH") {
+        retract_stored(retractions, Path::new(&path));
+    }
+}
+
+// The file retractions are written to next to an output file, e.g. patterns.retractions.csv for patterns.csv
+fn retractions_path(path: &Path) -> PathBuf {
+    let extension: String = match path.extension() {
+        Some(extension) => format!("retractions.{}", extension.to_string_lossy()),
+        None => "retractions".to_string(),
+    };
+
+    path.with_extension(extension)
+}
+
+// Records retractions in a SQLite database, removing any of the retracted patterns it holds
+#[cfg(feature = "sqlite")]
+fn retract_stored(retractions: &[Retraction], path: &Path) {
+    use sandwich_detector::storage;
+
+    let result = storage::open(path).and_then(|mut conn| {
+        for retraction in retractions {
+            storage::retract_pattern(&mut conn, retraction)?;
+        }
+
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => eprintln!("Recorded {} retractions in {}", retractions.len(), path.display()),
+        Err(e) => eprintln!("Failed to record retractions in {}: {}", path.display(), e),
+    }
+}
 
-    // Get the indices of accounts involved in the instruction
-    let relevant_accounts: HashSet<usize> = ix
-        .accounts
-        .iter()
-        .filter_map(|&idx| {
-            let account_idx = idx as usize;
-            if account_idx < account_keys.len() {
-                Some(account_idx)
-            } else {
-                None
+// Persists the detected patterns to a SQLite database, skipping ones it already holds
+#[cfg(feature = "sqlite")]
+fn write_sqlite(patterns: &[Pattern], path: &std::path::Path) {
+    use sandwich_detector::storage;
+
+    let result = storage::open(path).and_then(|conn| {
+        let mut inserted: usize = 0;
+
+        for pattern in patterns {
+            if storage::insert_pattern(&conn, pattern)? {
+                inserted += 1;
             }
-        })
-        .collect();
+        }
 
-    // Create maps for pre and post balances
-    let pre_map: HashMap<usize, &UiTransactionTokenBalance> = pre_token_balances
-        .iter()
-        .filter(|b| relevant_accounts.contains(&(b.account_index as usize)))
-        .map(|b| (b.account_index as usize, b))
-        .collect();
+        Ok(inserted)
+    });
 
-    let post_map: HashMap<usize, &UiTransactionTokenBalance> = post_token_balances
-        .iter()
-        .filter(|b| relevant_accounts.contains(&(b.account_index as usize)))
-        .map(|b| (b.account_index as usize, b))
-        .collect();
+    match result {
+        Ok(inserted) => eprintln!("Stored {} new patterns in {}", inserted, path.display()),
+        Err(e) => eprintln!("Failed to store patterns in {}: {}", path.display(), e),
+    }
+}
 
-    // Track changes for each mint
-    let mut other_mint_changes: HashMap<String, Vec<(f64, usize)>> = HashMap::new();
-    let mut primary_mint = String::new();
-    let mut max_abs_change = 0.0;
-    let mut wsol_change: Option<f64> = None;
-
-    // Identify the primary token being swapped (the one with the largest absolute change)
-    for (idx, pre_balance) in pre_map.iter() {
-        if let Some(post_balance) = post_map.get(idx) {
-            // Convert lamports to SOL by dividing by 1e9
-            let pre_amount = pre_balance.ui_token_amount.amount.parse::<f64>().unwrap_or(0.0) / 1e9;
-            let post_amount = post_balance.ui_token_amount.amount.parse::<f64>().unwrap_or(0.0) / 1e9;
-            let change = post_amount - pre_amount;
-
-            if change.abs() > 0.0 && pre_balance.mint != WSOL_MINT {
-                if change.abs() > max_abs_change {
-                    max_abs_change = change.abs();
-                    primary_mint = pre_balance.mint.clone();
-                }
-                other_mint_changes
-                    .entry(pre_balance.mint.clone())
-                    .or_default()
-                    .push((change, *idx));
+// A range scan's row in the database's runs table, advanced as each block's patterns are stored
+#[cfg(feature = "sqlite")]
+struct Checkpoint {
+    conn: rusqlite::Connection,
+    run: sandwich_detector::storage::Run,
+}
+
+// Opens the checkpoint for a range scan if SANDWICH_DB_PATH is set, starting a new run, or with --resume continuing
+// the latest one by narrowing the range to the slots it has left
+#[cfg(feature = "sqlite")]
+fn open_checkpoint(options: &mut CliOptions) -> std::result::Result<Option<Checkpoint>, String> {
+    use sandwich_detector::storage;
+
+    let path: String = match env::var("SANDWICH_DB_PATH") {
+        Ok(path) => path,
+        Err(_) if options.resume => return Err("--resume requires SANDWICH_DB_PATH to be set".to_string()),
+        Err(_) => return Ok(None),
+    };
+
+    if !options.resume && options.slot_range.is_none() {
+        return Ok(None);
+    }
+
+    let conn: rusqlite::Connection =
+        storage::open(std::path::Path::new(&path)).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let target_program: &str = &options.analysis.classifier.target_program;
+
+    let run: storage::Run = if options.resume {
+        let run: storage::Run = storage::latest_run(&conn)
+            .map_err(|e| format!("Failed to read runs from {}: {}", path, e))?
+            .ok_or(format!("No run to resume in {}", path))?;
+
+        match run.resume_slot(target_program).map_err(|e| e.to_string())? {
+            Some(slot) => {
+                eprintln!("Resuming run {} from slot {} to {}", run.id, slot, run.end_slot);
+                options.slot_range = Some((slot, run.end_slot));
+            }
+            None => {
+                return Err(format!(
+                    "Run {} already reached slot {}; nothing to resume",
+                    run.id, run.end_slot
+                ))
             }
         }
+
+        run
+    } else {
+        let (start_slot, end_slot) = options.slot_range.unwrap_or_default();
+        storage::start_run(&conn, target_program, start_slot, end_slot)
+            .map_err(|e| format!("Failed to record the run in {}: {}", path, e))?
+    };
+
+    Ok(Some(Checkpoint { conn, run }))
+}
+
+// Stores a block's patterns and advances the run past it
+// A failed write is only reported, since the patterns are still written to the database at the end of the run
+#[cfg(feature = "sqlite")]
+fn checkpoint_block(checkpoint: Option<&mut Checkpoint>, analysis: &BlockAnalysis) {
+    if let Some(Checkpoint { conn, run }) = checkpoint {
+        if let Err(e) = sandwich_detector::storage::record_block(conn, run, analysis.slot, &analysis.patterns) {
+            eprintln!("Failed to checkpoint block {}: {}", analysis.slot, e);
+        }
     }
+}
 
-    // Look for wSOL changes associated with the primary token swap
-    if !primary_mint.is_empty() {
-        let mut primary_accounts: HashSet<String> = HashSet::new();
+// Writes the detected patterns to a Parquet file
+#[cfg(feature = "parquet")]
+fn write_parquet(patterns: &[Pattern], path: &std::path::Path) {
+    use sandwich_detector::parquet_sink::{ParquetSink, DEFAULT_ROW_GROUP_SIZE};
 
-        // Collect owners of accounts involved in the primary token swap
-        if let Some(changes) = other_mint_changes.get(&primary_mint) {
-            for &(_, idx) in changes {
-                if let Some(balance) = pre_map.get(&idx) {
-                    if let Some(owner) = &balance.owner.as_ref().map(|s| s.as_str()) {
-                        primary_accounts.insert(owner.to_string());
-                    }
+    let result = ParquetSink::create(path, DEFAULT_ROW_GROUP_SIZE).and_then(|mut sink| {
+        for pattern in patterns {
+            sink.write(pattern)?;
+        }
+
+        sink.close()
+    });
+
+    match result {
+        Ok(()) => eprintln!("Wrote {} patterns to {}", patterns.len(), path.display()),
+        Err(e) => eprintln!("Failed to write Parquet file {}: {}", path.display(), e),
+    }
+}
+
+// Labels attackers found in the known-bot registry, if one was loaded
+fn label_attackers(patterns: &mut [Pattern], registry: Option<&BotRegistry>) {
+    if let Some(registry) = registry {
+        for pattern in patterns {
+            registry.label_pattern(pattern);
+        }
+    }
+}
+
+// Values each pattern's profit in USD, if a SOL price is available
+fn price_patterns(patterns: &mut [Pattern], source: Option<&dyn SolPriceSource>) {
+    let price: Option<f64> = source.and_then(|source| source.sol_price_usd());
+
+    for pattern in patterns {
+        pattern.sol_price_usd = price;
+    }
+}
+
+// Attaches each pattern's token symbol from its metadata, if it has one
+async fn name_tokens(helius: &Helius, cache: &DecimalsCache, patterns: &mut [Pattern]) {
+    let mints: Vec<String> = patterns.iter().map(|pattern| pattern.token.clone()).collect();
+    let symbols: HashMap<String, String> = get_token_symbols(helius, cache, &mints).await;
+
+    for pattern in patterns {
+        pattern.token_symbol = symbols.get(&pattern.token).cloned();
+    }
+}
+
+// Resolves each mint's symbol with DAS getAssetBatch, caching the result (including mints without one) alongside
+// the decimals
+// Mints whose metadata can't be fetched are reported and left out, so they're shown by address
+async fn get_token_symbols(helius: &Helius, cache: &DecimalsCache, mints: &[String]) -> HashMap<String, String> {
+    let mut symbols: HashMap<String, String> = HashMap::new();
+    let mut uncached: Vec<String> = Vec::new();
+
+    for mint in mints {
+        if symbols.contains_key(mint) || uncached.contains(mint) {
+            continue;
+        }
+
+        match cache.get_symbol(mint) {
+            Ok(Some(symbol)) => {
+                if let Some(symbol) = symbol {
+                    symbols.insert(mint.clone(), symbol);
                 }
             }
+            Ok(None) => uncached.push(mint.clone()),
+            Err(e) => eprintln!("Failed to read the symbol cache for token {}: {}", mint, e),
         }
+    }
 
-        // Identify wSOL changes only for the primary accounts
-        for (idx, pre_balance) in pre_map.iter() {
-            if let Some(post_balance) = post_map.get(idx) {
-                if pre_balance.mint == WSOL_MINT {
-                    if let Some(owner) = &pre_balance.owner.as_ref().map(|s| s.as_str()) {
-                        if primary_accounts.contains(*owner) {
-                            // Convert lamports to SOL
-                            let pre_amount = pre_balance.ui_token_amount.amount.parse::<f64>().unwrap_or(0.0) / 1e9;
-                            let post_amount = post_balance.ui_token_amount.amount.parse::<f64>().unwrap_or(0.0) / 1e9;
-
-                            // **Calculate wSOL Change Based on Instruction Type**
-                            // For "AutoSwapIn", wsol_change = post - pre (increase)
-                            // For "AutoSwapOut", wsol_change = pre - post (decrease)
-                            wsol_change = match instruction_type {
-                                "AutoSwapIn" => Some(post_amount - pre_amount),
-                                "AutoSwapOut" => Some(pre_amount - post_amount),
-                                _ => None,
-                            };
-
-                            break; // Assume only one relevant wSOL change per instruction
-                        }
-                    }
-                }
+    for chunk in uncached.chunks(MAX_ASSET_BATCH) {
+        let request: GetAssetBatch = GetAssetBatch {
+            ids: chunk.to_vec(),
+            display_options: None,
+        };
+
+        let assets: Vec<Option<Asset>> = match helius.rpc().get_asset_batch(request).await {
+            Ok(assets) => assets,
+            Err(e) => {
+                eprintln!("Failed to fetch metadata for {} tokens: {}", chunk.len(), e);
+                METRICS.rpc_errors.inc();
+                continue;
+            }
+        };
+
+        for (mint, asset) in chunk.iter().zip(assets) {
+            let symbol: Option<String> = asset.as_ref().and_then(asset_symbol);
+
+            if let Err(e) = cache.insert_symbol(mint, symbol.clone()) {
+                eprintln!("Failed to cache the symbol for token {}: {}", mint, e);
+            }
+
+            if let Some(symbol) = symbol {
+                symbols.insert(mint.clone(), symbol);
             }
         }
     }
 
-    // Process the Primary token canges
-    if let Some(token_changes) = other_mint_changes.get(&primary_mint) {
-        let (decrease, increase): (Vec<_>, Vec<_>) = token_changes.iter().partition(|&&(change, _)| change < 0.0);
+    symbols
+}
 
-        if let (Some(&(dec_change, dec_idx)), Some(&(inc_change, _))) = (decrease.first(), increase.first()) {
-            let decimals: u8 = 9; // Temp set - will get overwritten by RPC call later
+// Estimates the RPC requests a scan of num_blocks blocks makes: one getBlock per block, plus one getMultipleAccounts
+// per MAX_MULTIPLE_ACCOUNTS mints whose decimals are looked up
+// Mints already cached from earlier blocks aren't accounted for, so this is an upper bound for a clean run
+fn estimate_request_count(num_blocks: u64, avg_mints_per_block: u64) -> u64 {
+    let decimals_requests_per_block: u64 = avg_mints_per_block.div_ceil(MAX_MULTIPLE_ACCOUNTS as u64);
 
-            let decrease_amount: u64 = (dec_change.abs() * 10f64.powi(decimals as i32)) as u64;
-            let increase_amount: u64 = (inc_change * 10f64.powi(decimals as i32)) as u64;
+    num_blocks * (1 + decimals_requests_per_block)
+}
 
-            swap_info.from_mint = primary_mint.clone();
-            swap_info.from_amount = decrease_amount;
-            swap_info.to_mint = primary_mint.clone();
-            swap_info.to_amount = increase_amount;
-            swap_info.wsol_change = wsol_change;
-            swap_info.decimals = decimals;
+// The RPC requests a scan is expected to make, for --estimate
+#[derive(Debug, PartialEq)]
+struct RequestEstimate {
+    requests: u64,
+    // Set for a subscription, which runs until stopped, so requests are counted per rooted slot it follows
+    per_slot: bool,
+    // Requests that depend on what the scan finds or how the RPC responds, so aren't counted
+    excluded: Vec<String>,
+}
 
-            // Set swapper from the account with the decrease
-            if let Some(pre_balance) = pre_map.get(&dec_idx) {
-                if let Some(owner) = &pre_balance.owner.as_ref().map(|s| s.as_str()) {
-                    swap_info.swapper = owner.to_string();
-                }
+impl RequestEstimate {
+    // Explains what the count covers and how long the requests take at the rate limit, printed after it
+    fn notes(&self, options: &CliOptions) -> Vec<String> {
+        let requests_per_second: f64 = options.requests_per_second.unwrap_or(DEFAULT_REQUESTS_PER_SECOND);
+        let mut notes: Vec<String> = Vec::new();
+
+        if self.per_slot {
+            notes.push("Requests per rooted slot followed, since a subscription runs until stopped".to_string());
+        } else if let Some(sampling) = &options.analysis.sampling {
+            notes.push(format!(
+                "Blocks are sampled at {:.1}%, so the count is scaled to match",
+                sampling.rate * 100.0
+            ));
+        }
+        notes.push(format!(
+            "At {} requests per second, these take at least {:.1}s; rate-limited requests wait their turn rather than \
+             being retried, so the limit adds time but no requests",
+            requests_per_second,
+            self.requests as f64 / requests_per_second,
+        ));
+        notes.extend(
+            self.excluded
+                .iter()
+                .map(|excluded| format!("Not counted: {}", excluded)),
+        );
+
+        notes
+    }
+}
+
+// Number of blocks a scan over the given number of slots expects to fetch, after sampling
+fn sampled_block_count(num_blocks: u64, options: &CliOptions) -> u64 {
+    match &options.analysis.sampling {
+        Some(sampling) => (num_blocks as f64 * sampling.rate).ceil() as u64,
+        None => num_blocks,
+    }
+}
+
+// Estimates the requests of the scan the options describe, for --estimate
+// Mode-specific extras that depend on the blocks themselves, like empty-block retries and symbol lookups, are listed
+// as exclusions rather than guessed at
+fn estimate_scan_requests(options: &CliOptions) -> std::result::Result<RequestEstimate, String> {
+    let avg_mints_per_block: u64 = if options.analysis.resolve_decimals {
+        options.avg_mints_per_block.unwrap_or(DEFAULT_ESTIMATE_MINTS_PER_BLOCK)
+    } else {
+        0
+    };
+    let mut excluded: Vec<String> = Vec::new();
+
+    if options.selftest || options.replay_path.is_some() {
+        return Err("--estimate doesn't apply to offline runs, which make no RPC requests".to_string());
+    }
+
+    if options.analysis.resolve_decimals {
+        excluded.push("fewer decimals lookups for mints cached by an earlier block or run".to_string());
+    }
+
+    // A transaction's block is found with one getTransaction call, and recent blocks with one getSlot call; a
+    // subscription is fed rooted slots over the websocket and fetches each one's block
+    let (requests, per_slot): (u64, bool) = if options.subscribe {
+        excluded.push(format!(
+            "the blocks of up to {} slots missed while reconnecting the websocket",
+            MAX_BACKFILL_SLOTS
+        ));
+        (estimate_request_count(1, avg_mints_per_block), true)
+    } else {
+        match (&options.target, options.slot_range) {
+            (Some(_), _) if options.classify_only => (1, false),
+            (Some(_), _) => (1 + estimate_request_count(1, avg_mints_per_block), false),
+            (None, Some((start_slot, end_slot))) if options.dump_block_path.is_some() => {
+                (end_slot - start_slot + 1, false)
+            }
+            (None, Some((start_slot, end_slot))) => {
+                let fetched_blocks: u64 = sampled_block_count(end_slot - start_slot + 1, options);
+                (estimate_request_count(fetched_blocks, avg_mints_per_block), false)
             }
+            (None, None) => {
+                let fetched_blocks: u64 =
+                    sampled_block_count(options.recent_blocks.unwrap_or(DEFAULT_RECENT_BLOCKS), options);
+
+                excluded.push(format!(
+                    "up to {} retries of each block within {} slots of the tip that comes back empty",
+                    options.empty_block_retry.attempts, options.empty_block_retry.recent_slot_window
+                ));
+                if options.reorg_check.is_some() {
+                    excluded.push(
+                        "re-verifying each block with patterns once it's finalized, and the getSlot polls waiting \
+                         for it"
+                            .to_string(),
+                    );
+                }
 
-            // Filter out interactions with the holding account
-            if swap_info.swapper == "DKLvbSugkGMf4PBMakfHW9BdvcYj7Y7FRbsiL6v5DRy2" {
-                println!("Filtered out swap involving holding account: {}", swap_info.swapper);
-                return None;
+                (1 + estimate_request_count(fetched_blocks, avg_mints_per_block), false)
             }
+        }
+    };
+
+    if !options.no_symbols && !options.classify_only && options.dump_block_path.is_none() {
+        excluded.push(format!(
+            "one getAssetBatch per {} tokens with detected patterns, to look up their symbols",
+            MAX_ASSET_BATCH
+        ));
+    }
 
-            return Some(swap_info);
+    Ok(RequestEstimate {
+        requests,
+        per_slot,
+        excluded,
+    })
+}
+
+// Aggregate statistics over all blocks analyzed in a run
+#[derive(Default)]
+struct RunSummary {
+    blocks_analyzed: usize,
+    // Blocks that produced at least one completed pattern
+    blocks_with_patterns: usize,
+    // Vote/non-vote totals across blocks, if composition was counted
+    composition: Option<BlockComposition>,
+    // Attempted/failed swap totals across blocks, if failed transactions were tracked
+    swap_attempts: Option<SwapAttempts>,
+    patterns_found: usize,
+    // Patterns from confirmed blocks that were later reorged out
+    patterns_retracted: usize,
+    // Set when only a sample of slots was analyzed, so counts are scaled up as estimates
+    sampling: Option<SlotSampling>,
+    // Patterns left unfinished at the end of each block, if they were being explained
+    incomplete: Vec<IncompletePattern>,
+    // Target-program discriminators missing from the instruction map, with how often each was seen
+    unknown_discriminators: HashMap<String, usize>,
+}
+
+impl RunSummary {
+    // Records a block's analysis and hands back its patterns
+    fn record_block(&mut self, analysis: BlockAnalysis) -> Vec<Pattern> {
+        self.blocks_analyzed += 1;
+
+        if !analysis.patterns.is_empty() {
+            self.blocks_with_patterns += 1;
+        }
+        self.patterns_found += analysis.patterns.len();
+        self.incomplete.extend(analysis.incomplete);
+
+        if let Some(composition) = analysis.composition {
+            let total: &mut BlockComposition = self.composition.get_or_insert_with(BlockComposition::default);
+            total.vote_txs += composition.vote_txs;
+            total.non_vote_txs += composition.non_vote_txs;
         }
+
+        if let Some(attempts) = analysis.swap_attempts {
+            let total: &mut SwapAttempts = self.swap_attempts.get_or_insert_with(SwapAttempts::default);
+            total.attempted += attempts.attempted;
+            total.failed += attempts.failed;
+        }
+
+        for (discriminator, count) in analysis.unknown_discriminators {
+            *self.unknown_discriminators.entry(discriminator).or_insert(0) += count;
+        }
+
+        analysis.patterns
     }
 
-    None
+    // Returns the fraction of analyzed blocks that contained a sandwich, if any blocks were analyzed
+    fn sandwich_block_ratio(&self) -> Option<f64> {
+        if self.blocks_analyzed == 0 {
+            return None;
+        }
+
+        Some(self.blocks_with_patterns as f64 / self.blocks_analyzed as f64)
+    }
+
+    fn to_summary(&self) -> String {
+        let summary: String = match self.sandwich_block_ratio() {
+            Some(ratio) => format!(
+                "Sandwiches found in {} of {} blocks ({:.2}%)",
+                self.blocks_with_patterns,
+                self.blocks_analyzed,
+                ratio * 100.0
+            ),
+            None => "No blocks analyzed".to_string(),
+        };
+
+        let summary: String = match &self.sampling {
+            Some(sampling) => format!(
+                "{}\nEstimated ~{:.0} sandwich patterns across all slots (sampled {:.2}% of slots, seed {})",
+                summary,
+                sampling.estimate(self.patterns_found),
+                sampling.rate * 100.0,
+                sampling.seed
+            ),
+            None => summary,
+        };
+
+        let summary: String = if self.patterns_retracted > 0 {
+            format!(
+                "{}\n{} patterns retracted after their confirmed block was reorged out",
+                summary, self.patterns_retracted
+            )
+        } else {
+            summary
+        };
+
+        let summary: String = match &self.composition {
+            Some(composition) => format!("{}\n{}", summary, composition.to_summary()),
+            None => summary,
+        };
+
+        let summary: String = match &self.swap_attempts {
+            Some(attempts) => format!("{}\n{}", summary, attempts.to_summary()),
+            None => summary,
+        };
+
+        if self.unknown_discriminators.is_empty() {
+            return summary;
+        }
+
+        // Most frequent first, so the likeliest new instruction types stand out
+        let mut unknown: Vec<(&String, &usize)> = self.unknown_discriminators.iter().collect();
+        unknown.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+        let unknown_lines: String = unknown
+            .iter()
+            .map(|(discriminator, count)| format!("\n- {}: {}", discriminator, count))
+            .collect();
+
+        format!("{}\nUnknown target-program discriminators:{}", summary, unknown_lines)
+    }
 }
 
-// Fetches num_blocks recent blocks
-async fn get_recent_blocks(helius: &Helius, num_blocks: u64) -> Result<Vec<UiConfirmedBlock>> {
-    let current_slot: u64 = helius.connection().get_slot()?;
-    let mut blocks: Vec<UiConfirmedBlock> = Vec::new();
+// Prints what was found in a single block as it's analyzed
+fn report_block(analysis: &BlockAnalysis, options: &CliOptions) {
+    if options.output.is_machine_readable() {
+        return;
+    }
 
-    let config: RpcBlockConfig = RpcBlockConfig {
-        commitment: None,
-        max_supported_transaction_version: Some(0),
-        transaction_details: Some(TransactionDetails::Full),
-        rewards: Some(true),
-        encoding: Some(UiTransactionEncoding::Base64),
+    if let Some(composition) = &analysis.composition {
+        println!("{}", composition.to_summary());
+    }
+
+    // Throttled output summarizes patterns periodically instead
+    if options.throttle.is_none() && !analysis.patterns.is_empty() {
+        println!(
+            "Found {} sandwich patterns at block height {}",
+            analysis.patterns.len(),
+            analysis.block_height.unwrap_or(0)
+        );
+    }
+}
+
+// Prints the detected patterns, limited to the most profitable ones if --top-n was given
+fn report_patterns(patterns: &[Pattern], summary: &RunSummary, options: &CliOptions) {
+    // Machine-readable output is the patterns alone, so it can be piped straight into another tool
+    if options.output.is_machine_readable() {
+        let reported: Vec<&Pattern> = match options.top_n {
+            Some(n) => top_by_sol_profit(patterns, n),
+            None => patterns.iter().collect(),
+        };
+
+        for (i, pattern) in reported.into_iter().enumerate() {
+            print_pattern(pattern, options, i == 0);
+        }
+
+        return;
+    }
+
+    if patterns.is_empty() {
+        println!("\nNo sandwich patterns found");
+        println!("{}", summary.to_summary());
+        return;
+    }
+
+    println!("\n=== Found {} sandwich patterns ===\n", patterns.len());
+
+    // Throttled output keeps the console to summaries; full detail goes to file sinks
+    if options.throttle.is_some() {
+        println!("{}", summary.to_summary());
+        return;
+    }
+
+    let reported: Vec<&Pattern> = match options.top_n {
+        Some(n) => {
+            let top: Vec<&Pattern> = top_by_sol_profit(patterns, n);
+            println!("Reporting the top {} by SOL profit\n", top.len());
+            top
+        }
+        None => patterns.iter().collect(),
     };
 
-    for slot in (current_slot.saturating_sub(num_blocks)..current_slot).rev() {
-        match helius.connection().get_block_with_config(slot, config.clone()) {
-            Ok(block) => {
-                blocks.push(block);
+    for (i, pattern) in reported.into_iter().enumerate() {
+        print_pattern(pattern, options, i == 0);
+    }
+
+    println!("{}", summary.to_summary());
+}
+
+// Prints a pattern in the --output format
+// The CSV header is printed before the first pattern
+fn print_pattern(pattern: &Pattern, options: &CliOptions, first: bool) {
+    match options.output {
+        OutputFormat::Text => {
+            println!("{}", pattern.to_summary_in(options.amount_unit));
+            println!("---");
+        }
+        OutputFormat::Json => {
+            if let Err(e) = write_ndjson(pattern, options.amount_unit, io::stdout().lock()) {
+                eprintln!(
+                    "Failed to print pattern {} as JSON: {}",
+                    pattern.create_tx().signature,
+                    e
+                );
             }
-            Err(e) => {
-                eprintln!("Failed to fetch block at slot {}: {}", slot, e);
-                continue;
+        }
+        OutputFormat::Csv => {
+            if let Err(e) = write_pattern_rows([pattern], std::io::stdout(), first) {
+                eprintln!(
+                    "Failed to write pattern {} as CSV: {}",
+                    pattern.create_tx().signature,
+                    e
+                );
             }
         }
     }
+}
 
-    Ok(blocks)
+// Writes a pattern as a single line of JSON, flushed right away so streaming consumers like jq see it as soon as
+// it's detected
+fn write_ndjson<W: Write>(pattern: &Pattern, unit: AmountUnit, mut writer: W) -> io::Result<()> {
+    let json: String = pattern.to_json_in(unit)?;
+
+    writeln!(writer, "{}", json)?;
+    writer.flush()
 }
 
-// Checks whether a given transaction was successful
-fn is_transaction_successful(meta: &UiTransactionStatusMeta) -> bool {
-    meta.err.is_none()
+// Prints why each residual pattern didn't complete, if --explain-incomplete was given
+fn report_incomplete(summary: &RunSummary, options: &CliOptions) {
+    if !options.analysis.explain_incomplete {
+        return;
+    }
+
+    if summary.incomplete.is_empty() {
+        println!("\nNo incomplete patterns left over");
+        return;
+    }
+
+    println!("\n=== {} incomplete patterns ===\n", summary.incomplete.len());
+
+    for incomplete in &summary.incomplete {
+        println!("{}", incomplete.to_summary());
+        println!("---");
+    }
 }
 
-// Checks if an address is a Jito tip address
-fn is_jito_tip_address(addr: &str) -> bool {
-    JITO_TIP_ADDRESSES.contains(&addr)
+// Prints sandwiches grouped by (attacker, pool), if --campaigns was given
+fn report_campaigns(patterns: &[Pattern], options: &CliOptions) {
+    if !options.campaigns {
+        return;
+    }
+
+    let campaigns: Vec<Campaign> = aggregate_campaigns(patterns);
+    println!("\n=== {} campaigns ===\n", campaigns.len());
+
+    for campaign in &campaigns {
+        println!("{}", campaign.to_summary());
+        println!("---");
+    }
 }
 
-// Checks Jito tups by comparing pre- and post-balances
-fn detect_jito_tip(account_keys: &[Pubkey], pre_balances: &[u64], post_balances: &[u64]) -> u64 {
-    let mut total_tip: u64 = 0;
+// Prints the most profitable attackers, if --leaderboard was given
+fn report_leaderboard(patterns: &[Pattern], options: &CliOptions) {
+    let n: usize = match options.leaderboard {
+        Some(n) => n,
+        None => return,
+    };
+
+    let leaders: Vec<AttackerStats> = top_attackers_by_profit(patterns, n);
+    println!("\n=== Top {} attackers by SOL profit ===\n", leaders.len());
 
-    for (i, key) in account_keys.iter().enumerate() {
-        let diff: u64 = post_balances[i].saturating_sub(pre_balances[i]);
+    for attacker in &leaders {
+        println!("{}", attacker.to_summary());
+        println!("---");
+    }
+}
+
+// Prints attackers ranked by Jito tips paid per SOL of profit, if --tip-efficiency was given
+fn report_tip_efficiency(patterns: &[Pattern], options: &CliOptions) {
+    if !options.tip_efficiency {
+        return;
+    }
+
+    let mut stats: Vec<AttackerStats> = aggregate_attackers(patterns);
+    rank_by_tip_efficiency(&mut stats);
+
+    println!("\n=== Tip efficiency of {} attackers ===\n", stats.len());
+
+    for attacker in &stats {
+        let efficiency: String = match attacker.tip_efficiency() {
+            Some(ratio) => format!("{:.4} SOL tipped per SOL profit", ratio),
+            None => "n/a (no profit)".to_string(),
+        };
+
+        println!(
+            "{}{}: {} ({} patterns, {:.9} SOL profit, {:.9} SOL tips)",
+            attacker.attacker,
+            attacker
+                .attacker_label
+                .as_ref()
+                .map(|label| format!(" ({})", label))
+                .unwrap_or_default(),
+            efficiency,
+            attacker.pattern_count,
+            attacker.total_sol_profit,
+            attacker.total_tips_lamports as f64 / 1e9,
+        );
+    }
+}
 
-        if diff >= MIN_JITO_TIP && is_jito_tip_address(&key.to_string()) {
-            total_tip += diff;
+// Command-line options
+#[derive(Default)]
+struct CliOptions {
+    // Transaction URL or signature whose block should be analyzed
+    target: Option<String>,
+    // Inclusive range of slots to analyze instead of recent blocks
+    slot_range: Option<(u64, u64)>,
+    // How many recent blocks to analyze, if not the default
+    recent_blocks: Option<u64>,
+    // Cluster to connect to, overriding HELIUS_CLUSTER
+    cluster: Option<Cluster>,
+    // Unit used for token amounts in reported patterns
+    amount_unit: AmountUnit,
+    // How to retry recent blocks that come back without transactions
+    empty_block_retry: EmptyBlockRetry,
+    // How many recent blocks to fetch at once, if not the default
+    fetch_concurrency: Option<usize>,
+    // Most RPC requests made per second, if not the default
+    requests_per_second: Option<f64>,
+    // Only report this many of the most profitable patterns
+    top_n: Option<usize>,
+    // How each reported pattern is printed
+    output: OutputFormat,
+    // JSON file mapping known bot addresses to labels
+    bot_registry: Option<PathBuf>,
+    // Where to get the SOL/USD price for valuing profits
+    sol_price: Option<Box<dyn SolPriceSource>>,
+    // Group patterns by attacker and pool at the end of the run
+    campaigns: bool,
+    // Rank attackers by Jito tips paid per SOL of profit at the end of the run
+    tip_efficiency: bool,
+    // Rank the n most profitable attackers at the end of the run
+    leaderboard: Option<usize>,
+    // Analyze confirmed rather than finalized blocks, re-verifying them once finalized
+    reorg_check: Option<ReorgCheck>,
+    // Summarize patterns on the console at most once per interval instead of printing each one
+    throttle: Option<Duration>,
+    // Run the detector against archived blocks with known sandwiches instead of recent blocks
+    selftest: bool,
+    // Analyze blocks live as they're finalized instead of recent blocks
+    subscribe: bool,
+    // Print how many RPC requests the scan would make and exit without running it
+    estimate: bool,
+    // Classify only the target transaction, printing its legs, instead of analyzing its block
+    classify_only: bool,
+    // Saved getBlock response to analyze offline instead of fetching blocks
+    replay_path: Option<PathBuf>,
+    // File to save the --slot block to for later replay, instead of analyzing it
+    dump_block_path: Option<PathBuf>,
+    // Distinct mints per block assumed by --estimate, if not the default
+    avg_mints_per_block: Option<u64>,
+    // Directory of self-test cases and archived blocks, if not the bundled one
+    fixtures_dir: Option<PathBuf>,
+    // CSV file to write detected patterns to
+    csv_path: Option<PathBuf>,
+    // Append to the CSV file instead of overwriting it
+    csv_append: bool,
+    // Parquet file to write detected patterns to
+    #[cfg(feature = "parquet")]
+    parquet_path: Option<PathBuf>,
+    // Continue the latest range scan checkpointed in SANDWICH_DB_PATH instead of starting a new one
+    #[cfg(feature = "sqlite")]
+    resume: bool,
+    // Skip looking up token symbols, showing mints by address only
+    no_symbols: bool,
+    // Address to serve Prometheus metrics on while running
+    metrics_addr: Option<SocketAddr>,
+    analysis: AnalysisOptions,
+}
+
+// How reported patterns are printed to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum OutputFormat {
+    // A human-readable summary per pattern
+    #[default]
+    Text,
+    // One JSON object per line (NDJSON), with nothing else on stdout
+    Json,
+    // One CSV row per pattern, after a header
+    Csv,
+}
+
+impl OutputFormat {
+    // Whether stdout is reserved for the patterns, with progress and summaries left out or sent to stderr
+    fn is_machine_readable(self) -> bool {
+        self != OutputFormat::Text
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" | "ndjson" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!(
+                "Unknown output format '{}', expected 'text', 'json', or 'csv'",
+                s
+            )),
         }
     }
+}
 
-    total_tip
+// Settings for analyzing confirmed blocks and retracting patterns whose block is later reorged out
+struct ReorgCheck {
+    // Slots the finalized slot must be past a block before it's re-verified
+    depth: u64,
+    poll_interval: Duration,
+    // Stop waiting for finalization after this long, keeping the block's patterns
+    timeout: Duration,
 }
 
-// Checks non-vote transactions in a block for potential sandwich attacks
-pub async fn analyze_non_vote_transactions(helius: &Helius, block: &UiConfirmedBlock) -> Result<()> {
-    if let Some(transactions) = &block.transactions {
-        let mut pattern_tracker: PatternTracker = PatternTracker::new();
+impl Default for ReorgCheck {
+    fn default() -> Self {
+        Self {
+            depth: 0,
+            poll_interval: Duration::from_millis(400),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
 
-        // Filter for non-vote transactions
-        let non_vote_txs: Vec<&EncodedTransactionWithStatusMeta> = transactions
-            .iter()
-            .filter(|tx| {
-                if let Some(meta) = &tx.meta {
-                    if !is_transaction_successful(meta) {
-                        return false;
-                    }
+// Retry policy for freshly produced blocks whose transactions aren't available yet
+// This is separate from fetch errors, which are skipped, and only applies near the tip so
+// historical slots that are genuinely empty aren't refetched
+struct EmptyBlockRetry {
+    attempts: u32,
+    delay: Duration,
+    // Only slots at most this far behind the current slot are retried
+    recent_slot_window: u64,
+}
 
-                    let logs: Option<Vec<String>> = meta.log_messages.clone().into();
-                    if let Some(logs) = logs {
-                        let is_vote = logs
-                            .iter()
-                            .any(|log| log.contains("Vote111111111111111111111111111111111111111"));
-                        let has_target = logs.iter().any(|log| log.contains(TARGET_PROGRAM));
-                        !is_vote && has_target
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
-            })
-            .collect();
+impl Default for EmptyBlockRetry {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            delay: Duration::from_millis(400),
+            recent_slot_window: 32,
+        }
+    }
+}
 
-        let block_height: u64 = block.block_height.unwrap_or(0);
-        let block_time: Option<u64> = block.block_time.map(|x| x as u64);
+// Command-line arguments, parsed by clap; cli_options turns them into CliOptions and checks how they combine
+// Field doc comments are the --help text
+#[derive(Parser)]
+#[command(version, about = "Detects sandwich attacks by a known Solana MEV bot")]
+struct Args {
+    /// Transaction signature or explorer URL whose block to analyze, or `selftest` to check the detector against
+    /// the bundled fixtures
+    target: Option<String>,
+    /// Unit for reported token amounts: 'base' or 'ui'
+    #[arg(long)]
+    amount_unit: Option<AmountUnit>,
+    /// Times to refetch a recent block that came back without transactions
+    #[arg(long)]
+    empty_retries: Option<u32>,
+    /// Delay between refetches of an empty recent block, in milliseconds
+    #[arg(long)]
+    empty_retry_delay_ms: Option<u64>,
+    /// Inclusive slot range to analyze, as <start>:<end>
+    #[arg(long, value_name = "START:END", value_parser = parse_slot_range, conflicts_with_all = ["range", "slot"])]
+    slot_range: Option<(u64, u64)>,
+    /// Slot range to analyze in Rust syntax: <start>..<end> excludes the end, <start>..=<end> includes it
+    #[arg(long, value_name = "START..END", value_parser = parse_range, conflicts_with = "slot")]
+    range: Option<(u64, u64)>,
+    /// Single slot to analyze
+    #[arg(long)]
+    slot: Option<u64>,
+    /// How many recent blocks to analyze
+    #[arg(long, value_name = "BLOCKS")]
+    recent: Option<u64>,
+    /// Print how many RPC requests the scan would make and exit without running it
+    #[arg(long)]
+    estimate: bool,
+    /// Classify only the target transaction, printing its legs, instead of analyzing its block
+    #[arg(long)]
+    classify_only: bool,
+    /// Analyze a getBlock response saved with --dump-block, offline
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// Save the --slot block to a file for later replay instead of analyzing it
+    #[arg(long, value_name = "FILE")]
+    dump_block: Option<PathBuf>,
+    /// Distinct mints per block assumed by --estimate
+    #[arg(long)]
+    avg_mints: Option<u64>,
+    /// Cluster to connect to: 'mainnet', 'staked', or 'devnet', overriding HELIUS_CLUSTER
+    #[arg(long, value_parser = parse_cluster)]
+    cluster: Option<Cluster>,
+    /// How each pattern is printed: 'text', 'json', or 'csv'
+    #[arg(long)]
+    output: Option<OutputFormat>,
+    /// Same as --output json
+    #[arg(long, conflicts_with = "output")]
+    json: bool,
+    /// How many recent blocks to fetch at once
+    #[arg(long)]
+    fetch_concurrency: Option<usize>,
+    /// Most RPC requests made per second
+    #[arg(long)]
+    rps: Option<f64>,
+    /// Only report this many of the most profitable patterns
+    #[arg(long)]
+    top_n: Option<usize>,
+    /// Analyze only this fraction of slots, chosen deterministically
+    #[arg(long)]
+    sample_rate: Option<f64>,
+    /// Seed choosing the slots sampled by --sample-rate
+    #[arg(long, default_value_t = 0)]
+    sample_seed: u64,
+    /// Fixed SOL/USD price used to value profits
+    #[arg(long)]
+    sol_price_usd: Option<f64>,
+    /// JSON file mapping known bot addresses to labels
+    #[arg(long, value_name = "FILE")]
+    bot_registry: Option<PathBuf>,
+    /// CSV file to write detected patterns to
+    #[arg(long, value_name = "FILE")]
+    csv: Option<PathBuf>,
+    /// Append to the --csv file instead of overwriting it
+    #[arg(long)]
+    csv_append: bool,
+    /// Address to serve Prometheus metrics on while running
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Parquet file to write detected patterns to
+    #[cfg(feature = "parquet")]
+    #[arg(long, value_name = "FILE")]
+    parquet: Option<PathBuf>,
+    /// Analyze blocks live as they're finalized
+    #[arg(long)]
+    subscribe: bool,
+    /// Group patterns by attacker and pool at the end of the run
+    #[arg(long)]
+    campaigns: bool,
+    /// Rank attackers by Jito tips paid per SOL of profit at the end of the run
+    #[arg(long)]
+    tip_efficiency: bool,
+    /// Rank this many of the most profitable attackers at the end of the run
+    #[arg(long, value_name = "N")]
+    leaderboard: Option<usize>,
+    /// Directory of self-test cases and archived blocks to use instead of the bundled ones
+    #[arg(long, value_name = "DIR")]
+    fixtures: Option<PathBuf>,
+    /// Summarize patterns at most once per this many seconds instead of printing each one
+    #[arg(long)]
+    throttle_secs: Option<u64>,
+    /// Analyze confirmed rather than finalized blocks, retracting patterns whose block is reorged out
+    #[arg(long)]
+    confirmed: bool,
+    /// Slots past a confirmed block the finalized slot must be before it's re-verified; implies --confirmed
+    #[arg(long)]
+    reorg_depth: Option<u64>,
+    /// Most slots a sandwich's swap-out may land after its create
+    #[arg(long)]
+    max_block_gap: Option<u64>,
+    /// Most seconds between a sandwich's create and swap-out
+    #[arg(long)]
+    max_time_span_secs: Option<u64>,
+    /// Skip transactions paying less than this priority fee, in lamports
+    #[arg(long)]
+    min_priority_fee: Option<u64>,
+    /// Skip transactions consuming fewer compute units than this
+    #[arg(long)]
+    min_compute_units: Option<u64>,
+    /// Highest transaction version requested from the RPC
+    #[arg(long)]
+    max_transaction_version: Option<u8>,
+    /// Drop patterns whose token decimals couldn't be fetched
+    #[arg(long)]
+    strict_decimals: bool,
+    /// Skip decimals lookups, leaving amounts in base units
+    #[arg(long)]
+    no_decimals: bool,
+    /// Skip token symbol lookups, showing mints by address only
+    #[arg(long)]
+    no_symbols: bool,
+    /// Continue the latest range scan checkpointed in SANDWICH_DB_PATH
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    resume: bool,
+    /// Drop round-trips with no victim between the legs
+    #[arg(long)]
+    exclude_arb: bool,
+    /// Count vote and non-vote transactions per block
+    #[arg(long)]
+    count_votes: bool,
+    /// Print every classified transaction as a JSON line
+    #[arg(long)]
+    emit_classified: bool,
+    /// Which balance changes SOL profit is computed from: 'separate' or 'unified'
+    #[arg(long)]
+    sol_accounting: Option<SolAccounting>,
+    /// Only classify transactions with at least this many distinct known instructions
+    #[arg(long)]
+    min_known_instructions: Option<usize>,
+    /// Skip swap legs that moved no tokens
+    #[arg(long)]
+    require_token_movement: bool,
+    /// Swapper whose swaps aren't attacks; may be repeated
+    #[arg(long, value_name = "ADDRESS")]
+    ignore_swapper: Vec<Pubkey>,
+    /// Program whose instructions are classified
+    #[arg(long, value_name = "ADDRESS")]
+    target_program: Option<Pubkey>,
+    /// Map an 8-byte hex discriminator to a lifecycle instruction, as <discriminator>=<name>; may be repeated
+    #[arg(long, value_name = "DISCRIMINATOR=NAME")]
+    instruction: Vec<String>,
+    /// JSON file of Jito tip accounts and the minimum tip
+    #[arg(long, value_name = "FILE")]
+    jito_tips: Option<PathBuf>,
+    /// Smallest transfer to a tip account counted as a Jito tip, in lamports
+    #[arg(long)]
+    min_jito_tip: Option<u64>,
+    /// Report the legs left without a matching swap-out at the end of each block
+    #[arg(long)]
+    explain_incomplete: bool,
+    /// Also classify failed transactions
+    #[arg(long)]
+    track_failed: bool,
+    /// Where to read decimals from in mint account data
+    #[arg(long)]
+    mint_layout: Option<MintLayout>,
+}
 
-        for tx in non_vote_txs {
-            let mut classified_txs: Vec<ClassifiedTransaction> = find_known_instruction(tx, block_height, block_time);
+// Parses a --slot-range value, <start>:<end>
+fn parse_slot_range(value: &str) -> std::result::Result<(u64, u64), String> {
+    let range: Option<(u64, u64)> = value
+        .split_once(':')
+        .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)));
 
-            for classified_tx in &mut classified_txs {
-                if !classified_tx.from_mint.is_empty() {
-                    match get_token_decimals(helius, &classified_tx.from_mint).await {
-                        Ok(decimals) => {
-                            classified_tx.decimals = decimals;
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to fetch decimals for token {}: {}", classified_tx.from_mint, e);
-                        }
-                    }
+    match range {
+        Some((start, end)) if start <= end => Ok((start, end)),
+        _ => Err("expected <start>:<end> with start <= end".to_string()),
+    }
+}
+
+// Parses a --range value in Rust range syntax: START..END excludes END, START..=END includes it
+fn parse_range(value: &str) -> std::result::Result<(u64, u64), String> {
+    let range: Option<(u64, u64)> = match value.split_once("..=") {
+        Some((start, end)) => start.parse().ok().zip(end.parse().ok()),
+        None => value.split_once("..").and_then(|(start, end)| {
+            let end: u64 = end.parse::<u64>().ok()?.checked_sub(1)?;
+            Some((start.parse().ok()?, end))
+        }),
+    };
+
+    match range {
+        Some((start, end)) if start <= end => Ok((start, end)),
+        _ => Err("expected <start>..<end> or <start>..=<end> covering at least one slot".to_string()),
+    }
+}
+
+// Turns parsed arguments into options, checking the combinations clap can't express
+fn cli_options(args: Args) -> std::result::Result<CliOptions, String> {
+    let mut options: CliOptions = CliOptions {
+        slot_range: args.slot_range.or(args.range).or(args.slot.map(|slot| (slot, slot))),
+        recent_blocks: args.recent,
+        cluster: args.cluster,
+        amount_unit: args.amount_unit.unwrap_or_default(),
+        fetch_concurrency: args.fetch_concurrency,
+        requests_per_second: args.rps,
+        top_n: args.top_n,
+        output: if args.json {
+            OutputFormat::Json
+        } else {
+            args.output.unwrap_or_default()
+        },
+        bot_registry: args.bot_registry,
+        campaigns: args.campaigns,
+        tip_efficiency: args.tip_efficiency,
+        leaderboard: args.leaderboard,
+        throttle: args.throttle_secs.map(Duration::from_secs),
+        subscribe: args.subscribe,
+        estimate: args.estimate,
+        classify_only: args.classify_only,
+        replay_path: args.replay,
+        dump_block_path: args.dump_block,
+        avg_mints_per_block: args.avg_mints,
+        fixtures_dir: args.fixtures,
+        csv_path: args.csv,
+        csv_append: args.csv_append,
+        #[cfg(feature = "parquet")]
+        parquet_path: args.parquet,
+        #[cfg(feature = "sqlite")]
+        resume: args.resume,
+        no_symbols: args.no_symbols,
+        metrics_addr: args.metrics_addr,
+        ..CliOptions::default()
+    };
+
+    match args.target {
+        Some(target) if target == "selftest" => {
+            // Expectations don't depend on token decimals, so the self-test can run from archived blocks
+            options.selftest = true;
+            options.analysis.resolve_decimals = false;
+        }
+        target => options.target = target,
+    }
+
+    if let Some(attempts) = args.empty_retries {
+        options.empty_block_retry.attempts = attempts;
+    }
+    if let Some(delay_ms) = args.empty_retry_delay_ms {
+        options.empty_block_retry.delay = Duration::from_millis(delay_ms);
+    }
+
+    if args.confirmed || args.reorg_depth.is_some() {
+        options.reorg_check = Some(ReorgCheck {
+            depth: args.reorg_depth.unwrap_or(0),
+            ..ReorgCheck::default()
+        });
+    }
+
+    if let Some(price) = args.sol_price_usd {
+        if !price.is_finite() || price <= 0.0 {
+            return Err(format!(
+                "Invalid value '{}' for --sol-price-usd, expected a positive price",
+                price
+            ));
+        }
+        options.sol_price = Some(Box::new(FixedSolPrice(price)));
+    }
+
+    let analysis: &mut AnalysisOptions = &mut options.analysis;
+    // Decimals can't be looked up offline
+    if args.no_decimals || options.replay_path.is_some() {
+        analysis.resolve_decimals = false;
+    }
+    if let Some(mint_layout) = args.mint_layout {
+        analysis.mint_layout = mint_layout;
+    }
+    if let Some(max_block_gap) = args.max_block_gap {
+        analysis.max_block_gap = max_block_gap;
+    }
+    if let Some(max_time_span_secs) = args.max_time_span_secs {
+        analysis.max_time_span_secs = max_time_span_secs;
+    }
+    if let Some(min_priority_fee) = args.min_priority_fee {
+        analysis.min_priority_fee = min_priority_fee;
+    }
+    if let Some(min_compute_units) = args.min_compute_units {
+        analysis.min_compute_units = min_compute_units;
+    }
+    if let Some(max_transaction_version) = args.max_transaction_version {
+        analysis.max_transaction_version = max_transaction_version;
+    }
+    if let Some(sol_accounting) = args.sol_accounting {
+        analysis.sol_accounting = sol_accounting;
+    }
+    analysis.strict_decimals = args.strict_decimals;
+    analysis.exclude_arbitrage = args.exclude_arb;
+    analysis.count_votes = args.count_votes;
+    analysis.emit_classified = args.emit_classified;
+    analysis.explain_incomplete = args.explain_incomplete;
+    analysis.track_failed = args.track_failed;
+
+    let classifier: &mut Classifier = &mut analysis.classifier;
+    if let Some(min_known_instructions) = args.min_known_instructions {
+        classifier.min_known_instructions = min_known_instructions;
+    }
+    classifier.require_token_movement = args.require_token_movement;
+    classifier
+        .ignored_swappers
+        .extend(args.ignore_swapper.iter().map(|swapper| swapper.to_string()));
+    if let Some(program) = args.target_program {
+        classifier.target_program = program.to_string();
+    }
+    for value in &args.instruction {
+        let (discriminator, name) = value.split_once('=').ok_or(format!(
+            "Invalid value '{}' for --instruction, expected <discriminator>=<name>",
+            value
+        ))?;
+        classifier.add_instruction(discriminator, name)?;
+    }
+    // The tip accounts file comes first, so --min-jito-tip overrides its minimum
+    if let Some(path) = &args.jito_tips {
+        classifier.jito_tips = JitoTipConfig::from_json_file(path)?;
+    }
+    if let Some(min_tip) = args.min_jito_tip {
+        classifier.jito_tips.set_min_tip(min_tip);
+    }
+
+    if options.output.is_machine_readable() {
+        let reports: [(&str, bool); 5] = [
+            ("--campaigns", options.campaigns),
+            ("--tip-efficiency", options.tip_efficiency),
+            ("--leaderboard", options.leaderboard.is_some()),
+            ("--explain-incomplete", options.analysis.explain_incomplete),
+            ("--throttle-secs", options.throttle.is_some()),
+        ];
+
+        if let Some((flag, _)) = reports.iter().find(|(_, enabled)| *enabled) {
+            return Err(format!("{} can only be used with text output", flag));
+        }
+    }
+
+    let single_slot: bool = options.slot_range.is_some_and(|(start, end)| start == end);
+
+    if options.dump_block_path.is_some() && !single_slot {
+        return Err("--dump-block requires the --slot to save".to_string());
+    }
+
+    if options.replay_path.is_some() && options.slot_range.is_some() && !single_slot {
+        return Err("--replay takes a single --slot, since it analyzes one saved block".to_string());
+    }
+
+    #[cfg(feature = "sqlite")]
+    if options.resume && (options.slot_range.is_some() || options.target.is_some() || options.subscribe) {
+        return Err(
+            "--resume continues the checkpointed range, so it can't be given a slot, range, or target".to_string(),
+        );
+    }
+
+    // Only the recent-blocks scan analyzes confirmed blocks; subscriptions follow rooted slots, and ranges and
+    // transactions are fetched finalized, so none of them can be reorged out
+    if options.reorg_check.is_some() && (options.subscribe || options.slot_range.is_some() || options.target.is_some())
+    {
+        return Err("--confirmed and --reorg-depth only apply to scanning recent blocks".to_string());
+    }
+
+    if options.classify_only && options.target.is_none() {
+        return Err("--classify-only requires a transaction signature or URL".to_string());
+    }
+
+    options.analysis.classifier.validate_instruction_map()?;
+
+    if options
+        .requests_per_second
+        .is_some_and(|rps| !rps.is_finite() || rps <= 0.0)
+    {
+        return Err("--rps must be a positive number of requests per second".to_string());
+    }
+
+    if options.analysis.strict_decimals && !options.analysis.resolve_decimals {
+        return Err("--strict-decimals can't be combined with --no-decimals".to_string());
+    }
+
+    if let Some(rate) = args.sample_rate {
+        options.analysis.sampling = Some(SlotSampling::new(rate, args.sample_seed)?);
+    }
+
+    Ok(options)
+}
+
+// Maps a cluster name to the Helius cluster
+fn parse_cluster(name: &str) -> std::result::Result<Cluster, String> {
+    match name.to_lowercase().as_str() {
+        "mainnet" | "mainnet-beta" => Ok(Cluster::MainnetBeta),
+        "staked" | "staked-mainnet-beta" => Ok(Cluster::StakedMainnetBeta),
+        "devnet" => Ok(Cluster::Devnet),
+        "testnet" => Err("Helius doesn't serve testnet; use 'devnet' or 'mainnet'".to_string()),
+        _ => Err(format!(
+            "Unknown cluster '{}', expected 'mainnet', 'staked', or 'devnet'",
+            name
+        )),
+    }
+}
+
+// Creates a Helius client from HELIUS_API_KEY, returning an actionable message instead of panicking
+fn create_helius_client(cluster: Cluster) -> std::result::Result<Helius, String> {
+    let api_key: String = env::var("HELIUS_API_KEY").map_err(|_| {
+        "HELIUS_API_KEY not found. Set it in your environment or in a .env file (see .env.example)".to_string()
+    })?;
+
+    if api_key.trim().is_empty() {
+        return Err("HELIUS_API_KEY is empty. Get an API key from dev.helius.xyz and set it in .env".to_string());
+    }
+
+    Helius::new(&api_key, cluster).map_err(|e| {
+        format!(
+            "Failed to create a Helius client: {}. Check that HELIUS_API_KEY is valid and that you have network access",
+            e
+        )
+    })
+}
+
+// Fetches num_blocks recent blocks, oldest first so patterns spanning blocks can be tracked in order
+// Up to concurrency blocks are fetched at once; when sampling, only the sampled slots in that window are fetched
+async fn get_recent_blocks<B: BlockSource + Send + Sync + 'static>(
+    source: Arc<B>,
+    num_blocks: u64,
+    retry: &EmptyBlockRetry,
+    options: &AnalysisOptions,
+    commitment: CommitmentConfig,
+    concurrency: usize,
+) -> Result<Vec<(u64, UiConfirmedBlock)>> {
+    let current_slot: u64 = source.get_slot(commitment)?;
+
+    let config: RpcBlockConfig = block_config(options.max_transaction_version, Some(commitment));
+
+    let slots: Vec<u64> = (current_slot.saturating_sub(num_blocks)..current_slot)
+        .filter(|slot| options.sampling.is_none_or(|sampling| sampling.includes(*slot)))
+        .collect();
+
+    let mut blocks: Vec<(u64, UiConfirmedBlock)> = stream::iter(slots)
+        .map(|slot| fetch_recent_block(source.clone(), slot, current_slot, retry, config, options))
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(|block| async move { block })
+        .collect()
+        .await;
+
+    // Blocks complete in any order
+    blocks.sort_by_key(|(slot, _)| *slot);
+
+    Ok(blocks)
+}
+
+// Fetches one recent block, retrying it while it comes back empty
+// Returns None if the fetch failed, which is reported and skipped
+async fn fetch_recent_block<B: BlockSource + Send + Sync + 'static>(
+    source: Arc<B>,
+    slot: u64,
+    current_slot: u64,
+    retry: &EmptyBlockRetry,
+    config: RpcBlockConfig,
+    options: &AnalysisOptions,
+) -> Option<(u64, UiConfirmedBlock)> {
+    let mut block: UiConfirmedBlock = match fetch_block(source.clone(), slot, config).await {
+        Ok(block) => block,
+        Err(e) => {
+            report_block_fetch_error(slot, &e, options);
+            return None;
+        }
+    };
+
+    // Freshly produced blocks can come back before their transactions are available
+    if current_slot - slot <= retry.recent_slot_window {
+        let mut attempt: u32 = 0;
+
+        while is_block_empty(&block) && attempt < retry.attempts {
+            attempt += 1;
+            sleep(retry.delay).await;
+
+            match fetch_block(source.clone(), slot, config).await {
+                Ok(refetched) => block = refetched,
+                Err(e) => {
+                    eprintln!("Failed to refetch empty block at slot {}: {}", slot, e);
+                    METRICS.rpc_errors.inc();
+                    break;
                 }
             }
+        }
+    }
 
-            for classified_tx in classified_txs {
-                pattern_tracker.process_transaction(classified_tx);
-            }
+    Some((slot, block))
+}
+
+// Runs a blocking block fetch off the async runtime, so several can be in flight at once
+async fn fetch_block<B: BlockSource + Send + Sync + 'static>(
+    source: Arc<B>,
+    slot: u64,
+    config: RpcBlockConfig,
+) -> std::result::Result<UiConfirmedBlock, ClientError> {
+    tokio::task::spawn_blocking(move || source.get_block_with_config(slot, config))
+        .await
+        .unwrap_or_else(|e| Err(ClientErrorKind::Custom(format!("Block fetch task failed: {}", e)).into()))
+}
+
+// Whether a block analyzed at confirmed commitment survived to finalization
+enum BlockStatus {
+    Canonical,
+    // The slot was skipped or now holds a different block
+    Reorged,
+    // Finalization didn't reach the block before the timeout
+    Unfinalized,
+}
+
+// Waits until the finalized slot is the confirmation depth past the block's slot, then checks the finalized
+// block at that slot still has the blockhash that was analyzed
+async fn verify_canonical<B: BlockSource>(
+    source: &B,
+    slot: u64,
+    blockhash: &str,
+    reorg_check: &ReorgCheck,
+    max_transaction_version: u8,
+) -> Result<BlockStatus> {
+    let started: Instant = Instant::now();
+
+    while source.get_slot(CommitmentConfig::finalized())? < slot + reorg_check.depth {
+        if started.elapsed() >= reorg_check.timeout {
+            return Ok(BlockStatus::Unfinalized);
         }
 
-        let completed_patterns: &[Pattern] = pattern_tracker.get_completed_patterns();
+        sleep(reorg_check.poll_interval).await;
+    }
 
-        if !completed_patterns.is_empty() {
-            println!(
-                "\n=== Found {} sandwich patterns at block height {} ===\n",
-                completed_patterns.len(),
-                block_height
-            );
+    let config: RpcBlockConfig = RpcBlockConfig {
+        transaction_details: Some(TransactionDetails::None),
+        rewards: Some(false),
+        ..block_config(max_transaction_version, Some(CommitmentConfig::finalized()))
+    };
+
+    match source.get_block_with_config(slot, config) {
+        Ok(block) if block.blockhash == blockhash => Ok(BlockStatus::Canonical),
+        Ok(_) => Ok(BlockStatus::Reorged),
+        Err(e) if is_skipped_slot_error(&e) => Ok(BlockStatus::Reorged),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Checks whether an RPC error means the slot was skipped, so it has no block
+fn is_skipped_slot_error(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+                || *code == JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED
+    )
+}
+
+// Checks whether a fetched block came back without any transactions
+fn is_block_empty(block: &UiConfirmedBlock) -> bool {
+    block.transactions.as_ref().is_none_or(|txs| txs.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sandwich_detector::source::OfflineSource;
+    use solana_client::{rpc_config::RpcTransactionConfig, rpc_request::RpcResponseErrorData};
+    use solana_sdk::account::Account;
+    use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, EncodedTransactionWithStatusMeta};
+    use std::sync::Mutex;
+
+    // Serves each slot's scripted getBlock responses in turn, repeating the last, after the slot's delay if it has one
+    // Slots without a script fail as skipped
+    #[derive(Default)]
+    struct ScriptedSource {
+        slot: u64,
+        responses: HashMap<u64, Vec<UiConfirmedBlock>>,
+        delays: HashMap<u64, Duration>,
+        fetches: Mutex<HashMap<u64, usize>>,
+    }
 
-            for pattern in completed_patterns {
-                println!("{}", pattern.to_summary());
-                println!("---");
+    impl ScriptedSource {
+        fn fetches(&self, slot: u64) -> usize {
+            self.fetches.lock().unwrap().get(&slot).copied().unwrap_or(0)
+        }
+    }
+
+    impl BlockSource for ScriptedSource {
+        fn get_slot(&self, _commitment: CommitmentConfig) -> std::result::Result<u64, ClientError> {
+            Ok(self.slot)
+        }
+
+        fn get_block_with_config(
+            &self,
+            slot: u64,
+            _config: RpcBlockConfig,
+        ) -> std::result::Result<UiConfirmedBlock, ClientError> {
+            if let Some(delay) = self.delays.get(&slot) {
+                std::thread::sleep(*delay);
+            }
+
+            let fetch: usize = {
+                let mut fetches = self.fetches.lock().unwrap();
+                let count: &mut usize = fetches.entry(slot).or_default();
+                *count += 1;
+                *count - 1
+            };
+
+            match self.responses.get(&slot) {
+                Some(responses) => Ok(responses[fetch.min(responses.len() - 1)].clone()),
+                None => Err(RpcError::RpcResponseError {
+                    code: JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+                    message: format!("Slot {} was skipped", slot),
+                    data: RpcResponseErrorData::Empty,
+                }
+                .into()),
             }
         }
+
+        fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> std::result::Result<Vec<Option<Account>>, ClientError> {
+            Ok(vec![None; pubkeys.len()])
+        }
+
+        fn get_transaction_with_config(
+            &self,
+            signature: &Signature,
+            _config: RpcTransactionConfig,
+        ) -> std::result::Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+            Err(ClientErrorKind::Custom(format!("No transaction {}", signature)).into())
+        }
     }
 
-    Ok(())
+    // A block with the given hash, holding one placeholder transaction if populated and none otherwise
+    fn scripted_block(blockhash: &str, populated: bool) -> UiConfirmedBlock {
+        let transactions: Vec<EncodedTransactionWithStatusMeta> = if populated {
+            vec![serde_json::from_value(serde_json::json!({
+                "transaction": ["", "base64"],
+                "meta": null,
+            }))
+            .unwrap()]
+        } else {
+            Vec::new()
+        };
+
+        UiConfirmedBlock {
+            previous_blockhash: String::new(),
+            blockhash: blockhash.to_string(),
+            parent_slot: 0,
+            transactions: Some(transactions),
+            signatures: None,
+            rewards: None,
+            num_reward_partitions: None,
+            block_time: None,
+            block_height: None,
+        }
+    }
+
+    fn no_delay_retry() -> EmptyBlockRetry {
+        EmptyBlockRetry {
+            delay: Duration::ZERO,
+            ..EmptyBlockRetry::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_fresh_block_that_comes_back_empty_is_refetched_until_populated() {
+        let source: Arc<ScriptedSource> = Arc::new(ScriptedSource {
+            slot: 100,
+            responses: HashMap::from([
+                (99, vec![scripted_block("fresh", false), scripted_block("fresh", true)]),
+                (10, vec![scripted_block("old", false), scripted_block("old", true)]),
+            ]),
+            ..ScriptedSource::default()
+        });
+        let config: RpcBlockConfig = block_config(0, None);
+        let options: AnalysisOptions = AnalysisOptions::default();
+
+        let (slot, block) = fetch_recent_block(source.clone(), 99, 100, &no_delay_retry(), config, &options)
+            .await
+            .unwrap();
+        assert_eq!(slot, 99);
+        assert!(!is_block_empty(&block));
+        assert_eq!(source.fetches(99), 2);
+
+        // A slot further back than the recent window is genuinely empty, so it isn't refetched
+        let (_, block) = fetch_recent_block(source.clone(), 10, 100, &no_delay_retry(), config, &options)
+            .await
+            .unwrap();
+        assert!(is_block_empty(&block));
+        assert_eq!(source.fetches(10), 1);
+    }
+
+    #[tokio::test]
+    async fn recent_blocks_come_back_in_slot_order_whatever_order_they_finish_in() {
+        // The earliest slots take longest, and 97 and 99 have nothing scripted, so they fail as skipped
+        let source: Arc<ScriptedSource> = Arc::new(ScriptedSource {
+            slot: 100,
+            responses: HashMap::from([
+                (95, vec![scripted_block("95", true)]),
+                (96, vec![scripted_block("96", true)]),
+                (98, vec![scripted_block("98", true)]),
+            ]),
+            delays: HashMap::from([(95, Duration::from_millis(60)), (96, Duration::from_millis(30))]),
+            ..ScriptedSource::default()
+        });
+
+        let blocks: Vec<(u64, UiConfirmedBlock)> = get_recent_blocks(
+            source.clone(),
+            5,
+            &no_delay_retry(),
+            &AnalysisOptions::default(),
+            CommitmentConfig::confirmed(),
+            5,
+        )
+        .await
+        .unwrap();
+
+        let fetched: Vec<(u64, &str)> = blocks
+            .iter()
+            .map(|(slot, block)| (*slot, block.blockhash.as_str()))
+            .collect();
+        assert_eq!(fetched, [(95, "95"), (96, "96"), (98, "98")]);
+        assert!((95..100).all(|slot| source.fetches(slot) == 1));
+    }
+
+    #[test]
+    fn the_sandwich_block_ratio_is_blocks_with_patterns_over_blocks_analyzed() {
+        let mut summary: RunSummary = RunSummary::default();
+        assert_eq!(summary.sandwich_block_ratio(), None);
+
+        summary.blocks_analyzed = 4;
+        summary.blocks_with_patterns = 1;
+        assert_eq!(summary.sandwich_block_ratio(), Some(0.25));
+        assert!(summary
+            .to_summary()
+            .contains("Sandwiches found in 1 of 4 blocks (25.00%)"));
+    }
+
+    #[tokio::test]
+    async fn replaying_the_selftest_blocks_counts_the_blocks_with_sandwiches() {
+        let mut paths: Vec<PathBuf> =
+            std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/selftest/blocks"))
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+                .collect();
+        paths.sort();
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        // Every bundled block holds a sandwich, so an empty block is replayed alongside them
+        let mut blocks: Vec<UiConfirmedBlock> = paths.iter().map(|path| replay::read_block(path).unwrap()).collect();
+        blocks.push(scripted_block("empty", false));
+
+        let mut summary: RunSummary = RunSummary::default();
+        for (slot, block) in (0..).zip(&blocks) {
+            let analysis: BlockAnalysis = analyze_non_vote_transactions(
+                &OfflineSource,
+                &DecimalsCache::new(),
+                &mut new_tracker(&options),
+                slot,
+                block,
+                &options,
+            )
+            .await
+            .unwrap();
+            summary.record_block(analysis);
+        }
+
+        assert_eq!(summary.blocks_analyzed, paths.len() + 1);
+        assert_eq!(summary.blocks_with_patterns, paths.len());
+        assert_eq!(
+            summary.sandwich_block_ratio(),
+            Some(paths.len() as f64 / (paths.len() + 1) as f64)
+        );
+    }
+
+    #[tokio::test]
+    async fn each_pattern_is_written_as_its_own_line_of_json() {
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+
+        let mut patterns: Vec<Pattern> = Vec::new();
+        for slot in [1000, 2000] {
+            let path: String = format!("{}/fixtures/selftest/blocks/{}.json", env!("CARGO_MANIFEST_DIR"), slot);
+            let block: UiConfirmedBlock = replay::read_block(Path::new(&path)).unwrap();
+            let analysis: BlockAnalysis = analyze_non_vote_transactions(
+                &OfflineSource,
+                &DecimalsCache::new(),
+                &mut new_tracker(&options),
+                slot,
+                &block,
+                &options,
+            )
+            .await
+            .unwrap();
+            patterns.extend(analysis.patterns);
+        }
+        assert_eq!(patterns.len(), 2);
+
+        let mut output: Vec<u8> = Vec::new();
+        for pattern in &patterns {
+            write_ndjson(pattern, AmountUnit::Ui, &mut output).unwrap();
+        }
+
+        let output: String = String::from_utf8(output).unwrap();
+        assert!(output.ends_with('\n'));
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, pattern) in lines.iter().zip(&patterns) {
+            let json: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(json["create_tx"]["signature"], pattern.create_tx().signature);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_block_whose_slot_now_holds_another_block_or_none_was_reorged() {
+        let source: ScriptedSource = ScriptedSource {
+            slot: 100,
+            responses: HashMap::from([(90, vec![scripted_block("canonical", true)])]),
+            ..ScriptedSource::default()
+        };
+        let reorg_check: ReorgCheck = ReorgCheck {
+            depth: 5,
+            poll_interval: Duration::ZERO,
+            timeout: Duration::ZERO,
+        };
+        let status = |slot: u64, blockhash: &'static str| verify_canonical(&source, slot, blockhash, &reorg_check, 0);
+
+        assert!(matches!(status(90, "canonical").await.unwrap(), BlockStatus::Canonical));
+        assert!(matches!(
+            status(90, "confirmed-fork").await.unwrap(),
+            BlockStatus::Reorged
+        ));
+        // The slot the block was confirmed in was skipped by the finalized chain
+        assert!(matches!(
+            status(91, "confirmed-fork").await.unwrap(),
+            BlockStatus::Reorged
+        ));
+        // Finalization hasn't passed the confirmation depth, and the timeout has run out
+        assert!(matches!(
+            status(98, "canonical").await.unwrap(),
+            BlockStatus::Unfinalized
+        ));
+    }
+
+    #[test]
+    fn retractions_are_written_next_to_the_patterns_file() {
+        assert_eq!(
+            retractions_path(Path::new("out/patterns.csv")),
+            PathBuf::from("out/patterns.retractions.csv")
+        );
+        assert_eq!(
+            retractions_path(Path::new("patterns")),
+            PathBuf::from("patterns.retractions")
+        );
+    }
+
+    fn parse(args: &[&str]) -> std::result::Result<CliOptions, String> {
+        let args: Args = Args::try_parse_from(std::iter::once("sandwich-detector").chain(args.iter().copied()))
+            .map_err(|e| e.to_string())?;
+
+        cli_options(args)
+    }
+
+    #[test]
+    fn the_argument_definitions_are_consistent() {
+        use clap::CommandFactory;
+
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn ranges_and_flags_map_onto_options() {
+        let options: CliOptions = parse(&["--range", "10..20", "--rps", "5", "--json", "--no-decimals"]).unwrap();
+        assert_eq!(options.slot_range, Some((10, 19)));
+        assert_eq!(options.requests_per_second, Some(5.0));
+        assert_eq!(options.output, OutputFormat::Json);
+        assert!(!options.analysis.resolve_decimals);
+
+        assert_eq!(parse(&["--range", "10..=20"]).unwrap().slot_range, Some((10, 20)));
+        assert_eq!(parse(&["--slot-range", "7:9"]).unwrap().slot_range, Some((7, 9)));
+        assert_eq!(parse(&["--slot", "7"]).unwrap().slot_range, Some((7, 7)));
+
+        let options: CliOptions = parse(&["selftest"]).unwrap();
+        assert!(options.selftest && options.target.is_none());
+
+        let options: CliOptions = parse(&["--reorg-depth", "32"]).unwrap();
+        assert_eq!(options.reorg_check.map(|check| check.depth), Some(32));
+    }
+
+    #[test]
+    fn invalid_arguments_and_combinations_are_rejected() {
+        for args in [
+            &["--range", "20..10"][..],
+            &["--slot", "1", "--range", "1..5"],
+            &["--unknown-flag"],
+            &["--rps", "0"],
+            &["--classify-only"],
+            &["--dump-block", "block.json", "--range", "1..5"],
+            &["--strict-decimals", "--no-decimals"],
+            &["--output", "csv", "--campaigns"],
+            &["--instruction", "not-a-discriminator=AutoSwapIn"],
+            &["--subscribe", "--confirmed"],
+            &["--range", "1..5", "--reorg-depth", "3"],
+        ] {
+            assert!(parse(args).is_err(), "accepted {:?}", args);
+        }
+    }
+
+    #[test]
+    fn estimates_count_each_mode_and_list_what_they_leave_out() {
+        let estimate = |args: &[&str]| estimate_scan_requests(&parse(args).unwrap());
+
+        // One getBlock and one decimals batch per block, plus the getSlot finding recent blocks
+        let range: RequestEstimate = estimate(&["--range", "10..=19", "--estimate"]).unwrap();
+        assert_eq!((range.requests, range.per_slot), (20, false));
+        assert_eq!(
+            estimate(&["--range", "10..=19", "--sample-rate", "0.5"])
+                .unwrap()
+                .requests,
+            10
+        );
+        assert_eq!(
+            estimate(&["--recent", "10", "--avg-mints", "150"]).unwrap().requests,
+            31
+        );
+        assert_eq!(estimate(&["--recent", "10", "--no-decimals"]).unwrap().requests, 11);
+        assert_eq!(
+            estimate(&["--recent", "10", "--sample-rate", "0.5"]).unwrap().requests,
+            11
+        );
+        assert_eq!(
+            estimate(&["--slot", "7", "--dump-block", "block.json"])
+                .unwrap()
+                .requests,
+            1
+        );
+
+        // A subscription is counted per rooted slot, and only the recent-blocks scan retries empty blocks
+        let subscribe: RequestEstimate = estimate(&["--subscribe"]).unwrap();
+        assert_eq!((subscribe.requests, subscribe.per_slot), (2, true));
+        assert!(subscribe
+            .excluded
+            .iter()
+            .any(|excluded| excluded.contains("reconnecting")));
+        assert!(!range.excluded.iter().any(|excluded| excluded.contains("empty")));
+        let recent: RequestEstimate = estimate(&["--recent", "10"]).unwrap();
+        assert!(recent.excluded.iter().any(|excluded| excluded.contains("empty")));
+
+        let options: CliOptions = parse(&["--range", "10..=19", "--rps", "10"]).unwrap();
+        let notes: Vec<String> = range.notes(&options);
+        assert!(notes[0].contains("at least 2.0s"));
+        assert!(notes
+            .iter()
+            .any(|note| note.starts_with("Not counted: one getAssetBatch")));
+
+        assert!(estimate(&["selftest"]).is_err());
+    }
 }
@@ -0,0 +1,110 @@
+use prometheus::{Counter, Encoder, IntCounter, Registry, TextEncoder};
+use std::{io, net::SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::types::Pattern;
+
+// Counters for a continuously running detector, exported in the Prometheus text format
+pub struct Metrics {
+    registry: Registry,
+    pub blocks_analyzed: IntCounter,
+    pub patterns_detected: IntCounter,
+    // Sum of the positive SOL profits of detected patterns, since a counter can't decrease
+    pub total_sol_profit_observed: Counter,
+    pub rpc_errors: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry: Registry = Registry::new();
+
+        // The names and help texts are fixed and unique, so creating and registering them can't fail
+        let blocks_analyzed: IntCounter =
+            IntCounter::new("blocks_analyzed", "Blocks analyzed for sandwich patterns").expect("valid metric");
+        let patterns_detected: IntCounter =
+            IntCounter::new("patterns_detected", "Sandwich and arbitrage patterns detected").expect("valid metric");
+        let total_sol_profit_observed: Counter = Counter::new(
+            "total_sol_profit_observed",
+            "SOL profit of detected patterns, counting only profitable ones",
+        )
+        .expect("valid metric");
+        let rpc_errors: IntCounter = IntCounter::new("rpc_errors", "Failed RPC requests").expect("valid metric");
+
+        for collector in [&blocks_analyzed, &patterns_detected, &rpc_errors] {
+            registry.register(Box::new(collector.clone())).expect("unique metric");
+        }
+        registry
+            .register(Box::new(total_sol_profit_observed.clone()))
+            .expect("unique metric");
+
+        Metrics {
+            registry,
+            blocks_analyzed,
+            patterns_detected,
+            total_sol_profit_observed,
+            rpc_errors,
+        }
+    }
+
+    // Records an analyzed block and the patterns detected in it
+    pub fn record_block(&self, patterns: &[Pattern]) {
+        self.blocks_analyzed.inc();
+        self.patterns_detected.inc_by(patterns.len() as u64);
+
+        for pattern in patterns {
+            self.total_sol_profit_observed.inc_by(pattern.get_sol_profit().max(0.0));
+        }
+    }
+
+    // Returns every metric in the Prometheus text exposition format
+    pub fn encode(&self) -> String {
+        let mut buffer: Vec<u8> = Vec::new();
+
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            eprintln!("Failed to encode metrics: {}", e);
+        }
+
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Serves the metrics over HTTP on the given address until the listener fails
+// Every request gets the current metrics regardless of its path, which is all a Prometheus scraper needs
+pub async fn serve(metrics: &'static Metrics, addr: SocketAddr) -> io::Result<()> {
+    let listener: TcpListener = TcpListener::bind(addr).await?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, metrics).await {
+                eprintln!("Failed to serve metrics: {}", e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    // The request itself doesn't matter, but it's read so the client sees a clean response
+    let mut request: [u8; 1024] = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+
+    let body: String = metrics.encode();
+    let response: String = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
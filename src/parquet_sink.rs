@@ -0,0 +1,283 @@
+use std::{fs::File, path::Path, sync::Arc};
+
+use arrow::array::{ArrayRef, BooleanArray, Int64Array, StringArray, TimestampSecondArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::Result;
+
+use crate::types::{Pattern, Retraction};
+
+// Number of patterns buffered before they're written out as a row group
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 1024;
+
+// Writes patterns to a Parquet file with a typed schema for columnar analytics (e.g., DuckDB, Spark)
+// Lamport and base-unit amounts are stored as integers and block times as UTC timestamps
+pub struct ParquetSink {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    row_group_size: usize,
+    buffer: Vec<PatternRow>,
+}
+
+// A pattern flattened into the sink's columns
+struct PatternRow {
+    kind: String,
+    token: String,
+    attacker: String,
+    swapper: Option<String>,
+    block_height: u64,
+    block_time: Option<i64>,
+    token_profit: i64,
+    sol_profit_lamports: i64,
+    jito_tip_lamports: u64,
+    likely_wash: bool,
+    via_jito_bundle: bool,
+    create_signature: String,
+    swap_in_signature: String,
+    swap_out_signature: String,
+}
+
+impl From<&Pattern> for PatternRow {
+    fn from(pattern: &Pattern) -> Self {
+        Self {
+            kind: format!("{:?}", pattern.kind),
+            token: pattern.token.clone(),
+            attacker: pattern.attacker.clone(),
+            swapper: pattern.swapper.clone(),
+            block_height: pattern.create_tx().block_height,
+            block_time: pattern.create_tx().block_time,
+            token_profit: pattern.get_token_profit().clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            sol_profit_lamports: pattern
+                .get_sol_profit_lamports()
+                .clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            jito_tip_lamports: pattern.get_total_jito_tips(),
+            likely_wash: pattern.likely_wash,
+            via_jito_bundle: pattern.via_jito_bundle,
+            create_signature: pattern.create_tx().signature.clone(),
+            swap_in_signature: pattern.swap_in_tx().signature.clone(),
+            swap_out_signature: pattern.swap_out_tx().signature.clone(),
+        }
+    }
+}
+
+impl ParquetSink {
+    // Creates (or truncates) the file at path
+    pub fn create(path: &Path, row_group_size: usize) -> Result<Self> {
+        let schema: SchemaRef = Arc::new(Self::schema());
+        let file: File = File::create(path)?;
+        let writer: ArrowWriter<File> = ArrowWriter::try_new(file, schema.clone(), None)?;
+
+        Ok(Self {
+            writer,
+            schema,
+            row_group_size: row_group_size.max(1),
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("kind", DataType::Utf8, false),
+            Field::new("token", DataType::Utf8, false),
+            Field::new("attacker", DataType::Utf8, false),
+            Field::new("swapper", DataType::Utf8, true),
+            Field::new("block_height", DataType::UInt64, false),
+            Field::new(
+                "block_time",
+                DataType::Timestamp(TimeUnit::Second, Some("UTC".into())),
+                true,
+            ),
+            Field::new("token_profit", DataType::Int64, false),
+            Field::new("sol_profit_lamports", DataType::Int64, false),
+            Field::new("jito_tip_lamports", DataType::UInt64, false),
+            Field::new("likely_wash", DataType::Boolean, false),
+            Field::new("via_jito_bundle", DataType::Boolean, false),
+            Field::new("create_signature", DataType::Utf8, false),
+            Field::new("swap_in_signature", DataType::Utf8, false),
+            Field::new("swap_out_signature", DataType::Utf8, false),
+        ])
+    }
+
+    // Buffers a pattern, writing a row group once enough have accumulated
+    pub fn write(&mut self, pattern: &Pattern) -> Result<()> {
+        self.buffer.push(PatternRow::from(pattern));
+
+        if self.buffer.len() >= self.row_group_size {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Writes any buffered patterns out as a row group
+    pub fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let rows: Vec<PatternRow> = std::mem::take(&mut self.buffer);
+        let batch: RecordBatch = self.to_record_batch(&rows)?;
+
+        self.writer.write(&batch)?;
+        self.writer.flush()
+    }
+
+    // Flushes remaining patterns and finalizes the file footer
+    pub fn close(mut self) -> Result<()> {
+        self.flush()?;
+        self.writer.close()?;
+
+        Ok(())
+    }
+
+    fn to_record_batch(&self, rows: &[PatternRow]) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.kind.as_str()))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.token.as_str()))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.attacker.as_str()))),
+            Arc::new(StringArray::from_iter(rows.iter().map(|r| r.swapper.as_deref()))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.block_height))),
+            Arc::new(TimestampSecondArray::from_iter(rows.iter().map(|r| r.block_time)).with_timezone("UTC")),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.token_profit))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.sol_profit_lamports))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.jito_tip_lamports))),
+            Arc::new(BooleanArray::from_iter(rows.iter().map(|r| Some(r.likely_wash)))),
+            Arc::new(BooleanArray::from_iter(rows.iter().map(|r| Some(r.via_jito_bundle)))),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| r.create_signature.as_str()),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| r.swap_in_signature.as_str()),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| r.swap_out_signature.as_str()),
+            )),
+        ];
+
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+}
+
+// Writes retractions to a Parquet file of their own, since their columns differ from the patterns
+pub fn write_retractions(retractions: &[Retraction], path: &Path) -> Result<()> {
+    let schema: SchemaRef = Arc::new(retraction_schema());
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(retractions.iter().map(|r| r.slot))),
+        Arc::new(StringArray::from_iter_values(
+            retractions.iter().map(|r| r.blockhash.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            retractions.iter().map(|r| r.create_signature.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            retractions.iter().map(|r| r.swap_in_signature.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            retractions.iter().map(|r| r.swap_out_signature.as_str()),
+        )),
+    ];
+
+    let mut writer: ArrowWriter<File> = ArrowWriter::try_new(File::create(path)?, schema.clone(), None)?;
+    writer.write(&RecordBatch::try_new(schema, columns)?)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+pub fn retraction_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("slot", DataType::UInt64, false),
+        Field::new("blockhash", DataType::Utf8, false),
+        Field::new("create_signature", DataType::Utf8, false),
+        Field::new("swap_in_signature", DataType::Utf8, false),
+        Field::new("swap_out_signature", DataType::Utf8, false),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandwich_pattern;
+    use arrow::array::AsArray;
+    use arrow::datatypes::{Int64Type, UInt64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn patterns_round_trip_through_a_parquet_file() {
+        let mut first: Pattern = sandwich_pattern("a");
+        first.transactions.1.jito_tip_amount = 1_000;
+        first.transactions.2.jito_tip_amount = 2_000;
+        first.transactions.2.wsol_change = Some(1_500_000_000);
+        let second: Pattern = sandwich_pattern("b");
+
+        let path = std::env::temp_dir().join(format!("sandwich-detector-{}.parquet", std::process::id()));
+        // A row group size of one writes each pattern as its own row group
+        let mut sink: ParquetSink = ParquetSink::create(&path, 1).unwrap();
+        sink.write(&first).unwrap();
+        sink.write(&second).unwrap();
+        sink.close().unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        assert_eq!(builder.schema().as_ref(), &ParquetSink::schema());
+
+        let batches: Vec<RecordBatch> = builder.build().unwrap().collect::<std::result::Result<_, _>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rows: Vec<(String, u64, i64)> = batches
+            .iter()
+            .flat_map(|batch| {
+                let signatures = batch.column_by_name("swap_out_signature").unwrap().as_string::<i32>();
+                let tips = batch
+                    .column_by_name("jito_tip_lamports")
+                    .unwrap()
+                    .as_primitive::<UInt64Type>();
+                let profits = batch
+                    .column_by_name("sol_profit_lamports")
+                    .unwrap()
+                    .as_primitive::<Int64Type>();
+
+                (0..batch.num_rows())
+                    .map(|i| (signatures.value(i).to_string(), tips.value(i), profits.value(i)))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        assert_eq!(
+            rows,
+            [
+                ("a-sig3".to_string(), 3_000, 1_499_997_000),
+                ("b-sig3".to_string(), 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn retractions_round_trip_through_their_own_parquet_file() {
+        let retraction: Retraction = Retraction::new(&sandwich_pattern("a"), 500, "reorged-hash");
+
+        let path = std::env::temp_dir().join(format!("sandwich-detector-{}.retractions.parquet", std::process::id()));
+        write_retractions(std::slice::from_ref(&retraction), &path).unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(builder.schema().as_ref(), &retraction_schema());
+        let batches: Vec<RecordBatch> = builder.build().unwrap().collect::<std::result::Result<_, _>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(batches.len(), 1);
+        let batch: &RecordBatch = &batches[0];
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(
+            batch
+                .column_by_name("slot")
+                .unwrap()
+                .as_primitive::<UInt64Type>()
+                .value(0),
+            500
+        );
+        for (column, value) in [("blockhash", "reorged-hash"), ("swap_out_signature", "a-sig3")] {
+            assert_eq!(batch.column_by_name(column).unwrap().as_string::<i32>().value(0), value);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Offline replay of saved blocks, so a detection can be reproduced exactly from the getBlock response it came from
+//
+// A block is saved with --dump-block and replayed with --replay, which needs no API key or network since token
+// decimals aren't resolved
+
+use std::{fs, path::Path};
+
+use helius::error::{HeliusError, Result};
+use solana_transaction_status::UiConfirmedBlock;
+
+use sandwich_detector::analysis::{analyze_non_vote_transactions, get_block_by_slot, new_tracker, AnalysisOptions};
+use sandwich_detector::source::{BlockSource, OfflineSource};
+use sandwich_detector::types::{BlockAnalysis, DecimalsCache};
+
+// Reads a block saved from a getBlock response
+pub fn read_block(path: &Path) -> Result<UiConfirmedBlock> {
+    let contents: String = fs::read_to_string(path)
+        .map_err(|e| HeliusError::InvalidInput(format!("Failed to read block {}: {}", path.display(), e)))?;
+
+    serde_json::from_str(&contents)
+        .map_err(|e| HeliusError::InvalidInput(format!("Failed to parse block {}: {}", path.display(), e)))
+}
+
+// Saves a block in the getBlock response format read_block expects
+pub fn write_block(block: &UiConfirmedBlock, path: &Path) -> Result<()> {
+    let json: String = serde_json::to_string(block)
+        .map_err(|e| HeliusError::InvalidInput(format!("Failed to serialize block: {}", e)))?;
+
+    path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(path, json))
+        .map_err(|e| HeliusError::InvalidInput(format!("Failed to write block {}: {}", path.display(), e)))
+}
+
+// Fetches the block at slot and saves it to path for later replay
+// Returns whether the block was found
+pub fn dump_block<B: BlockSource>(source: &B, slot: u64, path: &Path, options: &AnalysisOptions) -> Result<bool> {
+    match get_block_by_slot(source, slot, options) {
+        Some(block) => {
+            write_block(&block, path)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Analyzes a saved block without any network access
+// Decimals lookups would fail offline, so options should have them turned off, leaving amounts in base units
+// The block's slot isn't part of a getBlock response, so it's taken as given, or else from a file name like
+// <slot>.json, or else as the slot after the block's parent
+pub async fn analyze_block_file(path: &Path, slot: Option<u64>, options: &AnalysisOptions) -> Result<BlockAnalysis> {
+    let block: UiConfirmedBlock = read_block(path)?;
+
+    let slot: u64 = slot
+        .or_else(|| path.file_stem()?.to_str()?.parse().ok())
+        .unwrap_or(block.parent_slot + 1);
+
+    analyze_non_vote_transactions(
+        &OfflineSource,
+        &DecimalsCache::new(),
+        &mut new_tracker(options),
+        slot,
+        &block,
+        options,
+    )
+    .await
+    .map_err(HeliusError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sandwich_detector::types::{Pattern, PatternKind};
+    use std::path::PathBuf;
+
+    // A synthetic block in the format --dump-block saves: the bot's create and buy, a holder selling into the pool,
+    // a victim's buy, then the bot's sell
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/replay/3000.json");
+
+    #[tokio::test]
+    async fn a_dumped_block_replays_to_its_sandwich() {
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        let analysis: BlockAnalysis = analyze_block_file(Path::new(FIXTURE), None, &options).await.unwrap();
+
+        // The slot comes from the file name
+        assert_eq!(analysis.slot, 3000);
+        assert_eq!(analysis.patterns.len(), 1);
+
+        let pattern: &Pattern = &analysis.patterns[0];
+        assert_eq!(pattern.kind, PatternKind::Sandwich);
+        assert_eq!(pattern.attacker, "4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi");
+        assert_eq!(pattern.token, "Mint111111111111111111111111111111111111111");
+        // The holder's sell between the legs isn't a victim
+        assert_eq!(pattern.victims.len(), 1);
+        assert_eq!(pattern.victims[0].signer, "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR");
+        assert!((pattern.get_sol_profit() - 0.099985).abs() < 0.5e-9);
+    }
+
+    #[test]
+    fn a_written_block_reads_back_unchanged() {
+        let block: UiConfirmedBlock = read_block(Path::new(FIXTURE)).unwrap();
+        let path: PathBuf = std::env::temp_dir().join(format!("replay-{}.json", std::process::id()));
+
+        write_block(&block, &path).unwrap();
+        let written: String = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, fs::read_to_string(FIXTURE).unwrap());
+    }
+}
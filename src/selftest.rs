@@ -0,0 +1,261 @@
+// End-to-end acceptance check: runs the detector over archived blocks with known sandwiches and compares
+// its output against verified expectations
+//
+// Fixtures are cases.json plus a blocks/ directory of getBlock responses named <slot>.json, as saved by
+// --dump-block. The bundled fixtures are compiled into the binary, so the check works from any directory;
+// --fixtures points at another directory instead. Everything runs offline, without an API key
+
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+
+use helius::error::{HeliusError, Result};
+use solana_transaction_status::UiConfirmedBlock;
+
+use sandwich_detector::types::{Pattern, PatternKind};
+
+use sandwich_detector::types::{BlockAnalysis, DecimalsCache};
+
+use sandwich_detector::analysis::{analyze_non_vote_transactions, new_tracker, AnalysisOptions};
+use sandwich_detector::source::OfflineSource;
+
+use super::replay::read_block;
+
+// Fixtures shipped with the crate, as (slot, getBlock response) for the blocks
+const BUNDLED_CASES: &str = include_str!("../fixtures/selftest/cases.json");
+const BUNDLED_BLOCKS: [(u64, &str); 2] = [
+    (1000, include_str!("../fixtures/selftest/blocks/1000.json")),
+    (2000, include_str!("../fixtures/selftest/blocks/2000.json")),
+];
+
+// Where the cases and their blocks are read from
+pub enum Fixtures {
+    Bundled,
+    Dir(PathBuf),
+}
+
+impl Fixtures {
+    fn cases(&self) -> Result<Vec<SelfTestCase>> {
+        let (contents, origin): (String, String) = match self {
+            Fixtures::Bundled => (BUNDLED_CASES.to_string(), "bundled cases.json".to_string()),
+            Fixtures::Dir(dir) => {
+                let path: PathBuf = dir.join("cases.json");
+                let contents: String = fs::read_to_string(&path).map_err(|e| {
+                    HeliusError::InvalidInput(format!("Failed to read self-test cases {}: {}", path.display(), e))
+                })?;
+                (contents, path.display().to_string())
+            }
+        };
+
+        serde_json::from_str(&contents)
+            .map_err(|e| HeliusError::InvalidInput(format!("Failed to parse self-test cases {}: {}", origin, e)))
+    }
+
+    // Returns None if the block isn't archived
+    fn block(&self, slot: u64) -> Result<Option<UiConfirmedBlock>> {
+        match self {
+            Fixtures::Bundled => BUNDLED_BLOCKS
+                .iter()
+                .find(|(bundled, _)| *bundled == slot)
+                .map(|(_, json)| {
+                    serde_json::from_str(json).map_err(|e| {
+                        HeliusError::InvalidInput(format!("Failed to parse bundled block {}: {}", slot, e))
+                    })
+                })
+                .transpose(),
+            Fixtures::Dir(dir) => {
+                let path: PathBuf = dir.join("blocks").join(format!("{}.json", slot));
+                if path.exists() {
+                    read_block(&path).map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SelfTestCase {
+    slot: u64,
+    description: String,
+    // Minimum number of patterns classified as sandwiches (rather than arbitrage) in the block
+    #[serde(default)]
+    min_sandwiches: usize,
+    // Specific patterns the detector must reproduce
+    #[serde(default)]
+    expected: Vec<ExpectedPattern>,
+}
+
+// A verified sandwich; every field that's set must match the same detected pattern
+#[derive(Deserialize)]
+struct ExpectedPattern {
+    attacker: Option<String>,
+    // The mint the attacker traded
+    token: Option<String>,
+    swap_in_signature: Option<String>,
+    swap_out_signature: Option<String>,
+    victim: Option<String>,
+    min_sol_profit: Option<f64>,
+    // Net SOL profit, matched to the lamport
+    sol_profit: Option<f64>,
+}
+
+impl ExpectedPattern {
+    fn matches(&self, pattern: &Pattern) -> bool {
+        self.attacker
+            .as_ref()
+            .is_none_or(|attacker| *attacker == pattern.attacker)
+            && self
+                .token
+                .as_ref()
+                .is_none_or(|token| *token == pattern.swap_in_tx().from_mint)
+            && self
+                .swap_in_signature
+                .as_ref()
+                .is_none_or(|signature| *signature == pattern.swap_in_tx().signature)
+            && self
+                .swap_out_signature
+                .as_ref()
+                .is_none_or(|signature| *signature == pattern.swap_out_tx().signature)
+            && self
+                .victim
+                .as_ref()
+                .is_none_or(|victim| pattern.victims.iter().any(|swap| swap.signer == *victim))
+            && self
+                .min_sol_profit
+                .is_none_or(|min_profit| pattern.get_sol_profit() >= min_profit)
+            && self
+                .sol_profit
+                .is_none_or(|profit| (pattern.get_sol_profit() - profit).abs() < 0.5e-9)
+    }
+
+    fn describe(&self) -> String {
+        let fields: Vec<String> = [
+            self.attacker.as_ref().map(|attacker| format!("attacker {}", attacker)),
+            self.token.as_ref().map(|token| format!("token {}", token)),
+            self.swap_in_signature
+                .as_ref()
+                .map(|signature| format!("swap-in {}", signature)),
+            self.swap_out_signature
+                .as_ref()
+                .map(|signature| format!("swap-out {}", signature)),
+            self.victim.as_ref().map(|victim| format!("victim {}", victim)),
+            self.min_sol_profit
+                .map(|min_profit| format!("profit >= {:.9} SOL", min_profit)),
+            self.sol_profit.map(|profit| format!("profit {:.9} SOL", profit)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if fields.is_empty() {
+            "any pattern".to_string()
+        } else {
+            fields.join(", ")
+        }
+    }
+}
+
+// Runs every case, printing a pass/fail report
+// Returns whether all cases passed
+pub async fn run(fixtures: &Fixtures, options: &AnalysisOptions) -> Result<bool> {
+    let cases: Vec<SelfTestCase> = fixtures.cases()?;
+
+    let mut passed: usize = 0;
+
+    for case in &cases {
+        println!("\n=== Slot {}: {} ===", case.slot, case.description);
+
+        let failures: Vec<String> = match fixtures.block(case.slot)? {
+            Some(block) => {
+                let analysis: BlockAnalysis = analyze_non_vote_transactions(
+                    &OfflineSource,
+                    &DecimalsCache::new(),
+                    &mut new_tracker(options),
+                    case.slot,
+                    &block,
+                    options,
+                )
+                .await?;
+                check_case(case, &analysis.patterns)
+            }
+            None => vec![format!(
+                "Block {} isn't archived; save it to blocks/{}.json with --dump-block",
+                case.slot, case.slot
+            )],
+        };
+
+        if failures.is_empty() {
+            println!("PASS");
+            passed += 1;
+        } else {
+            println!("FAIL");
+            for failure in &failures {
+                println!("- {}", failure);
+            }
+        }
+    }
+
+    println!("\nSelf-test: {} of {} cases passed", passed, cases.len());
+
+    Ok(passed == cases.len())
+}
+
+fn check_case(case: &SelfTestCase, patterns: &[Pattern]) -> Vec<String> {
+    let mut failures: Vec<String> = Vec::new();
+
+    let sandwiches: usize = patterns
+        .iter()
+        .filter(|pattern| pattern.kind == PatternKind::Sandwich)
+        .count();
+    if sandwiches < case.min_sandwiches {
+        failures.push(format!(
+            "Expected at least {} sandwiches, found {}",
+            case.min_sandwiches, sandwiches
+        ));
+    }
+
+    for expected in &case.expected {
+        if !patterns.iter().any(|pattern| expected.matches(pattern)) {
+            failures.push(format!("No detected pattern matched {}", expected.describe()));
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bundled_fixtures_pass_offline() {
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+
+        assert!(run(&Fixtures::Bundled, &options).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_wrong_expectation_fails() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("selftest-{}", std::process::id()));
+        fs::create_dir_all(dir.join("blocks")).unwrap();
+        fs::write(dir.join("blocks").join("1000.json"), BUNDLED_BLOCKS[0].1).unwrap();
+        fs::write(
+            dir.join("cases.json"),
+            r#"[{ "slot": 1000, "description": "wrong token", "expected": [{ "token": "Other" }] }]"#,
+        )
+        .unwrap();
+
+        let options: AnalysisOptions = AnalysisOptions {
+            resolve_decimals: false,
+            ..AnalysisOptions::default()
+        };
+        let passed: bool = run(&Fixtures::Dir(dir.clone()), &options).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!passed);
+    }
+}
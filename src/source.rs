@@ -0,0 +1,307 @@
+// Methods mirror RpcClient, which returns ClientError unboxed
+#![allow(clippy::result_large_err)]
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use helius::Helius;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_client::RpcClient,
+    rpc_config::{RpcBlockConfig, RpcTransactionConfig},
+};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiConfirmedBlock};
+
+// Helius' free tier allows 10 RPC requests per second
+pub const DEFAULT_REQUESTS_PER_SECOND: f64 = 10.0;
+
+// The RPC calls block analysis depends on, so it can run against something other than a live node, e.g. canned
+// blocks and accounts served from memory
+pub trait BlockSource {
+    fn get_slot(&self, commitment: CommitmentConfig) -> Result<u64, ClientError>;
+
+    fn get_block_with_config(&self, slot: u64, config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError>;
+
+    // Accounts are returned in the order of pubkeys, with None for accounts that don't exist
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError>;
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError>;
+}
+
+impl BlockSource for RpcClient {
+    fn get_slot(&self, commitment: CommitmentConfig) -> Result<u64, ClientError> {
+        self.get_slot_with_commitment(commitment)
+    }
+
+    fn get_block_with_config(&self, slot: u64, config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError> {
+        RpcClient::get_block_with_config(self, slot, config)
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        RpcClient::get_multiple_accounts(self, pubkeys)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        RpcClient::get_transaction_with_config(self, signature, config)
+    }
+}
+
+impl BlockSource for Helius {
+    fn get_slot(&self, commitment: CommitmentConfig) -> Result<u64, ClientError> {
+        self.connection().get_slot_with_commitment(commitment)
+    }
+
+    fn get_block_with_config(&self, slot: u64, config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError> {
+        self.connection().get_block_with_config(slot, config)
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        self.connection().get_multiple_accounts(pubkeys)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        self.connection().get_transaction_with_config(signature, config)
+    }
+}
+
+// Lets a source be shared with the blocking tasks that fetch blocks in parallel
+impl<B: BlockSource + ?Sized> BlockSource for Arc<B> {
+    fn get_slot(&self, commitment: CommitmentConfig) -> Result<u64, ClientError> {
+        (**self).get_slot(commitment)
+    }
+
+    fn get_block_with_config(&self, slot: u64, config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError> {
+        (**self).get_block_with_config(slot, config)
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        (**self).get_multiple_accounts(pubkeys)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        (**self).get_transaction_with_config(signature, config)
+    }
+}
+
+// A block source with nothing behind it, for analyzing saved blocks without reaching the network by accident
+// Every request fails, so decimals lookups should be turned off when analyzing with it
+pub struct OfflineSource;
+
+impl OfflineSource {
+    fn unavailable() -> ClientError {
+        ClientErrorKind::Custom("RPC requests aren't available while analyzing offline".to_string()).into()
+    }
+}
+
+impl BlockSource for OfflineSource {
+    fn get_slot(&self, _commitment: CommitmentConfig) -> Result<u64, ClientError> {
+        Err(Self::unavailable())
+    }
+
+    fn get_block_with_config(&self, _slot: u64, _config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError> {
+        Err(Self::unavailable())
+    }
+
+    fn get_multiple_accounts(&self, _pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        Err(Self::unavailable())
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        _signature: &Signature,
+        _config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        Err(Self::unavailable())
+    }
+}
+
+// A token bucket holding up to one second's worth of requests, refilled continuously at the configured rate
+// Tokens can go negative, which reserves a slot for each waiting caller so concurrent callers stay spaced out
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    // Refills the bucket for the time since it was last refilled, takes a token, and returns how long the caller must
+    // wait for it
+    fn take(&mut self, now: Instant, requests_per_second: f64, capacity: f64) -> Duration {
+        let elapsed: f64 = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * requests_per_second).min(capacity);
+        self.refilled_at = now;
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / requests_per_second)
+        }
+    }
+}
+
+// Wraps a source so every RPC call waits its turn under a requests-per-second limit, keeping parallel fetches from
+// bursting past the plan's rate limit and getting 429s
+// Calls block the calling thread while they wait, just like the blocking RPC calls they wrap
+pub struct RateLimitedClient<B> {
+    inner: B,
+    requests_per_second: f64,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl<B: BlockSource> RateLimitedClient<B> {
+    // requests_per_second must be positive; a burst of up to one second's worth of requests goes through at once
+    pub fn new(inner: B, requests_per_second: f64) -> Self {
+        Self {
+            inner,
+            requests_per_second,
+            bucket: Mutex::new(TokenBucket {
+                tokens: Self::capacity(requests_per_second),
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests_per_second
+    }
+
+    fn capacity(requests_per_second: f64) -> f64 {
+        requests_per_second.max(1.0)
+    }
+
+    // Takes a token, sleeping until it's available
+    fn acquire(&self) {
+        let wait: Duration = self
+            .bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take(
+                Instant::now(),
+                self.requests_per_second,
+                Self::capacity(self.requests_per_second),
+            );
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+impl<B: BlockSource> BlockSource for RateLimitedClient<B> {
+    fn get_slot(&self, commitment: CommitmentConfig) -> Result<u64, ClientError> {
+        self.acquire();
+        self.inner.get_slot(commitment)
+    }
+
+    fn get_block_with_config(&self, slot: u64, config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError> {
+        self.acquire();
+        self.inner.get_block_with_config(slot, config)
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        self.acquire();
+        self.inner.get_multiple_accounts(pubkeys)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        self.acquire();
+        self.inner.get_transaction_with_config(signature, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_bucket(capacity: f64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: capacity,
+            refilled_at: now,
+        }
+    }
+
+    #[test]
+    fn a_full_bucket_lets_a_burst_of_its_capacity_through_then_spaces_out_the_rest() {
+        let start: Instant = Instant::now();
+        let mut bucket: TokenBucket = full_bucket(4.0, start);
+
+        for _ in 0..4 {
+            assert_eq!(bucket.take(start, 4.0, 4.0), Duration::ZERO);
+        }
+        // Each caller past the burst reserves the next token, a quarter second after the one before
+        assert_eq!(bucket.take(start, 4.0, 4.0), Duration::from_millis(250));
+        assert_eq!(bucket.take(start, 4.0, 4.0), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn tokens_refill_at_the_rate_up_to_the_capacity() {
+        let start: Instant = Instant::now();
+        let mut bucket: TokenBucket = full_bucket(2.0, start);
+        bucket.take(start, 2.0, 2.0);
+        bucket.take(start, 2.0, 2.0);
+
+        // Half a second at 2 per second refills one token, so one request goes through and the next waits
+        let later: Instant = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(later, 2.0, 2.0), Duration::ZERO);
+        assert_eq!(bucket.take(later, 2.0, 2.0), Duration::from_millis(500));
+
+        // An idle minute refills no more than the capacity
+        let idle: Instant = later + Duration::from_secs(60);
+        assert_eq!(bucket.take(idle, 2.0, 2.0), Duration::ZERO);
+        assert_eq!(bucket.take(idle, 2.0, 2.0), Duration::ZERO);
+        assert_eq!(bucket.take(idle, 2.0, 2.0), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn a_rate_below_one_per_second_still_allows_a_single_request_at_once() {
+        let capacity: f64 = RateLimitedClient::<OfflineSource>::capacity(0.5);
+        let start: Instant = Instant::now();
+        let mut bucket: TokenBucket = full_bucket(capacity, start);
+
+        assert_eq!(capacity, 1.0);
+        assert_eq!(bucket.take(start, 0.5, 1.0), Duration::ZERO);
+        assert_eq!(bucket.take(start, 0.5, 1.0), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn the_client_waits_its_turn_between_requests() {
+        let client: RateLimitedClient<OfflineSource> = RateLimitedClient::new(OfflineSource, 20.0);
+        let start: Instant = Instant::now();
+
+        // 20 requests go through in the initial burst, and the next 2 wait 50ms each
+        for _ in 0..22 {
+            assert!(client.get_slot(CommitmentConfig::finalized()).is_err());
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
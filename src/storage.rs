@@ -0,0 +1,353 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension, Result, Transaction, TransactionBehavior};
+
+use crate::error::SandwichError;
+use crate::types::{Pattern, Retraction};
+
+// Schema migrations, applied in order; the database's user_version records how many have run
+const MIGRATIONS: [&str; 3] = [
+    "CREATE TABLE patterns (
+        swap_out_signature TEXT PRIMARY KEY,
+        swap_in_signature TEXT NOT NULL,
+        create_signature TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        token TEXT NOT NULL,
+        attacker TEXT NOT NULL,
+        victim TEXT,
+        swapper TEXT,
+        block_height INTEGER NOT NULL,
+        block_time INTEGER,
+        token_profit INTEGER NOT NULL,
+        sol_profit_lamports INTEGER NOT NULL,
+        jito_tip_lamports INTEGER NOT NULL,
+        likely_wash INTEGER NOT NULL,
+        via_jito_bundle INTEGER NOT NULL
+    );
+    CREATE INDEX patterns_attacker ON patterns (attacker);
+    CREATE INDEX patterns_block_height ON patterns (block_height);",
+    "CREATE TABLE runs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        target_program TEXT NOT NULL,
+        start_slot INTEGER NOT NULL,
+        end_slot INTEGER NOT NULL,
+        last_slot INTEGER
+    );",
+    "CREATE TABLE retractions (
+        swap_out_signature TEXT PRIMARY KEY,
+        swap_in_signature TEXT NOT NULL,
+        create_signature TEXT NOT NULL,
+        slot INTEGER NOT NULL,
+        blockhash TEXT NOT NULL
+    );",
+];
+
+// Opens (or creates) the database at path and brings its schema up to date
+pub fn open(path: &Path) -> Result<Connection> {
+    let conn: Connection = Connection::open(path)?;
+    migrate(&conn)?;
+
+    Ok(conn)
+}
+
+// Runs any migrations the database hasn't seen yet
+pub fn migrate(conn: &Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute_batch(migration)?;
+        conn.pragma_update(None, "user_version", i + 1)?;
+    }
+
+    Ok(())
+}
+
+// Stores a pattern, keyed by its swap-out signature so re-analyzing a block doesn't duplicate it
+// Returns whether the pattern was new
+pub fn insert_pattern(conn: &Connection, pattern: &Pattern) -> Result<bool> {
+    let inserted: usize = conn.execute(
+        "INSERT OR IGNORE INTO patterns (
+            swap_out_signature, swap_in_signature, create_signature, kind, token, attacker, victim, swapper,
+            block_height, block_time, token_profit, sol_profit_lamports, jito_tip_lamports, likely_wash,
+            via_jito_bundle
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            pattern.swap_out_tx().signature,
+            pattern.swap_in_tx().signature,
+            pattern.create_tx().signature,
+            format!("{:?}", pattern.kind),
+            pattern.token,
+            pattern.attacker,
+            pattern.victims.first().map(|victim| victim.signer.as_str()),
+            pattern.swapper,
+            pattern.create_tx().block_height as i64,
+            pattern.create_tx().block_time,
+            pattern.get_token_profit().clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            pattern
+                .get_sol_profit_lamports()
+                .clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            pattern.get_total_jito_tips() as i64,
+            pattern.likely_wash,
+            pattern.via_jito_bundle,
+        ],
+    )?;
+
+    Ok(inserted > 0)
+}
+
+// Records a retraction and removes the retracted pattern, if it was stored, in one transaction
+pub fn retract_pattern(conn: &mut Connection, retraction: &Retraction) -> Result<()> {
+    let tx: Transaction = conn.transaction()?;
+
+    tx.execute(
+        "INSERT OR IGNORE INTO retractions (
+            swap_out_signature, swap_in_signature, create_signature, slot, blockhash
+        ) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            retraction.swap_out_signature,
+            retraction.swap_in_signature,
+            retraction.create_signature,
+            retraction.slot as i64,
+            retraction.blockhash,
+        ],
+    )?;
+    tx.execute(
+        "DELETE FROM patterns WHERE swap_out_signature = ?1",
+        params![retraction.swap_out_signature],
+    )?;
+
+    tx.commit()
+}
+
+// A range scan recorded in the runs table, with the last slot whose patterns were fully stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub id: i64,
+    pub target_program: String,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub last_slot: Option<u64>,
+}
+
+impl Run {
+    // The slot a resumed scan continues from, or None if the run already reached its end slot
+    // Refuses to resume a run made against another program, since its checkpoint says nothing about this one
+    pub fn resume_slot(&self, target_program: &str) -> std::result::Result<Option<u64>, SandwichError> {
+        if self.target_program != target_program {
+            return Err(SandwichError::ResumeTargetMismatch {
+                stored: self.target_program.clone(),
+                configured: target_program.to_string(),
+            });
+        }
+
+        Ok(match self.last_slot {
+            Some(last_slot) if last_slot >= self.end_slot => None,
+            Some(last_slot) => Some(last_slot + 1),
+            None => Some(self.start_slot),
+        })
+    }
+}
+
+// Records the start of a scan over an inclusive slot range, returning it with no slots processed yet
+pub fn start_run(conn: &Connection, target_program: &str, start_slot: u64, end_slot: u64) -> Result<Run> {
+    conn.execute(
+        "INSERT INTO runs (target_program, start_slot, end_slot) VALUES (?1, ?2, ?3)",
+        params![target_program, start_slot as i64, end_slot as i64],
+    )?;
+
+    Ok(Run {
+        id: conn.last_insert_rowid(),
+        target_program: target_program.to_string(),
+        start_slot,
+        end_slot,
+        last_slot: None,
+    })
+}
+
+// Returns the most recently started run, if any
+pub fn latest_run(conn: &Connection) -> Result<Option<Run>> {
+    conn.query_row(
+        "SELECT id, target_program, start_slot, end_slot, last_slot FROM runs ORDER BY id DESC LIMIT 1",
+        [],
+        |row| {
+            Ok(Run {
+                id: row.get(0)?,
+                target_program: row.get(1)?,
+                start_slot: row.get::<_, i64>(2)? as u64,
+                end_slot: row.get::<_, i64>(3)? as u64,
+                last_slot: row.get::<_, Option<i64>>(4)?.map(|slot| slot as u64),
+            })
+        },
+    )
+    .optional()
+}
+
+// Stores a block's patterns and advances the run's checkpoint to its slot in one transaction, so a resumed run
+// never skips a block whose patterns weren't all stored
+// The write lock is taken up front, so concurrent writers wait rather than fail partway through
+pub fn record_block(conn: &mut Connection, run: &mut Run, slot: u64, patterns: &[Pattern]) -> Result<()> {
+    let tx: Transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    for pattern in patterns {
+        insert_pattern(&tx, pattern)?;
+    }
+    tx.execute(
+        "UPDATE runs SET last_slot = MAX(COALESCE(last_slot, ?2), ?2) WHERE id = ?1",
+        params![run.id, slot as i64],
+    )?;
+
+    tx.commit()?;
+    run.last_slot = run.last_slot.max(Some(slot));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandwich_pattern;
+    use crate::types::TARGET_PROGRAM;
+
+    fn memory_db() -> Connection {
+        let conn: Connection = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn migrating_a_patterns_only_database_adds_the_runs_table() {
+        let conn: Connection = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+
+        migrate(&conn).unwrap();
+
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        assert_eq!(latest_run(&conn).unwrap(), None);
+    }
+
+    #[test]
+    fn resumes_after_the_last_stored_slot() {
+        let mut conn: Connection = memory_db();
+        let mut run: Run = start_run(&conn, TARGET_PROGRAM, 100, 110).unwrap();
+        assert_eq!(run.resume_slot(TARGET_PROGRAM).unwrap(), Some(100));
+
+        record_block(&mut conn, &mut run, 101, &[sandwich_pattern("a")]).unwrap();
+        record_block(&mut conn, &mut run, 104, &[]).unwrap();
+
+        // A fresh process reads the checkpoint back from the database
+        let stored: Run = latest_run(&conn).unwrap().unwrap();
+        assert_eq!(stored, run);
+        assert_eq!(stored.last_slot, Some(104));
+        assert_eq!(stored.resume_slot(TARGET_PROGRAM).unwrap(), Some(105));
+
+        let patterns: i64 = conn
+            .query_row("SELECT COUNT(*) FROM patterns", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(patterns, 1);
+
+        let mut stored: Run = stored;
+        record_block(&mut conn, &mut stored, 110, &[]).unwrap();
+        assert_eq!(
+            latest_run(&conn).unwrap().unwrap().resume_slot(TARGET_PROGRAM).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn a_pattern_reads_back_with_its_columns_and_is_stored_once() {
+        let conn: Connection = memory_db();
+        let mut pattern: Pattern = sandwich_pattern("a");
+        pattern.transactions.1.wsol_change = Some(-2_000_000_001);
+        pattern.transactions.1.fee = 5_000;
+        pattern.transactions.2.wsol_change = Some(2_100_000_000);
+        pattern.transactions.2.fee = 5_000;
+        pattern.transactions.2.jito_tip_amount = 1_234;
+
+        assert!(insert_pattern(&conn, &pattern).unwrap());
+
+        let row: (String, String, String, String, i64, i64, i64, bool) = conn
+            .query_row(
+                "SELECT swap_in_signature, create_signature, token, attacker, block_height, sol_profit_lamports,
+                    jito_tip_lamports, via_jito_bundle
+                 FROM patterns WHERE swap_out_signature = ?1",
+                params!["a-sig3"],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                "a-sig1".to_string(),
+                "a-sig0".to_string(),
+                pattern.token.clone(),
+                "attacker".to_string(),
+                90,
+                // 0.099999999 SOL gross, less the tip and both fees, to the lamport
+                99_999_999 - 1_234 - 10_000,
+                1_234,
+                false,
+            )
+        );
+
+        // Re-analyzing the block stores nothing new
+        assert!(!insert_pattern(&conn, &pattern).unwrap());
+        let patterns: i64 = conn
+            .query_row("SELECT COUNT(*) FROM patterns", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(patterns, 1);
+    }
+
+    #[test]
+    fn a_retraction_is_recorded_and_removes_the_stored_pattern() {
+        let mut conn: Connection = memory_db();
+        let kept: Pattern = sandwich_pattern("a");
+        let reorged: Pattern = sandwich_pattern("b");
+        insert_pattern(&conn, &kept).unwrap();
+        insert_pattern(&conn, &reorged).unwrap();
+
+        retract_pattern(&mut conn, &Retraction::new(&reorged, 500, "reorged-hash")).unwrap();
+
+        let stored: Vec<String> = conn
+            .prepare("SELECT swap_out_signature FROM patterns")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(stored, ["a-sig3"]);
+
+        let retracted: (String, i64, String) = conn
+            .query_row(
+                "SELECT swap_out_signature, slot, blockhash FROM retractions",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(retracted, ("b-sig3".to_string(), 500, "reorged-hash".to_string()));
+    }
+
+    #[test]
+    fn refuses_to_resume_a_run_for_another_program() {
+        let conn: Connection = memory_db();
+        start_run(&conn, TARGET_PROGRAM, 100, 110).unwrap();
+
+        let run: Run = latest_run(&conn).unwrap().unwrap();
+        assert!(matches!(
+            run.resume_slot("11111111111111111111111111111111"),
+            Err(SandwichError::ResumeTargetMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,429 @@
+// Canned transactions, blocks and an in-memory block source for the unit tests, so detection can be exercised
+// end to end without any network access
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_config::{RpcBlockConfig, RpcTransactionConfig},
+};
+use solana_sdk::{
+    account::Account,
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::CompiledInstruction,
+    message::{
+        v0::{self, MessageAddressTableLookup},
+        Message, MessageHeader, VersionedMessage,
+    },
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::{TransactionVersion, VersionedTransaction},
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, EncodedTransactionWithStatusMeta,
+    TransactionBinaryEncoding, UiConfirmedBlock, UiTransactionTokenBalance,
+};
+
+use crate::source::BlockSource;
+use crate::types::{get_instruction_map, ClassifiedTransaction, Pattern, PatternTracker, TARGET_PROGRAM, WSOL_MINT};
+
+// A deterministic key for tests, distinct for each seed
+pub fn key(seed: u8) -> Pubkey {
+    Pubkey::new_from_array([seed; 32])
+}
+
+// The instruction data for one of the known lifecycle instructions, by name
+pub fn discriminator(name: &str) -> Vec<u8> {
+    let (discriminator, _) = get_instruction_map()
+        .into_iter()
+        .find(|(_, known)| *known == name)
+        .expect("unknown instruction name");
+
+    hex::decode(discriminator).unwrap()
+}
+
+// The System Program's address, the all-zero key
+pub fn system_program() -> Pubkey {
+    Pubkey::default()
+}
+
+// A System Program transfer of lamports between the given account indices, with the program at system_program
+pub fn transfer(from: u8, to: u8, system_program: u8, lamports: u64) -> CompiledInstruction {
+    let mut data: Vec<u8> = 2u32.to_le_bytes().to_vec();
+    data.extend(lamports.to_le_bytes());
+
+    CompiledInstruction::new_from_raw_parts(system_program, data, vec![from, to])
+}
+
+// A token balance entry as getBlock reports it, with the amount in base units
+pub fn token_balance(account_index: u8, mint: &str, owner: &Pubkey, amount: u64) -> UiTransactionTokenBalance {
+    serde_json::from_value(json!({
+        "accountIndex": account_index,
+        "mint": mint,
+        "owner": owner.to_string(),
+        "uiTokenAmount": {
+            "uiAmount": null,
+            "decimals": 6,
+            "amount": amount.to_string(),
+            "uiAmountString": amount.to_string(),
+        },
+    }))
+    .unwrap()
+}
+
+// A legacy transaction and the parts of its status meta the detector reads
+pub struct CannedTransaction {
+    pub signature: Signature,
+    pub account_keys: Vec<Pubkey>,
+    pub num_signers: u8,
+    pub instructions: Vec<CompiledInstruction>,
+    pub pre_balances: Vec<u64>,
+    pub post_balances: Vec<u64>,
+    pub pre_token_balances: Vec<UiTransactionTokenBalance>,
+    pub post_token_balances: Vec<UiTransactionTokenBalance>,
+    pub fee: u64,
+}
+
+impl CannedTransaction {
+    fn header(&self) -> MessageHeader {
+        MessageHeader {
+            num_required_signatures: self.num_signers,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 0,
+        }
+    }
+
+    // Encodes the transaction the way a base64 getBlock or getTransaction response carries it
+    pub fn encode(&self) -> EncodedTransactionWithStatusMeta {
+        let message: Message = Message {
+            header: self.header(),
+            account_keys: self.account_keys.clone(),
+            recent_blockhash: Hash::default(),
+            instructions: self.instructions.clone(),
+        };
+
+        self.encode_message(VersionedMessage::Legacy(message), json!(null))
+    }
+
+    // Encodes the transaction as a V0 message whose account keys go on past the static keys into the given
+    // addresses, loaded from a lookup table writable first, the way the RPC reports them in the meta
+    pub fn encode_with_lookup_table(
+        &self,
+        writable: &[Pubkey],
+        readonly: &[Pubkey],
+    ) -> EncodedTransactionWithStatusMeta {
+        let lookup: MessageAddressTableLookup = MessageAddressTableLookup {
+            account_key: key(250),
+            writable_indexes: (0..writable.len() as u8).collect(),
+            readonly_indexes: (0..readonly.len() as u8).map(|i| i + writable.len() as u8).collect(),
+        };
+        let message: v0::Message = v0::Message {
+            header: self.header(),
+            account_keys: self.account_keys.clone(),
+            recent_blockhash: Hash::default(),
+            instructions: self.instructions.clone(),
+            address_table_lookups: vec![lookup],
+        };
+        let to_strings = |keys: &[Pubkey]| keys.iter().map(|key| key.to_string()).collect::<Vec<String>>();
+
+        self.encode_message(
+            VersionedMessage::V0(message),
+            json!({ "writable": to_strings(writable), "readonly": to_strings(readonly) }),
+        )
+    }
+
+    fn encode_message(
+        &self,
+        message: VersionedMessage,
+        loaded_addresses: serde_json::Value,
+    ) -> EncodedTransactionWithStatusMeta {
+        let version: Option<TransactionVersion> = match message {
+            VersionedMessage::Legacy(_) => None,
+            VersionedMessage::V0(_) => Some(TransactionVersion::Number(0)),
+        };
+
+        let mut signatures: Vec<Signature> = vec![self.signature];
+        signatures.resize(self.num_signers.max(1) as usize, Signature::default());
+
+        let tx: VersionedTransaction = VersionedTransaction { signatures, message };
+
+        EncodedTransactionWithStatusMeta {
+            transaction: EncodedTransaction::Binary(
+                STANDARD.encode(bincode::serialize(&tx).unwrap()),
+                TransactionBinaryEncoding::Base64,
+            ),
+            meta: Some(
+                serde_json::from_value(json!({
+                    "err": null,
+                    "status": { "Ok": null },
+                    "fee": self.fee,
+                    "preBalances": self.pre_balances,
+                    "postBalances": self.post_balances,
+                    "innerInstructions": [],
+                    "logMessages": [],
+                    "preTokenBalances": self.pre_token_balances,
+                    "postTokenBalances": self.post_token_balances,
+                    "rewards": [],
+                    "loadedAddresses": loaded_addresses,
+                }))
+                .unwrap(),
+            ),
+            version,
+        }
+    }
+}
+
+// One swap between a signer and a pool, with the signer's token and wSOL changes in base units
+// The accounts are laid out as: signer, signer's token account, signer's wSOL account, pool token vault, pool wSOL
+// vault, the invoked program, then the sandwich account, which the swap instruction passes at positions 6 and 7
+pub struct Swap<'a> {
+    pub seed: u8,
+    pub signer: Pubkey,
+    pub pool: Pubkey,
+    pub mint: &'a str,
+    pub program: Pubkey,
+    pub data: Vec<u8>,
+    pub sandwich: Pubkey,
+    pub token_change: i64,
+    pub sol_change: i64,
+    pub tip: Option<(Pubkey, u64)>,
+}
+
+impl<'a> Swap<'a> {
+    // A swap leg of the sandwich program, by instruction name
+    pub fn leg(seed: u8, name: &str, signer: Pubkey, mint: &'a str, token_change: i64, sol_change: i64) -> Self {
+        Self {
+            seed,
+            signer,
+            pool: key(200),
+            mint,
+            program: TARGET_PROGRAM.parse().unwrap(),
+            data: discriminator(name),
+            sandwich: key(201),
+            token_change,
+            sol_change,
+            tip: None,
+        }
+    }
+
+    // A swap made directly against the pool by someone other than the bot
+    pub fn victim(seed: u8, signer: Pubkey, mint: &'a str, token_change: i64, sol_change: i64) -> Self {
+        Self {
+            program: key(202),
+            data: vec![9; 8],
+            ..Self::leg(seed, "AutoSwapIn", signer, mint, token_change, sol_change)
+        }
+    }
+
+    pub fn build(&self) -> CannedTransaction {
+        const SIGNER_TOKENS: u64 = 1_000_000_000;
+        const SIGNER_WSOL: u64 = 100_000_000_000;
+        const POOL_TOKENS: u64 = 1_000_000_000_000;
+        const POOL_WSOL: u64 = 500_000_000_000;
+
+        let shift = |balance: u64, change: i64| balance.checked_add_signed(change).unwrap();
+
+        let mut account_keys: Vec<Pubkey> = vec![
+            self.signer,
+            key(self.seed.wrapping_add(100)),
+            key(self.seed.wrapping_add(150)),
+            key(203),
+            key(204),
+            self.program,
+            self.sandwich,
+        ];
+        let mut accounts: Vec<u8> = vec![0, 1, 2, 3, 4, 0, 6, 6];
+        let mut pre_balances: Vec<u64> = vec![10_000_000_000, 2_039_280, 2_039_280, 2_039_280, 2_039_280, 1, 1];
+        let mut post_balances: Vec<u64> = pre_balances.clone();
+        post_balances[0] -= 5000;
+
+        let mut instructions: Vec<CompiledInstruction> = Vec::new();
+
+        // The tip is a System Program transfer from the signer, after the swap
+        if let Some((tip_account, tip)) = self.tip {
+            account_keys.extend([tip_account, system_program()]);
+            accounts.push(7);
+            pre_balances.extend([1_000_000, 1]);
+            post_balances.extend([1_000_000 + tip, 1]);
+            post_balances[0] -= tip;
+            instructions.push(transfer(0, 7, 8, tip));
+        }
+
+        instructions.insert(
+            0,
+            CompiledInstruction::new_from_raw_parts(5, self.data.clone(), accounts),
+        );
+
+        CannedTransaction {
+            signature: Signature::from([self.seed; 64]),
+            account_keys,
+            num_signers: 1,
+            instructions,
+            pre_balances,
+            post_balances,
+            pre_token_balances: vec![
+                token_balance(1, self.mint, &self.signer, SIGNER_TOKENS),
+                token_balance(2, WSOL_MINT, &self.signer, SIGNER_WSOL),
+                token_balance(3, self.mint, &self.pool, POOL_TOKENS),
+                token_balance(4, WSOL_MINT, &self.pool, POOL_WSOL),
+            ],
+            post_token_balances: vec![
+                token_balance(1, self.mint, &self.signer, shift(SIGNER_TOKENS, self.token_change)),
+                token_balance(2, WSOL_MINT, &self.signer, shift(SIGNER_WSOL, self.sol_change)),
+                token_balance(3, self.mint, &self.pool, shift(POOL_TOKENS, -self.token_change)),
+                token_balance(4, WSOL_MINT, &self.pool, shift(POOL_WSOL, -self.sol_change)),
+            ],
+            fee: 5000,
+        }
+    }
+}
+
+// The bot's CreateSandwichV2, which passes the sandwich account third
+pub fn create(seed: u8, signer: Pubkey) -> CannedTransaction {
+    CannedTransaction {
+        signature: Signature::from([seed; 64]),
+        account_keys: vec![signer, TARGET_PROGRAM.parse().unwrap(), key(201)],
+        num_signers: 1,
+        instructions: vec![CompiledInstruction::new_from_raw_parts(
+            1,
+            discriminator("CreateSandwichV2"),
+            vec![0, 0, 2],
+        )],
+        pre_balances: vec![10_000_000_000, 1, 1],
+        post_balances: vec![9_999_995_000, 1, 1],
+        pre_token_balances: Vec::new(),
+        post_token_balances: Vec::new(),
+        fee: 5000,
+    }
+}
+
+// The bot key, victim key, and mint of sandwich_transactions
+pub const BOT: u8 = 1;
+pub const VICTIM: u8 = 2;
+pub const MINT: &str = "Mint111111111111111111111111111111111111111";
+
+// A complete sandwich: the bot's create and front-run, a victim's buy of 20,000 tokens for 1 SOL, then the bot's
+// back-run, which sells the 50,000 tokens it bought for 2 SOL at a 0.1 SOL gain
+pub fn sandwich_transactions() -> Vec<EncodedTransactionWithStatusMeta> {
+    vec![
+        create(10, key(BOT)).encode(),
+        Swap::leg(11, "AutoSwapIn", key(BOT), MINT, 50_000, -2_000_000_000)
+            .build()
+            .encode(),
+        Swap::victim(12, key(VICTIM), MINT, 20_000, -1_000_000_000)
+            .build()
+            .encode(),
+        Swap::leg(13, "AutoSwapOut", key(BOT), MINT, -50_000, 2_100_000_000)
+            .build()
+            .encode(),
+    ]
+}
+
+// A completed pattern on its own sandwich account, whose legs are otherwise empty
+pub fn sandwich_pattern(sandwich_acc: &str) -> Pattern {
+    let leg = |instruction_type: &str, tx_index: usize| ClassifiedTransaction {
+        signature: format!("{}-sig{}", sandwich_acc, tx_index),
+        signer: "attacker".to_string(),
+        slot: 100,
+        tx_index,
+        block_height: 90,
+        instruction_type: instruction_type.to_string(),
+        sandwich_acc: sandwich_acc.to_string(),
+        from_mint: MINT.to_string(),
+        ..ClassifiedTransaction::new()
+    };
+
+    let mut tracker: PatternTracker = PatternTracker::new();
+    tracker.process_transaction(leg("CreateSandwichV2", 0));
+    tracker.process_transaction(leg("AutoSwapIn", 1));
+    tracker.process_transaction(leg("AutoSwapOut", 3));
+
+    tracker.take_completed().remove(0)
+}
+
+// A block holding the given transactions in order
+pub fn block(slot: u64, transactions: Vec<EncodedTransactionWithStatusMeta>) -> UiConfirmedBlock {
+    UiConfirmedBlock {
+        previous_blockhash: Hash::default().to_string(),
+        blockhash: Hash::new_from_array([slot as u8; 32]).to_string(),
+        parent_slot: slot - 1,
+        transactions: Some(transactions),
+        signatures: None,
+        rewards: None,
+        num_reward_partitions: None,
+        block_time: Some(1_700_000_000 + slot as i64),
+        block_height: Some(slot - 10),
+    }
+}
+
+// Serves canned blocks, transactions and accounts, failing any request for something it wasn't given
+#[derive(Default)]
+pub struct StubSource {
+    pub slot: u64,
+    // Number of getMultipleAccounts requests made so far
+    pub multiple_accounts_requests: AtomicUsize,
+    pub blocks: HashMap<u64, UiConfirmedBlock>,
+    // Each transaction with the slot and block time of the block it landed in
+    pub transactions: HashMap<Signature, (u64, Option<i64>, EncodedTransactionWithStatusMeta)>,
+    pub accounts: HashMap<Pubkey, Account>,
+}
+
+impl StubSource {
+    fn missing(what: String) -> ClientError {
+        ClientErrorKind::Custom(format!("{} isn't in the stub source", what)).into()
+    }
+
+    // Makes each of the block's transactions fetchable by signature
+    pub fn add_block(&mut self, slot: u64, block: UiConfirmedBlock) {
+        for tx in block.transactions.iter().flatten() {
+            let signature: Signature = tx.transaction.decode().unwrap().signatures[0];
+            self.transactions
+                .insert(signature, (slot, block.block_time, tx.clone()));
+        }
+
+        self.slot = self.slot.max(slot);
+        self.blocks.insert(slot, block);
+    }
+}
+
+impl BlockSource for StubSource {
+    fn get_slot(&self, _commitment: CommitmentConfig) -> Result<u64, ClientError> {
+        Ok(self.slot)
+    }
+
+    fn get_block_with_config(&self, slot: u64, _config: RpcBlockConfig) -> Result<UiConfirmedBlock, ClientError> {
+        self.blocks
+            .get(&slot)
+            .cloned()
+            .ok_or_else(|| Self::missing(format!("Block {}", slot)))
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        self.multiple_accounts_requests.fetch_add(1, Ordering::SeqCst);
+        Ok(pubkeys
+            .iter()
+            .map(|pubkey| self.accounts.get(pubkey).cloned())
+            .collect())
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        _config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        let (slot, block_time, transaction) = self
+            .transactions
+            .get(signature)
+            .ok_or_else(|| Self::missing(format!("Transaction {}", signature)))?;
+
+        Ok(EncodedConfirmedTransactionWithStatusMeta {
+            slot: *slot,
+            transaction: transaction.clone(),
+            block_time: *block_time,
+        })
+    }
+}
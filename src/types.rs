@@ -1,10 +1,33 @@
 use chrono::{DateTime, Utc};
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::error::{parse_pubkey, SandwichError};
 
 pub const MIN_JITO_TIP: u64 = 1000;
+// Net SOL profit (in SOL) below which a self-sandwich is considered volume inflation rather than extraction
+pub const WASH_PROFIT_TOLERANCE: f64 = 0.001;
 pub const TARGET_PROGRAM: &str = "vpeNALD89BZ4KxNUFjdLmFXBCwtyqBDQ85ouNoax38b";
+// The target program's holding account, whose swaps move the bot's inventory rather than attack anyone
+pub const HOLDING_ACCOUNT: &str = "DKLvbSugkGMf4PBMakfHW9BdvcYj7Y7FRbsiL6v5DRy2";
 pub const WSOL_MINT: &str = "So11111111111111111111111111111111111111112";
+pub const TOKEN_PROGRAM: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const TOKEN_2022_PROGRAM: &str = "TokenzQdBNbLqP5VEhdkAxS6EPFLC1PQnbrnMbR4cR";
+// Longest plausible time (in seconds) between a pattern's create and swap-out
+pub const DEFAULT_MAX_TIME_SPAN_SECS: u64 = 60;
+// Slots the swap-out may land after the create; 0 requires the whole sandwich in one block
+pub const DEFAULT_MAX_BLOCK_GAP: u64 = 0;
+// Slots an open or in-progress sandwich is kept waiting for its next leg before prune_older_than evicts it
+pub const DEFAULT_MAX_PENDING_SLOTS: u64 = 150;
+// Decimals assumed for display until a mint's real decimals are fetched; amounts are kept in base units regardless
+pub const DEFAULT_DECIMALS: u8 = 9;
 
 pub const JITO_TIP_ADDRESSES: [&str; 8] = [
     "96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5",
@@ -27,8 +50,8 @@ pub fn get_instruction_map() -> HashMap<&'static str, &'static str> {
     m.insert("b3ecc1a00df8fe9a", "CreateSandwichV2");
     m.insert("5bb527f9eccb5e90", "AutoSwapIn");
     m.insert("b024faebda2bde25", "AutoSwapOut");
-    // m.insert("14d812f9d70bd653", "Cashout");
-    // m.insert("ea200c477e05dba0", "Exit");
+    m.insert("14d812f9d70bd653", "Cashout");
+    m.insert("ea200c477e05dba0", "Exit");
     // m.insert("7edb0b2a6825518b", "ExitPrice");
     // m.insert("55e5a4f78f5c0591", "ExitInactivity");
     // m.insert("b404ba74bbf3e278", "MigrateTokenData");
@@ -36,12 +59,23 @@ pub fn get_instruction_map() -> HashMap<&'static str, &'static str> {
     m
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct ClassifiedTransaction {
     pub signature: String,
     pub signer: String,
+    // Every required signer of the transaction, in order, so co-signers and relayers aren't lost
+    pub signers: Vec<String>,
+    // The account that paid the transaction fee, which is always the first account key
+    pub fee_payer: String,
+    // Slot of the block the transaction landed in, which is always known even when the block height isn't
+    pub slot: u64,
+    // Position of the transaction within its block, since every transaction in a block shares its block time
+    // 0 when the transaction was classified on its own rather than from a block
+    pub tx_index: usize,
+    // 0 if the RPC didn't report the block's height; only reported, since ordering, gaps and pruning go by slot
     pub block_height: u64,
-    pub block_time: Option<u64>,
+    // Unix timestamp of the block, as reported by the RPC
+    pub block_time: Option<i64>,
     pub instruction_type: String,
     pub sandwich_acc: String,
     pub swapper: String,
@@ -50,9 +84,26 @@ pub struct ClassifiedTransaction {
     pub from_amount: u64,
     pub to_amount: u64,
     pub jito_tip_amount: u64,
-    pub wsol_change: Option<f64>,
+    // Whether the signer's own lamports funded the Jito tip, i.e. the tip is already part of lamport_change
+    pub tip_paid_by_signer: bool,
+    // wSOL moved by the swap in lamports, positive when the bot received SOL and negative when it paid
+    // Kept as an integer so it's exact; converted to SOL only for display
+    pub wsol_change: Option<i128>,
     pub lamport_change: i64,
+    // Transaction fee paid, including any priority fee, in lamports
+    pub fee: u64,
+    // The transaction reverted, so none of its changes besides the fee took effect
+    pub failed: bool,
+    // The signer's native SOL and wSOL deltas combined, in lamports, if balances were available
+    pub sol_equivalent_change: Option<i64>,
     pub decimals: u8,
+    // False until the mint's real decimals have been fetched, in which case decimals is only a default
+    pub decimals_resolved: bool,
+    pub reserves: Option<PoolReserves>,
+    // Owner of the pool's token reserve account, identifying the pool that was swapped against
+    pub pool: Option<String>,
+    // Every non-wSOL mint the swap moved through, largest first, so multi-hop routes keep their other tokens
+    pub mint_changes: Vec<MintChange>,
 }
 
 impl ClassifiedTransaction {
@@ -60,6 +111,10 @@ impl ClassifiedTransaction {
         ClassifiedTransaction {
             signature: String::new(),
             signer: String::new(),
+            signers: Vec::new(),
+            fee_payer: String::new(),
+            slot: 0,
+            tx_index: 0,
             block_height: 0,
             block_time: None,
             instruction_type: String::new(),
@@ -70,11 +125,95 @@ impl ClassifiedTransaction {
             from_amount: 0,
             to_amount: 0,
             jito_tip_amount: 0,
+            tip_paid_by_signer: false,
             wsol_change: None,
             lamport_change: 0,
-            decimals: 9, // Default to 9
+            fee: 0,
+            failed: false,
+            sol_equivalent_change: None,
+            decimals: DEFAULT_DECIMALS,
+            decimals_resolved: false,
+            reserves: None,
+            pool: None,
+            mint_changes: Vec::new(),
+        }
+    }
+
+    // Fills in the token accounting of a swap leg; other instructions keep the empty defaults from new()
+    pub fn apply_swap_info(&mut self, swap_info: SwapInfo) {
+        self.swapper = swap_info.swapper;
+        self.from_mint = swap_info.from_mint;
+        self.to_mint = swap_info.to_mint;
+        self.from_amount = swap_info.from_amount;
+        self.to_amount = swap_info.to_amount;
+        self.wsol_change = swap_info.wsol_change;
+        self.decimals = swap_info.decimals;
+        self.reserves = swap_info.reserves;
+        self.pool = swap_info.pool;
+        self.mint_changes = swap_info.mint_changes;
+    }
+
+    // Moves the leg onto another mint it swapped through, e.g. the sandwiched hop of a multi-hop route, along with
+    // that mint's decimals, which fall back to the default if they weren't resolved
+    // Returns false, leaving the leg unchanged, if the swap didn't move that mint
+    pub fn focus_on_mint(&mut self, mint: &str) -> bool {
+        match self.mint_changes.iter().find(|change| change.mint == mint) {
+            Some(change) => {
+                self.from_mint = change.mint.clone();
+                self.to_mint = change.mint.clone();
+                self.from_amount = change.from_amount;
+                self.to_amount = change.to_amount;
+                self.decimals = change.decimals.unwrap_or(DEFAULT_DECIMALS);
+                self.decimals_resolved = change.decimals.is_some();
+                true
+            }
+            None => false,
         }
     }
+
+    // Where the transaction landed on chain: its slot, then its position within the block
+    pub fn position(&self) -> (u64, usize) {
+        (self.slot, self.tx_index)
+    }
+
+    // The Jito tip not already part of the signer's balance deltas, in lamports
+    // lamport_change (and so sol_equivalent_change) is the signer's post - pre balance, which already includes the
+    // transaction fee and, when the signer funded it, the tip; only a tip paid by another account is a cost on top
+    pub fn tip_outside_balance(&self) -> i64 {
+        if self.tip_paid_by_signer {
+            0
+        } else {
+            self.jito_tip_amount as i64
+        }
+    }
+
+    // Slots from this leg to a later one
+    // Slots overcount blocks when slots in between were skipped, but unlike block heights they're always known, so
+    // they never collapse distinct blocks into one
+    pub fn slots_until(&self, later: &ClassifiedTransaction) -> u64 {
+        later.slot.saturating_sub(self.slot)
+    }
+
+    // Returns the block time as a UTC datetime, if it's known and in range
+    pub fn datetime(&self) -> Option<DateTime<Utc>> {
+        DateTime::<Utc>::from_timestamp(self.block_time?, 0)
+    }
+}
+
+impl Default for ClassifiedTransaction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A mint moved by a swap instruction: the largest decrease and increase among its token accounts, in base units
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MintChange {
+    pub mint: String,
+    pub from_amount: u64,
+    pub to_amount: u64,
+    // The mint's decimals, once they've been fetched
+    pub decimals: Option<u8>,
 }
 
 #[derive(Debug, Serialize)]
@@ -84,8 +223,12 @@ pub struct SwapInfo {
     pub to_mint: String,
     pub from_amount: u64,
     pub to_amount: u64,
-    pub wsol_change: Option<f64>,
+    // In lamports, positive when the bot received SOL
+    pub wsol_change: Option<i128>,
     pub decimals: u8,
+    pub reserves: Option<PoolReserves>,
+    pub pool: Option<String>,
+    pub mint_changes: Vec<MintChange>,
 }
 
 impl SwapInfo {
@@ -97,24 +240,393 @@ impl SwapInfo {
             from_amount: 0,
             to_amount: 0,
             wsol_change: None,
-            decimals: 9, // Default to 9
+            decimals: DEFAULT_DECIMALS,
+            reserves: None,
+            pool: None,
+            mint_changes: Vec::new(),
+        }
+    }
+}
+
+impl Default for SwapInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Converts a base-unit amount to UI units for a token with the given decimals
+pub fn ui_amount(amount: i128, decimals: u8) -> f64 {
+    amount as f64 / 10_f64.powi(decimals as i32)
+}
+
+// How token amounts are presented in output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmountUnit {
+    // Raw integer amounts as stored on-chain
+    BaseUnits,
+    // Decimals-adjusted amounts, as shown in wallets and explorers
+    #[default]
+    Ui,
+}
+
+impl AmountUnit {
+    // Formats a base-unit amount for a token with the given decimals
+    pub fn format(&self, amount: i128, decimals: u8) -> String {
+        match self {
+            AmountUnit::BaseUnits => amount.to_string(),
+            AmountUnit::Ui => format!("{:.*}", decimals as usize, ui_amount(amount, decimals)),
+        }
+    }
+
+    // The amount as a JSON value: an integer in base units, or a decimal in UI units
+    pub fn to_json_value(&self, amount: i128, decimals: u8) -> serde_json::Value {
+        match self {
+            AmountUnit::BaseUnits => serde_json::json!(amount),
+            AmountUnit::Ui => serde_json::json!(ui_amount(amount, decimals)),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AmountUnit::BaseUnits => "base units",
+            AmountUnit::Ui => "tokens",
+        }
+    }
+}
+
+impl FromStr for AmountUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "base" | "base-units" | "raw" => Ok(AmountUnit::BaseUnits),
+            "ui" | "tokens" => Ok(AmountUnit::Ui),
+            _ => Err(format!("Unknown amount unit '{}', expected 'base' or 'ui'", s)),
+        }
+    }
+}
+
+// Which balance changes a pattern's SOL profit is computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolAccounting {
+    // wSOL moved by the swap legs, minus the swap-out's Jito tip and the pattern's transaction fees
+    #[default]
+    Separate,
+    // The attacker's combined native SOL and wSOL delta on each swap leg, which includes actual fees and tips
+    Unified,
+}
+
+impl FromStr for SolAccounting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "separate" | "wsol" => Ok(SolAccounting::Separate),
+            "unified" | "combined" => Ok(SolAccounting::Unified),
+            _ => Err(format!(
+                "Unknown SOL accounting '{}', expected 'separate' or 'unified'",
+                s
+            )),
+        }
+    }
+}
+
+// Size of the base mint layout shared by SPL Token and Token-2022
+pub const MINT_BASE_LEN: usize = 82;
+// Token-2022 pads extended mints to the token account size, then stores an account type byte before the TLV data
+const TOKEN_2022_ACCOUNT_TYPE_OFFSET: usize = 165;
+const TOKEN_2022_ACCOUNT_TYPE_MINT: u8 = 1;
+
+// Where a mint account stores its decimals byte
+// Both SPL Token and Token-2022 share the same 82-byte base mint layout with decimals at offset 44;
+// Token-2022 mints may be followed by TLV extensions, which don't move the decimals field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MintLayout {
+    #[default]
+    SplToken,
+    Token2022,
+    // A nonstandard deployment storing decimals at the given byte offset
+    Custom {
+        decimals_offset: usize,
+    },
+}
+
+impl MintLayout {
+    pub fn decimals_offset(&self) -> usize {
+        match self {
+            MintLayout::SplToken | MintLayout::Token2022 => 44,
+            MintLayout::Custom { decimals_offset } => *decimals_offset,
+        }
+    }
+
+    // Picks the layout for a mint owned by the given program
+    // A custom layout is kept as is, since it describes a nonstandard deployment the owner can't identify
+    pub fn for_owner(&self, owner: &str) -> Result<MintLayout, SandwichError> {
+        match (self, owner) {
+            (MintLayout::Custom { .. }, _) => Ok(*self),
+            (_, TOKEN_PROGRAM) => Ok(MintLayout::SplToken),
+            (_, TOKEN_2022_PROGRAM) => Ok(MintLayout::Token2022),
+            _ => Err(SandwichError::MalformedAccountData(format!(
+                "Account is owned by {}, not a token program",
+                owner
+            ))),
         }
     }
+
+    // Reads the decimals from raw mint account data, validating it's long enough for this layout
+    // Standard layouts are also checked to actually be an initialized mint rather than a token account
+    pub fn parse_decimals(&self, account_data: &[u8]) -> Result<u8, SandwichError> {
+        match self {
+            MintLayout::SplToken if account_data.len() != MINT_BASE_LEN => {
+                return Err(SandwichError::MalformedAccountData(format!(
+                    "Account data is {} bytes, expected a {}-byte SPL Token mint",
+                    account_data.len(),
+                    MINT_BASE_LEN
+                )));
+            }
+            MintLayout::Token2022 => Self::check_token_2022_mint(account_data)?,
+            _ => {}
+        }
+
+        let offset: usize = self.decimals_offset();
+
+        account_data.get(offset).copied().ok_or_else(|| {
+            SandwichError::MalformedAccountData(format!(
+                "Mint account data is {} bytes, too short to read decimals at offset {}",
+                account_data.len(),
+                offset
+            ))
+        })
+    }
+
+    // A Token-2022 mint is either the bare base layout or, with extensions, padded past the token account size
+    // and tagged with the mint account type
+    fn check_token_2022_mint(account_data: &[u8]) -> Result<(), SandwichError> {
+        if account_data.len() == MINT_BASE_LEN {
+            return Ok(());
+        }
+
+        match account_data.get(TOKEN_2022_ACCOUNT_TYPE_OFFSET) {
+            Some(&TOKEN_2022_ACCOUNT_TYPE_MINT) => Ok(()),
+            Some(account_type) => Err(SandwichError::MalformedAccountData(format!(
+                "Token-2022 account has type {}, not a mint",
+                account_type
+            ))),
+            None => Err(SandwichError::MalformedAccountData(format!(
+                "Account data is {} bytes, not a valid Token-2022 mint",
+                account_data.len()
+            ))),
+        }
+    }
+}
+
+impl FromStr for MintLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "spl" | "spl-token" | "token" => Ok(MintLayout::SplToken),
+            "token-2022" | "token2022" => Ok(MintLayout::Token2022),
+            other => other
+                .parse::<usize>()
+                .map(|decimals_offset| MintLayout::Custom { decimals_offset })
+                .map_err(|_| {
+                    format!(
+                        "Unknown mint layout '{}', expected 'spl', 'token-2022', or a byte offset",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+// Deterministic probabilistic sampling of slots, for estimating aggregates over large ranges
+// Each slot is kept or skipped based on a hash of (seed, slot), so the same seed always picks the same slots
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlotSampling {
+    // Fraction of slots to keep, in (0, 1]
+    pub rate: f64,
+    pub seed: u64,
+}
+
+impl SlotSampling {
+    pub fn new(rate: f64, seed: u64) -> Result<Self, String> {
+        if !(rate > 0.0 && rate <= 1.0) {
+            return Err(format!("Sample rate must be in (0, 1], got {}", rate));
+        }
+
+        Ok(Self { rate, seed })
+    }
+
+    pub fn includes(&self, slot: u64) -> bool {
+        // splitmix64 finalizer, mapped onto [0, 1)
+        let mut z: u64 = slot ^ self.seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < self.rate
+    }
+
+    // Scales a count observed over the sampled slots up to the full range
+    pub fn estimate(&self, sampled_count: usize) -> f64 {
+        sampled_count as f64 / self.rate
+    }
+}
+
+// Pool vault balances (in base units) observed before a swap executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PoolReserves {
+    pub token_reserve: u64,
+    pub sol_reserve: u64,
 }
 
+impl PoolReserves {
+    // Estimates the relative move in the pool's spot price caused by swapping amount_in into it
+    // Assumes a constant-product (x * y = k) pool and ignores swap fees, so the spot price
+    // scales by (x / (x + dx))^2 and the impact is 1 minus that ratio
+    pub fn price_impact(&self, amount_in: u64, sol_in: bool) -> Option<f64> {
+        let reserve_in: u64 = if sol_in { self.sol_reserve } else { self.token_reserve };
+        let reserve_out: u64 = if sol_in { self.token_reserve } else { self.sol_reserve };
+
+        if reserve_in == 0 || reserve_out == 0 {
+            return None;
+        }
+
+        let ratio: f64 = reserve_in as f64 / (reserve_in as f64 + amount_in as f64);
+
+        Some(1.0 - ratio * ratio)
+    }
+
+    // Output of swapping amount_in into a constant-product pool, ignoring swap fees: dy = y * dx / (x + dx)
+    pub fn amount_out(&self, amount_in: u64, sol_in: bool) -> Option<u64> {
+        let reserve_in: u64 = if sol_in { self.sol_reserve } else { self.token_reserve };
+        let reserve_out: u64 = if sol_in { self.token_reserve } else { self.sol_reserve };
+
+        if reserve_in == 0 || reserve_out == 0 {
+            return None;
+        }
+
+        let out: u128 = reserve_out as u128 * amount_in as u128 / (reserve_in as u128 + amount_in as u128);
+
+        Some(out as u64)
+    }
+
+    // Reserves after swapping amount_in into the pool, under the same assumptions as amount_out
+    pub fn after_swap(&self, amount_in: u64, sol_in: bool) -> Option<PoolReserves> {
+        let out: u64 = self.amount_out(amount_in, sol_in)?;
+
+        Some(if sol_in {
+            PoolReserves {
+                token_reserve: self.token_reserve - out,
+                sol_reserve: self.sol_reserve.saturating_add(amount_in),
+            }
+        } else {
+            PoolReserves {
+                token_reserve: self.token_reserve.saturating_add(amount_in),
+                sol_reserve: self.sol_reserve - out,
+            }
+        })
+    }
+}
+
+// A victim's buy of the pattern's token that landed between the attacker's swap-in and swap-out
+#[derive(Debug, Clone, Serialize)]
+pub struct VictimSwap {
+    pub signature: String,
+    pub signer: String,
+    // Every key that signed the victim's transaction, starting with the signer
+    pub signers: Vec<String>,
+    // The victim's token account that received the token
+    pub token_account: String,
+    // SOL spent on the swap in lamports, excluding the transaction fee
+    pub sol_in: u64,
+    // Tokens actually received, in base units
+    pub tokens_received: u64,
+}
+
+// What a victim lost to the front-run, compared to executing against the pool without it
+#[derive(Debug, Clone, Serialize)]
+pub struct VictimLoss {
+    pub signature: String,
+    pub token_account: String,
+    // Tokens the victim would have received without the front-run, in base units
+    pub counterfactual_tokens: u64,
+    pub token_loss: u64,
+    // The token loss valued at the victim's counterfactual execution price, in SOL
+    pub sol_loss: f64,
+}
+
+// Whether a create -> in -> out triple actually sandwiched someone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PatternKind {
+    // Another party traded the same token between the swap-in and swap-out
+    Sandwich,
+    // A round-trip with no identifiable victim between the legs, e.g. pure arbitrage
+    Arbitrage,
+}
+
+#[derive(Clone)]
 pub struct Pattern {
+    pub kind: PatternKind,
     pub token: String,
     pub attacker: String,
     pub swapper: Option<String>,
+    // The pool the front-run swapped against, if it could be identified
+    pub pool: Option<String>,
+    // Pool reserves right before the front-run, if they could be reconstructed
+    pub reserves: Option<PoolReserves>,
+    // Set when the pattern looks like the attacker sandwiching its own trades to inflate volume, once its victims
+    // are known
+    pub likely_wash: bool,
+    // Known bot name or operator for the attacker, from a BotRegistry
+    pub attacker_label: Option<String>,
+    // Whether the sandwich appears to have landed through a Jito bundle
+    pub via_jito_bundle: bool,
+    // How get_sol_profit measures the attacker's SOL profit
+    pub sol_accounting: SolAccounting,
+    // The (create, swap-in, swap-out) legs; prefer the create_tx, swap_in_tx, and swap_out_tx accessors
     pub transactions: (ClassifiedTransaction, ClassifiedTransaction, ClassifiedTransaction),
+    // Swap-ins on the same sandwich account after the first one, before the swap-out
+    pub additional_swap_ins: Vec<ClassifiedTransaction>,
+    // Buys of the token by other parties between the swap-in and swap-out, in block order
+    pub victims: Vec<VictimSwap>,
+    // Most blocks the swap-out may land after the create for the pattern to be valid
+    pub max_block_gap: u64,
+    // SOL/USD price used to value the profit, from a SolPriceSource
+    pub sol_price_usd: Option<f64>,
+    // The Cashout or Exit that withdrew the sandwich account's proceeds, if it was seen
+    pub cashout_tx: Option<ClassifiedTransaction>,
+    // The token's symbol from its metadata, if it was looked up and has one
+    pub token_symbol: Option<String>,
+}
+
+// Finds the mint both swap legs moved, preferring the swap-in's largest change, then the swap-in's other hops in
+// order of size
+// Returns None if the legs share no mint or either lacks mint changes, in which case the legs are left as classified
+fn shared_mint(swap_in_tx: &ClassifiedTransaction, swap_out_tx: &ClassifiedTransaction) -> Option<String> {
+    if swap_in_tx.from_mint == swap_out_tx.from_mint {
+        return None;
+    }
+
+    let swaps_out = |mint: &str| swap_out_tx.mint_changes.iter().any(|change| change.mint == mint);
+
+    swap_in_tx
+        .mint_changes
+        .iter()
+        .map(|change| change.mint.as_str())
+        .find(|mint| swaps_out(mint))
+        .map(String::from)
 }
 
 impl Pattern {
     // Creates a new pattern from its component transactions
     pub fn new(
         create_tx: ClassifiedTransaction,
-        swap_in_tx: ClassifiedTransaction,
-        swap_out_tx: ClassifiedTransaction,
+        mut swap_in_tx: ClassifiedTransaction,
+        mut swap_out_tx: ClassifiedTransaction,
     ) -> Option<Self> {
         // Validate that all transactions have the same sandwich_acc
         if create_tx.sandwich_acc != swap_in_tx.sandwich_acc || swap_in_tx.sandwich_acc != swap_out_tx.sandwich_acc {
@@ -122,9 +634,26 @@ impl Pattern {
         }
 
         // Validate the proper transaction sequence
-        if create_tx.block_time > swap_in_tx.block_time || swap_in_tx.block_time > swap_out_tx.block_time {
+        // Slots and positions within the block are always known, so they order the legs; legs in one transaction share
+        // a position and are in instruction order already
+        // Block times are only compared where both legs have one, since a missing time would otherwise sort before
+        // every known one
+        if create_tx.position() > swap_in_tx.position() || swap_in_tx.position() > swap_out_tx.position() {
             return None;
         }
+        if !times_in_order(create_tx.block_time, swap_in_tx.block_time)
+            || !times_in_order(swap_in_tx.block_time, swap_out_tx.block_time)
+            || !times_in_order(create_tx.block_time, swap_out_tx.block_time)
+        {
+            return None;
+        }
+
+        // On multi-hop routes the largest change in each leg can be a different hop, so both legs are moved onto the
+        // mint they share
+        if let Some(mint) = shared_mint(&swap_in_tx, &swap_out_tx) {
+            swap_in_tx.focus_on_mint(&mint);
+            swap_out_tx.focus_on_mint(&mint);
+        }
 
         // Get the proper token from the swap transactions
         let token: String = if !swap_in_tx.from_mint.is_empty() {
@@ -135,12 +664,113 @@ impl Pattern {
             return None;
         };
 
-        Some(Self {
+        let mut pattern: Self = Self {
+            kind: PatternKind::Sandwich,
             token,
             attacker: create_tx.signer.clone(),
+            attacker_label: None,
             swapper: Some(swap_in_tx.swapper.clone()),
+            pool: swap_in_tx.pool.clone(),
+            reserves: swap_in_tx.reserves,
+            likely_wash: false,
+            via_jito_bundle: false,
+            sol_accounting: SolAccounting::default(),
             transactions: (create_tx, swap_in_tx, swap_out_tx),
-        })
+            additional_swap_ins: Vec::new(),
+            victims: Vec::new(),
+            max_block_gap: DEFAULT_MAX_BLOCK_GAP,
+            sol_price_usd: None,
+            cashout_tx: None,
+            token_symbol: None,
+        };
+        pattern.via_jito_bundle = pattern.detect_jito_bundle();
+
+        Some(pattern)
+    }
+
+    /// The CreateSandwichV2 transaction that opened the sandwich account
+    ///
+    /// The legs are also available by role through `swap_in_tx` and `swap_out_tx`, so callers don't depend on the
+    /// order they're stored in:
+    ///
+    /// ```
+    /// use sandwich_detector::types::{ClassifiedTransaction, Pattern, PatternTracker};
+    ///
+    /// let leg = |instruction_type: &str, tx_index: usize| ClassifiedTransaction {
+    ///     signature: format!("sig{}", tx_index),
+    ///     signer: "attacker".to_string(),
+    ///     slot: 100,
+    ///     tx_index,
+    ///     instruction_type: instruction_type.to_string(),
+    ///     sandwich_acc: "sandwich".to_string(),
+    ///     from_mint: "mint".to_string(),
+    ///     ..ClassifiedTransaction::new()
+    /// };
+    ///
+    /// let mut tracker = PatternTracker::new();
+    /// tracker.process_transaction(leg("CreateSandwichV2", 0));
+    /// tracker.process_transaction(leg("AutoSwapIn", 1));
+    /// tracker.process_transaction(leg("AutoSwapOut", 3));
+    ///
+    /// let pattern: Pattern = tracker.take_completed().remove(0);
+    /// assert_eq!(pattern.create_tx().signature, "sig0");
+    /// assert_eq!(pattern.swap_in_tx().signature, "sig1");
+    /// assert_eq!(pattern.swap_out_tx().signature, "sig3");
+    /// ```
+    pub fn create_tx(&self) -> &ClassifiedTransaction {
+        &self.transactions.0
+    }
+
+    /// The attacker's front-running AutoSwapIn transaction
+    pub fn swap_in_tx(&self) -> &ClassifiedTransaction {
+        &self.transactions.1
+    }
+
+    /// The attacker's back-running AutoSwapOut transaction
+    pub fn swap_out_tx(&self) -> &ClassifiedTransaction {
+        &self.transactions.2
+    }
+
+    // Whether the profit was withdrawn from the sandwich account, rather than only on paper
+    pub fn is_realized(&self) -> bool {
+        self.cashout_tx.is_some()
+    }
+
+    // Returns true if every leg landed in the same slot and at least one of them tipped a Jito tip account
+    // A bundle lands atomically in one slot and only needs a single tip, so a tip alone isn't enough: legs spread
+    // over several slots were sent separately, whichever of them tipped
+    fn detect_jito_bundle(&self) -> bool {
+        let (create_tx, swap_in_tx, swap_out_tx) = &self.transactions;
+        let legs: Vec<&ClassifiedTransaction> = [create_tx, swap_in_tx, swap_out_tx]
+            .into_iter()
+            .chain(&self.additional_swap_ins)
+            .collect();
+
+        legs.iter().all(|tx| tx.slot == create_tx.slot) && legs.iter().any(|tx| tx.jito_tip_amount > 0)
+    }
+
+    // Returns true if a victim's transaction was signed by one of the attacker's own signers and the net profit is
+    // negligible, i.e. the attacker sandwiched itself to inflate volume
+    // Needs the pattern's victims, so it's only meaningful once they've been found
+    pub fn detect_wash(&self) -> bool {
+        let (create_tx, swap_in_tx, swap_out_tx) = &self.transactions;
+
+        let authorities: HashSet<&str> = [create_tx, swap_in_tx, swap_out_tx]
+            .into_iter()
+            .flat_map(|tx| tx.signers.iter().chain([&tx.signer, &tx.fee_payer]))
+            .chain([&self.attacker])
+            .filter(|key| !key.is_empty())
+            .map(|key| key.as_str())
+            .collect();
+
+        let self_sandwiched: bool = self.victims.iter().any(|victim| {
+            victim
+                .signers
+                .iter()
+                .any(|signer| authorities.contains(signer.as_str()))
+        });
+
+        self_sandwiched && self.get_sol_profit().abs() < WASH_PROFIT_TOLERANCE
     }
 
     // Returns true if this is a profitable sandwich attack
@@ -164,8 +794,12 @@ impl Pattern {
             return false;
         }
 
-        // Validate transaction sequence is in the same block
-        if create_tx.block_height != swap_in_tx.block_height || swap_in_tx.block_height != swap_out_tx.block_height {
+        // Validate the transactions are in order and the swap-out landed within the allowed number of slots,
+        // which is the same block unless max_block_gap allows adjacent ones
+        if create_tx.position() > swap_in_tx.position()
+            || swap_in_tx.position() > swap_out_tx.position()
+            || create_tx.slots_until(swap_out_tx) > self.max_block_gap
+        {
             return false;
         }
 
@@ -188,117 +822,2434 @@ impl Pattern {
         swap_out.from_amount as i128 - swap_in.from_amount as i128
     }
 
-    // Returns the SOL profit including both SOL and wSOL changes
+    // Returns the token's symbol with its mint address, or just the mint address when the symbol isn't known
+    pub fn get_token_label(&self) -> String {
+        self.token_symbol
+            .as_ref()
+            .map(|symbol| format!("{} ({})", symbol, self.token))
+            .unwrap_or_else(|| self.token.clone())
+    }
+
+    // Returns the token profit in UI units, using the swap-in's decimals
+    // Only meaningful once the decimals are resolved; until then they're the default of 9
+    pub fn get_token_profit_ui(&self) -> f64 {
+        ui_amount(self.get_token_profit(), self.swap_in_tx().decimals)
+    }
+
+    // Returns the SOL profit using the pattern's configured SOL accounting
+    // Unified accounting falls back to separate channels if either swap leg lacks balance data
     pub fn get_sol_profit(&self) -> f64 {
+        self.get_sol_profit_as(self.sol_accounting)
+    }
+
+    // Returns the net SOL profit under the given accounting, whatever the pattern was built with
+    pub fn get_sol_profit_as(&self, sol_accounting: SolAccounting) -> f64 {
+        self.get_sol_profit_lamports_as(sol_accounting) as f64 / 1e9
+    }
+
+    // Returns the SOL profit in lamports using the pattern's configured SOL accounting, exact for storage
+    pub fn get_sol_profit_lamports(&self) -> i128 {
+        self.get_sol_profit_lamports_as(self.sol_accounting)
+    }
+
+    pub fn get_sol_profit_lamports_as(&self, sol_accounting: SolAccounting) -> i128 {
+        match sol_accounting {
+            SolAccounting::Separate => self.get_separate_sol_profit_lamports(),
+            SolAccounting::Unified => self
+                .get_unified_sol_profit_lamports()
+                .unwrap_or_else(|| self.get_separate_sol_profit_lamports()),
+        }
+    }
+
+    // Returns the net SOL profit: the gross wSOL edge less the Jito tip and the transaction fees
+    pub fn get_separate_sol_profit(&self) -> f64 {
+        self.get_separate_sol_profit_lamports() as f64 / 1e9
+    }
+
+    fn get_separate_sol_profit_lamports(&self) -> i128 {
+        self.get_gross_sol_profit_lamports() - self.get_total_jito_tips() as i128 - self.get_total_fees() as i128
+    }
+
+    // Returns the wSOL received by the swap-out minus the wSOL spent by the swap-in, before any costs
+    pub fn get_gross_sol_profit(&self) -> f64 {
+        self.get_gross_sol_profit_lamports() as f64 / 1e9
+    }
+
+    fn get_gross_sol_profit_lamports(&self) -> i128 {
         let (_, swap_in_tx, swap_out_tx) = &self.transactions;
 
-        let wsol_in: f64 = swap_in_tx.wsol_change.unwrap_or(0.0).abs(); // Positive (amount received)
-        let wsol_out: f64 = swap_out_tx.wsol_change.unwrap_or(0.0).abs(); // Positive (amount sent)
-        let jito_tip: f64 = swap_out_tx.jito_tip_amount as f64 / 1e9;
-        let base_fees: f64 = 0.00001 * 2.0; // 2 base fees for in/out txs
+        // The swap-in's change is negative (SOL paid) and the swap-out's positive (SOL received)
+        let wsol_in: i128 = swap_in_tx.wsol_change.unwrap_or(0);
+        let wsol_out: i128 = swap_out_tx.wsol_change.unwrap_or(0);
+
+        wsol_out + wsol_in
+    }
+
+    // Returns the Jito tips paid across every leg in SOL
+    pub fn get_jito_tip_cost(&self) -> f64 {
+        self.get_total_jito_tips() as f64 / 1e9
+    }
+
+    // Returns the transaction fees of the create, swap-in, and swap-out in lamports, priority fees included
+    // A transaction holding more than one leg is only counted once
+    pub fn get_total_fees(&self) -> u64 {
+        let mut seen: HashSet<&str> = HashSet::new();
+
+        [self.create_tx(), self.swap_in_tx(), self.swap_out_tx()]
+            .into_iter()
+            .filter(|tx| seen.insert(tx.signature.as_str()))
+            .map(|tx| tx.fee)
+            .sum()
+    }
+
+    // Returns the total transaction fees in SOL
+    pub fn get_fee_cost(&self) -> f64 {
+        self.get_total_fees() as f64 / 1e9
+    }
+
+    // Returns the SOL profit from the attacker's unified native SOL + wSOL deltas across both swap legs
+    // The deltas already include each leg's fee and any tip the signer paid, so only tips paid from elsewhere are
+    // subtracted on top
+    pub fn get_unified_sol_profit(&self) -> Option<f64> {
+        self.get_unified_sol_profit_lamports()
+            .map(|lamports| lamports as f64 / 1e9)
+    }
+
+    fn get_unified_sol_profit_lamports(&self) -> Option<i128> {
+        let (_, swap_in_tx, swap_out_tx) = &self.transactions;
+
+        [swap_in_tx, swap_out_tx]
+            .iter()
+            .map(|tx| {
+                tx.sol_equivalent_change
+                    .map(|change| change as i128 - tx.tip_outside_balance() as i128)
+            })
+            .sum::<Option<i128>>()
+    }
+
+    // Returns the Jito tips paid across every leg of the pattern, in lamports
+    pub fn get_total_jito_tips(&self) -> u64 {
+        let (create_tx, swap_in_tx, swap_out_tx) = &self.transactions;
+
+        [create_tx, swap_in_tx, swap_out_tx]
+            .into_iter()
+            .chain(&self.additional_swap_ins)
+            .map(|tx| tx.jito_tip_amount)
+            .sum()
+    }
+
+    // Estimates each victim's loss by replaying their buys against the pre-front-run reserves
+    //
+    // Assumes a constant-product (x * y = k) pool with no swap fees, where only the front-run is removed:
+    // each victim's counterfactual output is computed against the reserves left by the victims before it,
+    // and any other trades between the legs are ignored. Returns nothing if reserves are unknown
+    pub fn get_victim_losses(&self) -> Vec<VictimLoss> {
+        let mut reserves: PoolReserves = match self.reserves {
+            Some(reserves) => reserves,
+            None => return Vec::new(),
+        };
+        let mut losses: Vec<VictimLoss> = Vec::new();
+
+        for victim in &self.victims {
+            let counterfactual_tokens: u64 = match reserves.amount_out(victim.sol_in, true) {
+                Some(tokens) => tokens,
+                None => break,
+            };
+            let token_loss: u64 = counterfactual_tokens.saturating_sub(victim.tokens_received);
+            let sol_loss: f64 = if counterfactual_tokens > 0 {
+                token_loss as f64 * victim.sol_in as f64 / counterfactual_tokens as f64 / 1e9
+            } else {
+                0.0
+            };
+
+            losses.push(VictimLoss {
+                signature: victim.signature.clone(),
+                token_account: victim.token_account.clone(),
+                counterfactual_tokens,
+                token_loss,
+                sol_loss,
+            });
+
+            reserves = match reserves.after_swap(victim.sol_in, true) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+
+        losses
+    }
+
+    // Returns the estimated price impact of the front-run on the pool, if reserves are known
+    pub fn get_price_impact(&self) -> Option<f64> {
+        let reserves: PoolReserves = self.reserves?;
+        let sol_in: u64 = self.swap_in_tx().wsol_change?.unsigned_abs() as u64;
+
+        reserves.price_impact(sol_in, true)
+    }
+
+    // Estimates how much worse the first victim's execution price was because of the front-run, as a fraction
+    //
+    // Compares the victim's actual price (SOL in per token out) to the price they'd have gotten against the
+    // pre-front-run reserves, under the same constant-product, no-fee assumptions as get_victim_losses
+    // A victim who paid 1 SOL for 90 tokens but would've received 100 without the front-run saw an 11.1% move
+    pub fn victim_price_impact(&self) -> Option<f64> {
+        let victim: &VictimSwap = self.victims.first()?;
+        let counterfactual_tokens: u64 = self.reserves?.amount_out(victim.sol_in, true)?;
 
-        // Profit = Amount received - Amount sent - Jito tip - Base fees
-        wsol_out - wsol_in - jito_tip - base_fees
+        if victim.tokens_received == 0 || counterfactual_tokens == 0 {
+            return None;
+        }
+
+        Some(counterfactual_tokens as f64 / victim.tokens_received as f64 - 1.0)
     }
 
-    // Returns a formatted string summarizing the pattern
+    // Returns a formatted string summarizing the pattern, with token amounts in UI units
     pub fn to_summary(&self) -> String {
+        self.to_summary_in(AmountUnit::default())
+    }
+
+    // The unit amounts are actually presented in: base units whenever the token's decimals weren't resolved
+    fn presented_unit(&self, unit: AmountUnit) -> AmountUnit {
+        if self.swap_in_tx().decimals_resolved {
+            unit
+        } else {
+            AmountUnit::BaseUnits
+        }
+    }
+
+    // Returns a formatted string summarizing the pattern, with token amounts in the given unit
+    // Amounts are always shown in base units if the token's decimals weren't resolved
+    pub fn to_summary_in(&self, unit: AmountUnit) -> String {
+        let unit: AmountUnit = self.presented_unit(unit);
         let token_profit: i128 = self.get_token_profit();
         let wsol_profit: f64 = self.get_sol_profit();
-        let time_str: String = self
-            .transactions
-            .0
-            .block_time
-            .map(|t| {
-                DateTime::<Utc>::from_timestamp(t as i64, 0)
-                    .map(|dt| dt.format("%Y-%m-%d %H:%M:%S UTC").to_string())
-                    .unwrap_or_else(|| "Invalid timestamp".to_string())
-            })
-            .unwrap_or_else(|| "Unknown".to_string());
-        let decimals: i32 = self.transactions.1.decimals.into();
-
-        // Function to format token amounts using correct decimals
-        let format_token_amount = |amount: i128| -> String {
-            let decimal_divisor = 10_f64.powi(decimals as i32);
-            format!("{:.6}", amount as f64 / decimal_divisor)
+        let time_str: String = match (self.create_tx().block_time, self.create_tx().datetime()) {
+            (Some(_), Some(dt)) => dt.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            (Some(_), None) => "Invalid timestamp".to_string(),
+            (None, _) => "Unknown".to_string(),
         };
+        let decimals: u8 = self.swap_in_tx().decimals;
 
-        format!(
+        let summary: String = format!(
             "Sandwich Attack Pattern:\n\
+             Kind: {:?}\n\
              Token: {}\n\
-             Token Profit: {} tokens\n\
-             SOL Profit: {:.9} SOL\n\
-             Attacker: {}\n\
+             Token Profit: {} {}\n\
+             SOL Profit: {:.9} SOL{}\n\
+             Gross SOL Profit: {:.9} SOL (costs: {:.9} SOL Jito tip, {:.9} SOL fees)\n\
+             Attacker: {}{}\n\
+             Fee Payer: {}\n\
+             Signers: {}\n\
              Swapper: {}\n\
+             Pool: {}\n\
              Block Height: {}\n\
              Time: {}\n\
+             Price Impact: {}\n\
+             Victim Price Impact: {}\n\
+             Likely Wash Trade: {}\n\
              Transactions:\n\
              - Create: {}\n\
              - Swap In: {} (amount: {})\n\
              - Swap Out: {} (amount: {})\n\
-             Jito Tips Paid: {}\n",
-            self.transactions.1.from_mint,
-            format_token_amount(token_profit),
+             Jito Tips Paid: {}\n\
+             Total Fees Paid: {}\n\
+             Via Jito Bundle: {}\n\
+             Realized: {}\n",
+            self.kind,
+            self.get_token_label(),
+            unit.format(token_profit, decimals),
+            unit.label(),
             wsol_profit,
+            self.sol_price_usd
+                .map(|price| format!(" (~${:.2})", self.get_usd_profit(price)))
+                .unwrap_or_default(),
+            self.get_gross_sol_profit(),
+            self.get_jito_tip_cost(),
+            self.get_fee_cost(),
             self.attacker,
+            self.attacker_label
+                .as_ref()
+                .map(|label| format!(" ({})", label))
+                .unwrap_or_default(),
+            self.swap_in_tx().fee_payer,
+            self.swap_in_tx().signers.join(", "),
             self.swapper.as_ref().unwrap_or(&String::from("Unknown")),
-            self.transactions.0.block_height,
+            self.pool.as_deref().unwrap_or("Unknown"),
+            self.create_tx().block_height,
             time_str,
-            self.transactions.0.signature,
-            self.transactions.1.signature,
-            self.transactions.1.from_amount,
-            self.transactions.2.signature,
-            self.transactions.2.from_amount,
-            self.transactions.2.jito_tip_amount,
-        )
+            self.get_price_impact()
+                .map(|impact| format!("{:.4}%", impact * 100.0))
+                .unwrap_or_else(|| "Unknown".to_string()),
+            self.victim_price_impact()
+                .map(|impact| format!("{:.4}%", impact * 100.0))
+                .unwrap_or_else(|| "Unknown".to_string()),
+            if self.likely_wash { "Yes" } else { "No" },
+            self.create_tx().signature,
+            self.swap_in_tx().signature,
+            unit.format(self.swap_in_tx().from_amount.into(), decimals),
+            self.swap_out_tx().signature,
+            unit.format(self.swap_out_tx().from_amount.into(), decimals),
+            self.get_total_jito_tips(),
+            self.get_total_fees(),
+            if self.via_jito_bundle { "Yes" } else { "No" },
+            self.cashout_tx
+                .as_ref()
+                .map(|cashout_tx| format!("Yes ({})", cashout_tx.signature))
+                .unwrap_or_else(|| "No".to_string()),
+        );
+
+        let victim_lines: String = self
+            .get_victim_losses()
+            .iter()
+            .map(|loss| {
+                format!(
+                    "- Victim {} ({}): lost {} {} (~{:.9} SOL)\n",
+                    loss.signature,
+                    loss.token_account,
+                    unit.format(loss.token_loss.into(), decimals),
+                    unit.label(),
+                    loss.sol_loss
+                )
+            })
+            .collect();
+
+        if victim_lines.is_empty() {
+            summary
+        } else {
+            format!("{}Victim Losses:\n{}", summary, victim_lines)
+        }
     }
-}
 
-// Tracks potential sandwich attacks in progress
-#[derive(Default)]
-pub struct PatternTracker {
-    // Map of sandwich_acc -> create transaction
-    open_positions: HashMap<String, ClassifiedTransaction>,
-    // Map of sandwich_acc -> (create_tx, swap_in_tx)
-    in_progress: HashMap<String, (ClassifiedTransaction, ClassifiedTransaction)>,
-    // Completed patterns
-    completed: Vec<Pattern>,
-}
+    // Returns the SOL profit valued in USD at the given SOL/USD price
+    pub fn get_usd_profit(&self, sol_price_usd: f64) -> f64 {
+        self.get_sol_profit() * sol_price_usd
+    }
 
-impl PatternTracker {
-    pub fn new() -> Self {
-        Self::default()
+    // Returns the pattern as a single-line JSON object, e.g. for piping detections into jq
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
     }
 
-    pub fn process_transaction(&mut self, tx: ClassifiedTransaction) {
-        match tx.instruction_type.as_str() {
-            "CreateSandwichV2" => {
-                // Store create transaction indexed by sandwich account
-                self.open_positions.insert(tx.sandwich_acc.clone(), tx);
-            }
-            "AutoSwapIn" => {
-                // If we find a matching create transaction, move both to in_progress
-                if let Some(create_tx) = self.open_positions.remove(&tx.sandwich_acc) {
-                    self.in_progress.insert(tx.sandwich_acc.clone(), (create_tx, tx));
-                }
-            }
-            "AutoSwapOut" => {
-                // If we find matching in_progress transactions, try to create a pattern
-                if let Some((create_tx, swap_in_tx)) = self.in_progress.remove(&tx.sandwich_acc) {
-                    if let Some(pattern) = Pattern::new(create_tx, swap_in_tx, tx) {
-                        self.completed.push(pattern);
-                    }
-                }
-            }
-            _ => {}
-        }
+    // Like to_json, but with the token profit in the given unit, as the summary presents it
+    // The legs keep their raw base-unit amounts either way
+    pub fn to_json_in(&self, unit: AmountUnit) -> serde_json::Result<String> {
+        serde_json::to_string(&self.json(unit))
     }
 
-    pub fn get_completed_patterns(&self) -> &[Pattern] {
-        &self.completed
+    fn json(&self, unit: AmountUnit) -> PatternJson<'_> {
+        let amount_unit: AmountUnit = self.presented_unit(unit);
+
+        PatternJson {
+            kind: self.kind,
+            token: &self.token,
+            token_symbol: self.token_symbol.as_deref(),
+            attacker: &self.attacker,
+            attacker_label: self.attacker_label.as_deref(),
+            victim: self.victims.first().map(|victim| victim.signer.as_str()),
+            swapper: self.swapper.as_deref(),
+            pool: self.pool.as_deref(),
+            block_height: self.create_tx().block_height,
+            block_time: self.create_tx().block_time,
+            amount_unit,
+            token_profit: amount_unit.to_json_value(self.get_token_profit(), self.swap_in_tx().decimals),
+            sol_profit: self.get_sol_profit(),
+            usd_profit: self.sol_price_usd.map(|price| self.get_usd_profit(price)),
+            sol_accounting: self.sol_accounting,
+            jito_tips: self.get_total_jito_tips(),
+            fees: self.get_total_fees(),
+            price_impact: self.get_price_impact(),
+            victim_price_impact: self.victim_price_impact(),
+            likely_wash: self.likely_wash,
+            via_jito_bundle: self.via_jito_bundle,
+            reserves: self.reserves,
+            create_tx: self.create_tx(),
+            swap_in_tx: self.swap_in_tx(),
+            swap_out_tx: self.swap_out_tx(),
+            additional_swap_ins: &self.additional_swap_ins,
+            victims: &self.victims,
+            victim_losses: self.get_victim_losses(),
+            realized: self.is_realized(),
+            cashout_tx: self.cashout_tx.as_ref(),
+        }
+    }
+}
+
+// The serialized shape of a pattern: its legs plus the computed profit and victim fields
+#[derive(Serialize)]
+struct PatternJson<'a> {
+    kind: PatternKind,
+    token: &'a str,
+    token_symbol: Option<&'a str>,
+    attacker: &'a str,
+    attacker_label: Option<&'a str>,
+    // The first party that bought between the legs, if any
+    victim: Option<&'a str>,
+    swapper: Option<&'a str>,
+    pool: Option<&'a str>,
+    block_height: u64,
+    block_time: Option<i64>,
+    amount_unit: AmountUnit,
+    token_profit: serde_json::Value,
+    sol_profit: f64,
+    usd_profit: Option<f64>,
+    sol_accounting: SolAccounting,
+    jito_tips: u64,
+    fees: u64,
+    price_impact: Option<f64>,
+    victim_price_impact: Option<f64>,
+    likely_wash: bool,
+    via_jito_bundle: bool,
+    reserves: Option<PoolReserves>,
+    create_tx: &'a ClassifiedTransaction,
+    swap_in_tx: &'a ClassifiedTransaction,
+    swap_out_tx: &'a ClassifiedTransaction,
+    additional_swap_ins: &'a [ClassifiedTransaction],
+    victims: &'a [VictimSwap],
+    victim_losses: Vec<VictimLoss>,
+    realized: bool,
+    cashout_tx: Option<&'a ClassifiedTransaction>,
+}
+
+// Serialized with amounts in base units, so nothing is lost to rounding
+impl Serialize for Pattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.json(AmountUnit::BaseUnits).serialize(serializer)
+    }
+}
+
+// The result of analyzing a single block
+pub struct BlockAnalysis {
+    pub slot: u64,
+    pub block_height: Option<u64>,
+    pub patterns: Vec<Pattern>,
+    // Present when vote transactions were counted
+    pub composition: Option<BlockComposition>,
+    // Residual tracker state, only collected when explaining incomplete patterns
+    pub incomplete: Vec<IncompletePattern>,
+    // Present when failed transactions were tracked
+    pub swap_attempts: Option<SwapAttempts>,
+    // Occurrences of each target-program discriminator missing from the instruction map, hex-encoded, so new
+    // instruction types the bot uses can be discovered
+    pub unknown_discriminators: HashMap<String, usize>,
+}
+
+// Counts of vote and non-vote transactions, for block composition analysis
+#[derive(Debug, Default, Serialize)]
+pub struct BlockComposition {
+    pub vote_txs: usize,
+    pub non_vote_txs: usize,
+}
+
+impl BlockComposition {
+    pub fn vote_ratio(&self) -> Option<f64> {
+        let total: usize = self.vote_txs + self.non_vote_txs;
+
+        if total == 0 {
+            return None;
+        }
+
+        Some(self.vote_txs as f64 / total as f64)
+    }
+
+    pub fn to_summary(&self) -> String {
+        format!(
+            "Block composition: {} vote / {} non-vote transactions ({:.2}% vote)",
+            self.vote_txs,
+            self.non_vote_txs,
+            self.vote_ratio().unwrap_or(0.0) * 100.0
+        )
+    }
+}
+
+// Counts of attempted and failed swap legs (AutoSwapIn and AutoSwapOut), for measuring how often attacks revert
+#[derive(Debug, Default, Serialize)]
+pub struct SwapAttempts {
+    pub attempted: usize,
+    pub failed: usize,
+}
+
+impl SwapAttempts {
+    pub fn count(txs: &[ClassifiedTransaction]) -> Self {
+        let swaps: Vec<&ClassifiedTransaction> = txs
+            .iter()
+            .filter(|tx| tx.instruction_type == "AutoSwapIn" || tx.instruction_type == "AutoSwapOut")
+            .collect();
+
+        Self {
+            attempted: swaps.len(),
+            failed: swaps.iter().filter(|tx| tx.failed).count(),
+        }
+    }
+
+    pub fn failure_rate(&self) -> Option<f64> {
+        if self.attempted == 0 {
+            return None;
+        }
+
+        Some(self.failed as f64 / self.attempted as f64)
+    }
+
+    pub fn to_summary(&self) -> String {
+        format!(
+            "Swap legs: {} failed of {} attempted ({:.2}% failed)",
+            self.failed,
+            self.attempted,
+            self.failure_rate().unwrap_or(0.0) * 100.0
+        )
+    }
+}
+
+// Accumulates the legs of a pattern as they're seen: a create, zero or more swap-ins, and a close (swap-out)
+#[derive(Default)]
+pub struct PatternBuilder {
+    create_tx: Option<ClassifiedTransaction>,
+    swap_in_txs: Vec<ClassifiedTransaction>,
+    close_tx: Option<ClassifiedTransaction>,
+    // Reject patterns whose legs span more than this many seconds, if set
+    max_time_span_secs: Option<u64>,
+    // Reject patterns whose swap-out lands more than this many blocks after the create
+    max_block_gap: u64,
+}
+
+impl PatternBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_create(mut self, tx: ClassifiedTransaction) -> Self {
+        self.create_tx = Some(tx);
+        self
+    }
+
+    pub fn add_swap_in(mut self, tx: ClassifiedTransaction) -> Self {
+        self.swap_in_txs.push(tx);
+        self
+    }
+
+    pub fn with_close(mut self, tx: ClassifiedTransaction) -> Self {
+        self.close_tx = Some(tx);
+        self
+    }
+
+    pub fn with_max_time_span(mut self, secs: Option<u64>) -> Self {
+        self.max_time_span_secs = secs;
+        self
+    }
+
+    pub fn with_max_block_gap(mut self, slots: u64) -> Self {
+        self.max_block_gap = slots;
+        self
+    }
+
+    // The most recently added leg, preferring the close, then the latest swap-in, then the create
+    fn last_leg(&self) -> Option<&ClassifiedTransaction> {
+        self.close_tx
+            .as_ref()
+            .or(self.swap_in_txs.last())
+            .or(self.create_tx.as_ref())
+    }
+
+    // Validates the accumulated legs and finalizes them into a pattern
+    // Requires a create, at least one swap-in, and a close, all on the same sandwich account, with the close no more
+    // than the max block gap after the create
+    // A create and close further apart than the max time span are rejected, e.g. a stale position paired
+    // with a much later exit after the sandwich account was reused
+    pub fn build(self) -> Option<Pattern> {
+        self.build_or_reject()?.ok()
+    }
+
+    // Like build, but hands back a pattern whose legs matched yet failed validation, along with why
+    // None if the legs can't form a pattern at all, e.g. one is missing or they're on different sandwich accounts
+    pub fn build_or_reject(self) -> Option<Result<Pattern, (Pattern, RejectReason)>> {
+        let create_tx: ClassifiedTransaction = self.create_tx?;
+        let close_tx: ClassifiedTransaction = self.close_tx?;
+
+        let mut swap_in_txs = self.swap_in_txs.into_iter();
+        let first_swap_in: ClassifiedTransaction = swap_in_txs.next()?;
+        let additional_swap_ins: Vec<ClassifiedTransaction> = swap_in_txs.collect();
+
+        if additional_swap_ins
+            .iter()
+            .any(|tx| tx.sandwich_acc != create_tx.sandwich_acc)
+        {
+            return None;
+        }
+
+        let mut pattern: Pattern = Pattern::new(create_tx, first_swap_in, close_tx)?;
+        pattern.additional_swap_ins = additional_swap_ins;
+        pattern.via_jito_bundle = pattern.detect_jito_bundle();
+        pattern.max_block_gap = self.max_block_gap;
+
+        let (create_tx, swap_in_tx, swap_out_tx) = &pattern.transactions;
+
+        let reason: Option<RejectReason> = match (self.max_time_span_secs, create_tx.block_time, swap_out_tx.block_time)
+        {
+            (Some(max_secs), Some(start), Some(end)) if end.saturating_sub(start) > max_secs as i64 => {
+                Some(RejectReason::TimeSpanExceeded)
+            }
+            _ if create_tx.slots_until(swap_out_tx) > self.max_block_gap => Some(RejectReason::BlockGapExceeded),
+            _ if mints_mismatch(swap_in_tx, swap_out_tx) => Some(RejectReason::MintMismatch),
+            _ => None,
+        };
+
+        match reason {
+            Some(reason) => Some(Err((pattern, reason))),
+            None => Some(Ok(pattern)),
+        }
+    }
+}
+
+// Whether an earlier leg's block time doesn't come after a later leg's; unknown times don't constrain the order
+// Equal times are in order, since legs in the same block (or blocks in the same second) share a timestamp
+fn times_in_order(earlier: Option<i64>, later: Option<i64>) -> bool {
+    match (earlier, later) {
+        (Some(earlier), Some(later)) => earlier <= later,
+        _ => true,
+    }
+}
+
+// Why a matched create, swap-in, and swap-out weren't reported as a pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RejectReason {
+    // The create and swap-out are further apart than the max time span
+    TimeSpanExceeded,
+    // The swap-out landed more blocks after the create than the max block gap
+    BlockGapExceeded,
+    // The swaps traded different tokens, which may be a detection bug or a strategy the tracker doesn't model
+    MintMismatch,
+}
+
+impl RejectReason {
+    pub fn description(&self) -> &'static str {
+        match self {
+            RejectReason::TimeSpanExceeded => "Legs matched, but the create and swap-out are too far apart in time",
+            RejectReason::BlockGapExceeded => "Legs matched, but the swap-out landed too many slots after the create",
+            RejectReason::MintMismatch => "Legs matched, but the swap-in and swap-out traded different tokens",
+        }
+    }
+}
+
+// Whether two swap legs, or either leg on its own, moved different tokens
+// A leg without token accounting has no mint and can't mismatch
+fn mints_mismatch(swap_in_tx: &ClassifiedTransaction, swap_out_tx: &ClassifiedTransaction) -> bool {
+    let mints: Vec<&str> = [swap_in_tx, swap_out_tx]
+        .iter()
+        .flat_map(|tx| [tx.from_mint.as_str(), tx.to_mint.as_str()])
+        .filter(|mint| !mint.is_empty())
+        .collect();
+
+    mints.windows(2).any(|pair| pair[0] != pair[1])
+}
+
+// Token decimals and symbols by mint address, shared by everything resolving them against the same cluster
+// Each detector can own its own cache, so detectors for different clusters don't see each other's mints
+#[derive(Debug, Default)]
+pub struct DecimalsCache {
+    decimals: Mutex<HashMap<String, u8>>,
+    // None records a mint without metadata, so it isn't looked up again
+    symbols: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl DecimalsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, mint: &str) -> Result<Option<u8>, SandwichError> {
+        Ok(self.lock()?.get(mint).copied())
+    }
+
+    pub fn insert(&self, mint: &str, decimals: u8) -> Result<(), SandwichError> {
+        self.lock()?.insert(mint.to_string(), decimals);
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> Result<usize, SandwichError> {
+        Ok(self.lock()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, SandwichError> {
+        Ok(self.len()? == 0)
+    }
+
+    // Returns None if the mint hasn't been looked up, or Some(None) if it was but has no symbol
+    pub fn get_symbol(&self, mint: &str) -> Result<Option<Option<String>>, SandwichError> {
+        Ok(self.lock_symbols()?.get(mint).cloned())
+    }
+
+    pub fn insert_symbol(&self, mint: &str, symbol: Option<String>) -> Result<(), SandwichError> {
+        self.lock_symbols()?.insert(mint.to_string(), symbol);
+
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<String, u8>>, SandwichError> {
+        self.decimals
+            .lock()
+            .map_err(|_| SandwichError::PoisonedLock("decimals cache".to_string()))
+    }
+
+    fn lock_symbols(&self) -> Result<MutexGuard<'_, HashMap<String, Option<String>>>, SandwichError> {
+        self.symbols
+            .lock()
+            .map_err(|_| SandwichError::PoisonedLock("symbol cache".to_string()))
+    }
+}
+
+// Supplies the SOL/USD price patterns are valued at; implement it to plug in a live price feed
+pub trait SolPriceSource {
+    fn sol_price_usd(&self) -> Option<f64>;
+}
+
+// A fixed SOL/USD price, e.g. given on the command line or for reproducible reports
+#[derive(Debug, Clone, Copy)]
+pub struct FixedSolPrice(pub f64);
+
+impl SolPriceSource for FixedSolPrice {
+    fn sol_price_usd(&self) -> Option<f64> {
+        Some(self.0)
+    }
+}
+
+// Which accounts count as Jito tip accounts and the smallest transfer to them treated as a tip
+// Defaults to JITO_TIP_ADDRESSES and MIN_JITO_TIP, and can be overridden when Jito rotates its tip accounts
+#[derive(Debug, Clone)]
+pub struct JitoTipConfig {
+    pub tip_addresses: HashSet<String>,
+    pub min_tip: u64,
+}
+
+// The on-disk form of JitoTipConfig, where any omitted field keeps its default
+#[derive(Deserialize)]
+struct JitoTipConfigFile {
+    tip_addresses: Option<Vec<String>>,
+    min_tip: Option<u64>,
+}
+
+impl Default for JitoTipConfig {
+    fn default() -> Self {
+        Self {
+            tip_addresses: JITO_TIP_ADDRESSES.iter().map(|address| address.to_string()).collect(),
+            min_tip: MIN_JITO_TIP,
+        }
+    }
+}
+
+impl JitoTipConfig {
+    // Loads a JSON object like {"tip_addresses": ["..."], "min_tip": 1000}
+    // A tip_addresses list replaces the defaults rather than extending them, so retired accounts can be dropped
+    pub fn from_json_file(path: &Path) -> Result<Self, String> {
+        let contents: String = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read Jito tip config {}: {}", path.display(), e))?;
+        let file: JitoTipConfigFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse Jito tip config {}: {}", path.display(), e))?;
+
+        let mut config: JitoTipConfig = Self::default();
+
+        if let Some(tip_addresses) = file.tip_addresses {
+            config.tip_addresses.clear();
+
+            for address in tip_addresses {
+                config
+                    .add_tip_address(&address)
+                    .map_err(|e| format!("Invalid Jito tip config {}: {}", path.display(), e))?;
+            }
+        }
+
+        if let Some(min_tip) = file.min_tip {
+            config.set_min_tip(min_tip);
+        }
+
+        Ok(config)
+    }
+
+    pub fn add_tip_address(&mut self, address: &str) -> Result<(), SandwichError> {
+        parse_pubkey(address)?;
+        self.tip_addresses.insert(address.to_string());
+
+        Ok(())
+    }
+
+    pub fn set_min_tip(&mut self, min_tip: u64) {
+        self.min_tip = min_tip;
+    }
+
+    pub fn is_tip_address(&self, address: &str) -> bool {
+        self.tip_addresses.contains(address)
+    }
+}
+
+// Maps known bot addresses to a human-readable name or operator, for attributing attackers
+#[derive(Debug, Default)]
+pub struct BotRegistry {
+    labels: HashMap<String, String>,
+}
+
+impl BotRegistry {
+    // Loads a registry from a JSON object mapping pubkeys to labels, e.g. {"<pubkey>": "arsc"}
+    pub fn from_json_file(path: &Path) -> Result<Self, String> {
+        let contents: String =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read bot registry {}: {}", path.display(), e))?;
+        let labels: HashMap<String, String> = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse bot registry {}: {}", path.display(), e))?;
+
+        Ok(Self { labels })
+    }
+
+    pub fn label_for(&self, address: &str) -> Option<&str> {
+        self.labels.get(address).map(|label| label.as_str())
+    }
+
+    // Attaches the attacker's label to the pattern, if the attacker is registered
+    pub fn label_pattern(&self, pattern: &mut Pattern) {
+        pattern.attacker_label = self.label_for(&pattern.attacker).map(String::from);
+    }
+}
+
+// A sandwich account whose pattern never completed, with the last leg seen and why it stalled
+#[derive(Debug, Clone, Serialize)]
+pub struct IncompletePattern {
+    pub sandwich_acc: String,
+    pub last_instruction_type: String,
+    pub last_signature: String,
+    pub slot: u64,
+    // Number of swap-ins seen after the create
+    pub swap_ins_seen: usize,
+    pub reason: String,
+}
+
+impl IncompletePattern {
+    // Explains a rejected pattern by its swap-out, the leg that completed it
+    pub fn from_rejected(pattern: &Pattern, reason: RejectReason) -> Self {
+        Self::from_leg(
+            &pattern.create_tx().sandwich_acc,
+            pattern.swap_out_tx(),
+            1 + pattern.additional_swap_ins.len(),
+            reason.description(),
+        )
+    }
+
+    fn from_leg(sandwich_acc: &str, leg: &ClassifiedTransaction, swap_ins_seen: usize, reason: &str) -> Self {
+        Self {
+            sandwich_acc: sandwich_acc.to_string(),
+            last_instruction_type: leg.instruction_type.clone(),
+            last_signature: leg.signature.clone(),
+            slot: leg.slot,
+            swap_ins_seen,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn to_summary(&self) -> String {
+        format!(
+            "Sandwich Account: {}\n\
+             Last Leg: {} at slot {} ({})\n\
+             Swap-ins Seen: {}\n\
+             Reason: {}",
+            self.sandwich_acc,
+            self.last_instruction_type,
+            self.slot,
+            self.last_signature,
+            self.swap_ins_seen,
+            self.reason,
+        )
+    }
+}
+
+// A pattern withdrawn because the confirmed block it was found in was reorged out before it finalized
+// Sinks record these alongside patterns, so consumers that acted on a pattern can undo it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Retraction {
+    pub slot: u64,
+    // The blockhash of the block that was analyzed, which is no longer canonical
+    pub blockhash: String,
+    pub create_signature: String,
+    pub swap_in_signature: String,
+    pub swap_out_signature: String,
+}
+
+impl Retraction {
+    pub fn new(pattern: &Pattern, slot: u64, blockhash: &str) -> Self {
+        Self {
+            slot,
+            blockhash: blockhash.to_string(),
+            create_signature: pattern.create_tx().signature.clone(),
+            swap_in_signature: pattern.swap_in_tx().signature.clone(),
+            swap_out_signature: pattern.swap_out_tx().signature.clone(),
+        }
+    }
+
+    pub fn to_summary(&self) -> String {
+        format!(
+            "Retracted pattern {} at slot {}: block {} was reorged out",
+            self.create_signature, self.slot, self.blockhash
+        )
+    }
+}
+
+// Callback receiving each classified transaction fed to a PatternTracker
+pub type ClassifiedCallback = Box<dyn FnMut(&ClassifiedTransaction)>;
+
+// Callback receiving each pattern a PatternTracker completes
+pub type CompletedCallback = Box<dyn FnMut(&Pattern)>;
+
+// Tracks potential sandwich attacks in progress
+// Legs are moved from the stacks into builders and then into patterns, never cloned, so each classified transaction
+// is allocated once however long it waits for its sandwich to complete
+pub struct PatternTracker {
+    // Called with every classified transaction before the tracker consumes it
+    on_classified: Option<ClassifiedCallback>,
+    // Called with every completed pattern once it's been enriched, through notify_complete
+    on_complete: Option<CompletedCallback>,
+    // Map of sandwich_acc -> stack of create transactions not yet swapped into, most recent last
+    open_positions: HashMap<String, Vec<ClassifiedTransaction>>,
+    // Map of sandwich_acc -> stack of builders holding the create and swap-in legs, most recent last
+    // Accounts can be reused for nested or back-to-back attacks, so legs are matched in LIFO order
+    in_progress: HashMap<String, Vec<PatternBuilder>>,
+    // Completed patterns
+    completed: Vec<Pattern>,
+    // Patterns whose legs matched but failed validation, with why
+    rejected: Vec<(Pattern, RejectReason)>,
+    // Longest time in seconds a pattern's legs may span, or None to disable the check
+    max_time_span_secs: Option<u64>,
+    // How many slots behind prune_older_than's slot a pending sandwich's latest leg may be
+    max_pending_slots: u64,
+    // Most slots a pattern's swap-out may land after its create
+    max_block_gap: u64,
+}
+
+impl Default for PatternTracker {
+    fn default() -> Self {
+        Self {
+            on_classified: None,
+            on_complete: None,
+            open_positions: HashMap::new(),
+            in_progress: HashMap::new(),
+            completed: Vec::new(),
+            rejected: Vec::new(),
+            max_time_span_secs: Some(DEFAULT_MAX_TIME_SPAN_SECS),
+            max_pending_slots: DEFAULT_MAX_PENDING_SLOTS,
+            max_block_gap: DEFAULT_MAX_BLOCK_GAP,
+        }
+    }
+}
+
+impl PatternTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registers a callback receiving the raw classified transaction feed, e.g. for custom correlation
+    pub fn set_on_classified(&mut self, callback: impl FnMut(&ClassifiedTransaction) + 'static) {
+        self.on_classified = Some(Box::new(callback));
+    }
+
+    // Registers a callback receiving each completed pattern, rather than polling the completed patterns
+    // It fires from notify_complete, once the pattern has been enriched with what needs the rest of its block
+    // (victims, arbitrage classification, wash and Jito flags), which analyze_non_vote_transactions does per block
+    pub fn set_on_complete(&mut self, callback: impl FnMut(&Pattern) + 'static) {
+        self.on_complete = Some(Box::new(callback));
+    }
+
+    pub fn set_max_time_span(&mut self, secs: Option<u64>) {
+        self.max_time_span_secs = secs;
+    }
+
+    pub fn set_max_pending_slots(&mut self, slots: u64) {
+        self.max_pending_slots = slots;
+    }
+
+    // Allows a pattern's swap-out to land up to this many slots after its create
+    // Pending legs are kept across blocks regardless, so feed blocks in order and prune them with prune_older_than
+    pub fn set_max_block_gap(&mut self, slots: u64) {
+        self.max_block_gap = slots;
+    }
+
+    // Evicts open positions and in-progress patterns whose latest leg is more than max_pending_slots behind
+    // slot, e.g. a failed attack or a dropped swap-out, so a long-running tracker doesn't grow unbounded
+    // An in-progress pattern is aged by its latest swap-in, so one still receiving legs is kept
+    // Returns the number of pending creates and in-progress patterns evicted
+    pub fn prune_older_than(&mut self, slot: u64) -> usize {
+        let cutoff: u64 = slot.saturating_sub(self.max_pending_slots);
+        let before: usize = self.pending_count();
+
+        self.open_positions.retain(|_, creates| {
+            creates.retain(|create_tx| create_tx.slot >= cutoff);
+            !creates.is_empty()
+        });
+        self.in_progress.retain(|_, builders| {
+            builders.retain(|builder| builder.last_leg().is_some_and(|leg| leg.slot >= cutoff));
+            !builders.is_empty()
+        });
+
+        before - self.pending_count()
+    }
+
+    // Forgets every pending, completed, and rejected pattern, keeping the tracker's settings and callbacks
+    // Used to start each block afresh when legs aren't allowed to span blocks
+    pub fn reset(&mut self) {
+        self.open_positions.clear();
+        self.in_progress.clear();
+        self.completed.clear();
+        self.rejected.clear();
+    }
+
+    // Number of creates and in-progress patterns still waiting for their next leg
+    fn pending_count(&self) -> usize {
+        self.open_positions.values().map(Vec::len).sum::<usize>()
+            + self.in_progress.values().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn process_transaction(&mut self, tx: ClassifiedTransaction) {
+        if let Some(callback) = self.on_classified.as_mut() {
+            callback(&tx);
+        }
+
+        match tx.instruction_type.as_str() {
+            "CreateSandwichV2" => {
+                // Push the create onto its sandwich account's stack, keeping any earlier unmatched creates
+                self.open_positions.entry(tx.sandwich_acc.clone()).or_default().push(tx);
+            }
+            "AutoSwapIn" => {
+                // If we find a matching create transaction, move both to in_progress
+                // A further swap-in with no create left joins the latest in-progress pattern instead of being dropped
+                let create_tx: Option<ClassifiedTransaction> = pop_last(&mut self.open_positions, &tx.sandwich_acc);
+
+                if let Some(create_tx) = create_tx {
+                    let builder: PatternBuilder = PatternBuilder::new()
+                        .with_create(create_tx)
+                        .with_max_time_span(self.max_time_span_secs)
+                        .with_max_block_gap(self.max_block_gap);
+                    self.in_progress
+                        .entry(tx.sandwich_acc.clone())
+                        .or_default()
+                        .push(builder.add_swap_in(tx));
+                } else if let Some(builder) = pop_last(&mut self.in_progress, &tx.sandwich_acc) {
+                    self.in_progress
+                        .entry(tx.sandwich_acc.clone())
+                        .or_default()
+                        .push(builder.add_swap_in(tx));
+                }
+            }
+            "AutoSwapOut" => {
+                // If we find matching in_progress transactions, try to create a pattern from the latest one
+                if let Some(builder) = pop_last(&mut self.in_progress, &tx.sandwich_acc) {
+                    match builder.with_close(tx).build_or_reject() {
+                        Some(Ok(pattern)) => self.completed.push(pattern),
+                        Some(Err(rejected)) => self.rejected.push(rejected),
+                        None => {}
+                    }
+                }
+            }
+            "Cashout" | "Exit" => {
+                // Withdrawing from the sandwich account realizes the latest completed pattern on it
+                // Only patterns still held by the tracker can be marked, so a cashout after take_completed is ignored
+                let pattern: Option<&mut Pattern> = self.completed.iter_mut().rev().find(|pattern| {
+                    pattern.create_tx().sandwich_acc == tx.sandwich_acc
+                        && pattern.cashout_tx.is_none()
+                        && pattern.swap_out_tx().slot <= tx.slot
+                });
+
+                if let Some(pattern) = pattern {
+                    pattern.cashout_tx = Some(tx);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get_completed_patterns(&self) -> &[Pattern] {
+        &self.completed
+    }
+
+    // Returns the completed patterns that made at least the given SOL profit, e.g. to ignore dust sandwiches
+    pub fn completed_above(&self, min_sol_profit: f64) -> Vec<&Pattern> {
+        self.completed
+            .iter()
+            .filter(|pattern| pattern.get_sol_profit() >= min_sol_profit)
+            .collect()
+    }
+
+    // Returns the completed patterns that were profitable for the attacker
+    pub fn profitable_only(&self) -> Vec<&Pattern> {
+        self.completed
+            .iter()
+            .filter(|pattern| pattern.is_profitable())
+            .collect()
     }
 
     pub fn clear_completed(&mut self) {
         self.completed.clear();
     }
+
+    // Moves the completed patterns out of the tracker
+    pub fn take_completed(&mut self) -> Vec<Pattern> {
+        std::mem::take(&mut self.completed)
+    }
+
+    // Hands each of the patterns to the on_complete callback, if one is set
+    // Callers feeding process_transaction themselves call this once they've finished enriching take_completed's
+    // patterns, so the callback never sees a half-built pattern
+    pub fn notify_complete(&mut self, patterns: &[Pattern]) {
+        if let Some(callback) = self.on_complete.as_mut() {
+            for pattern in patterns {
+                callback(pattern);
+            }
+        }
+    }
+
+    pub fn get_rejected_patterns(&self) -> &[(Pattern, RejectReason)] {
+        &self.rejected
+    }
+
+    // Moves the rejected patterns out of the tracker
+    pub fn take_rejected(&mut self) -> Vec<(Pattern, RejectReason)> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    // Explains every residual open position and in-progress pattern, ordered by slot
+    // This is the complement to the completed patterns, for tuning recall
+    pub fn explain_incomplete(&self) -> Vec<IncompletePattern> {
+        let orphaned_creates = self.open_positions.iter().flat_map(|(sandwich_acc, creates)| {
+            creates.iter().map(move |create_tx| {
+                IncompletePattern::from_leg(sandwich_acc, create_tx, 0, "Create seen, but no swap-in followed")
+            })
+        });
+
+        let stalled = self
+            .in_progress
+            .iter()
+            .flat_map(|(sandwich_acc, builders)| builders.iter().map(move |builder| (sandwich_acc, builder)))
+            .filter_map(|(sandwich_acc, builder)| {
+                let leg: &ClassifiedTransaction = builder.last_leg()?;
+
+                Some(IncompletePattern::from_leg(
+                    sandwich_acc,
+                    leg,
+                    builder.swap_in_txs.len(),
+                    "Create and swap-in seen, but the swap-out never arrived",
+                ))
+            });
+
+        let mut incomplete: Vec<IncompletePattern> = orphaned_creates.chain(stalled).collect();
+        incomplete.sort_by(|a, b| a.slot.cmp(&b.slot).then_with(|| a.sandwich_acc.cmp(&b.sandwich_acc)));
+
+        incomplete
+    }
+}
+
+// Pops the most recent entry from a sandwich account's stack, dropping the stack once it's empty
+fn pop_last<T>(stacks: &mut HashMap<String, Vec<T>>, sandwich_acc: &str) -> Option<T> {
+    let stack: &mut Vec<T> = stacks.get_mut(sandwich_acc)?;
+    let last: Option<T> = stack.pop();
+
+    if stack.is_empty() {
+        stacks.remove(sandwich_acc);
+    }
+
+    last
+}
+
+// Returns the n most profitable patterns by net SOL profit, most profitable first
+pub fn top_by_sol_profit(patterns: &[Pattern], n: usize) -> Vec<&Pattern> {
+    let mut ranked: Vec<&Pattern> = patterns.iter().collect();
+    ranked.sort_by(|a, b| b.get_sol_profit().total_cmp(&a.get_sol_profit()));
+    ranked.truncate(n);
+
+    ranked
+}
+
+// Sandwiches by one attacker on one pool over a scan, for studying persistent attackers
+#[derive(Debug, Clone, Serialize)]
+pub struct Campaign {
+    pub attacker: String,
+    pub pool: String,
+    pub pattern_count: usize,
+    // Distinct victims across the campaign's patterns, by signer, so a victim hit twice counts once
+    pub victim_count: usize,
+    pub total_sol_profit: f64,
+    pub first_slot: u64,
+    pub last_slot: u64,
+    pub first_block_time: Option<i64>,
+    pub last_block_time: Option<i64>,
+}
+
+impl Campaign {
+    // Seconds between the first and last sandwich, if both block times are known
+    pub fn time_span_secs(&self) -> Option<u64> {
+        Some(self.last_block_time?.saturating_sub(self.first_block_time?).max(0) as u64)
+    }
+
+    pub fn to_summary(&self) -> String {
+        format!(
+            "Attacker: {}\n\
+             Pool: {}\n\
+             Patterns: {} ({} distinct victims)\n\
+             Total SOL Profit: {:.9} SOL\n\
+             Slots: {} - {}\n\
+             Time Span: {}",
+            self.attacker,
+            self.pool,
+            self.pattern_count,
+            self.victim_count,
+            self.total_sol_profit,
+            self.first_slot,
+            self.last_slot,
+            self.time_span_secs()
+                .map(|secs| format!("{}s", secs))
+                .unwrap_or_else(|| "Unknown".to_string()),
+        )
+    }
+}
+
+// Groups patterns into campaigns keyed on (attacker, pool), most patterns first
+// Patterns whose pool couldn't be identified are left out
+pub fn aggregate_campaigns(patterns: &[Pattern]) -> Vec<Campaign> {
+    let mut campaigns: HashMap<(String, String), Campaign> = HashMap::new();
+    let mut victims: HashMap<(String, String), HashSet<String>> = HashMap::new();
+
+    for pattern in patterns {
+        let pool: &String = match &pattern.pool {
+            Some(pool) => pool,
+            None => continue,
+        };
+        let slot: u64 = pattern.create_tx().slot;
+        let block_time: Option<i64> = pattern.create_tx().block_time;
+
+        let key: (String, String) = (pattern.attacker.clone(), pool.clone());
+        let campaign_victims: &mut HashSet<String> = victims.entry(key.clone()).or_default();
+        campaign_victims.extend(pattern.victims.iter().map(|victim| victim.signer.clone()));
+
+        let campaign: &mut Campaign = campaigns.entry(key).or_insert_with(|| Campaign {
+            attacker: pattern.attacker.clone(),
+            pool: pool.clone(),
+            pattern_count: 0,
+            victim_count: 0,
+            total_sol_profit: 0.0,
+            first_slot: slot,
+            last_slot: slot,
+            first_block_time: block_time,
+            last_block_time: block_time,
+        });
+
+        campaign.pattern_count += 1;
+        campaign.victim_count = campaign_victims.len();
+        campaign.total_sol_profit += pattern.get_sol_profit();
+        campaign.first_slot = campaign.first_slot.min(slot);
+        campaign.last_slot = campaign.last_slot.max(slot);
+        campaign.first_block_time = match (campaign.first_block_time, block_time) {
+            (Some(first), Some(time)) => Some(first.min(time)),
+            (first, time) => first.or(time),
+        };
+        campaign.last_block_time = campaign.last_block_time.max(block_time);
+    }
+
+    let mut campaigns: Vec<Campaign> = campaigns.into_values().collect();
+    campaigns.sort_by(|a, b| {
+        b.pattern_count
+            .cmp(&a.pattern_count)
+            .then_with(|| a.attacker.cmp(&b.attacker))
+            .then_with(|| a.pool.cmp(&b.pool))
+    });
+
+    campaigns
+}
+
+// Totals for one attacker across a set of patterns
+#[derive(Debug, Clone, Serialize)]
+pub struct AttackerStats {
+    pub attacker: String,
+    pub attacker_label: Option<String>,
+    pub pattern_count: usize,
+    pub total_sol_profit: f64,
+    pub total_tips_lamports: u64,
+}
+
+impl AttackerStats {
+    // Jito tips paid per SOL of profit extracted; lower means the attacker bids more efficiently
+    // Undefined when the attacker made no profit
+    pub fn tip_efficiency(&self) -> Option<f64> {
+        if self.total_sol_profit <= 0.0 {
+            return None;
+        }
+
+        Some(self.total_tips_lamports as f64 / 1e9 / self.total_sol_profit)
+    }
+
+    pub fn to_summary(&self) -> String {
+        format!(
+            "Attacker: {}{}\n\
+             Patterns: {}\n\
+             Total SOL Profit: {:.9} SOL\n\
+             Total Jito Tips: {:.9} SOL",
+            self.attacker,
+            self.attacker_label
+                .as_ref()
+                .map(|label| format!(" ({})", label))
+                .unwrap_or_default(),
+            self.pattern_count,
+            self.total_sol_profit,
+            self.total_tips_lamports as f64 / 1e9,
+        )
+    }
+}
+
+// Aggregates patterns into per-attacker totals, in order of first appearance
+pub fn aggregate_attackers(patterns: &[Pattern]) -> Vec<AttackerStats> {
+    let mut stats: Vec<AttackerStats> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+
+    for pattern in patterns {
+        let i: usize = *index.entry(pattern.attacker.as_str()).or_insert_with(|| {
+            stats.push(AttackerStats {
+                attacker: pattern.attacker.clone(),
+                attacker_label: pattern.attacker_label.clone(),
+                pattern_count: 0,
+                total_sol_profit: 0.0,
+                total_tips_lamports: 0,
+            });
+            stats.len() - 1
+        });
+
+        let attacker: &mut AttackerStats = &mut stats[i];
+        attacker.pattern_count += 1;
+        attacker.total_sol_profit += pattern.get_sol_profit();
+        attacker.total_tips_lamports += pattern.get_total_jito_tips();
+    }
+
+    stats
+}
+
+// Returns the n attackers with the most total SOL profit, most profitable first
+pub fn top_attackers_by_profit(patterns: &[Pattern], n: usize) -> Vec<AttackerStats> {
+    let mut stats: Vec<AttackerStats> = aggregate_attackers(patterns);
+    stats.sort_by(|a, b| b.total_sol_profit.total_cmp(&a.total_sol_profit));
+    stats.truncate(n);
+
+    stats
+}
+
+// Sorts attackers from most to least tip-efficient, with attackers that made no profit last
+pub fn rank_by_tip_efficiency(stats: &mut [AttackerStats]) {
+    stats.sort_by(|a, b| match (a.tip_efficiency(), b.tip_efficiency()) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+}
+
+// Batches pattern output into periodic summaries so bursts of activity don't flood the console
+pub struct OutputThrottle {
+    interval: Duration,
+    // Totals follow the run's --sol-accounting, not whatever each pattern happened to be built with
+    sol_accounting: SolAccounting,
+    // When the first pattern of the current window was recorded
+    window_start: Option<Instant>,
+    count: usize,
+    sol_extracted: f64,
+}
+
+impl OutputThrottle {
+    pub fn new(interval: Duration, sol_accounting: SolAccounting) -> Self {
+        Self {
+            interval,
+            sol_accounting,
+            window_start: None,
+            count: 0,
+            sol_extracted: 0.0,
+        }
+    }
+
+    // Adds a pattern to the current window, returning the window's summary once the interval has elapsed
+    pub fn record(&mut self, pattern: &Pattern, now: Instant) -> Option<String> {
+        let start: Instant = *self.window_start.get_or_insert(now);
+        self.count += 1;
+        self.sol_extracted += pattern.get_sol_profit_as(self.sol_accounting);
+
+        if now.duration_since(start) >= self.interval {
+            return self.flush(now);
+        }
+
+        None
+    }
+
+    // Summarizes and resets the current window, if it has any patterns
+    pub fn flush(&mut self, now: Instant) -> Option<String> {
+        let start: Instant = self.window_start.take()?;
+        let summary: String = format!(
+            "{} sandwiches, {:.4} SOL extracted in last {}s",
+            self.count,
+            self.sol_extracted,
+            now.duration_since(start).as_secs().max(1)
+        );
+
+        self.count = 0;
+        self.sol_extracted = 0.0;
+
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandwich_pattern;
+    use std::{cell::Cell, rc::Rc};
+
+    // A leg of the given instruction type on one sandwich account, at the given position in slot 100
+    fn leg(instruction_type: &str, tx_index: usize) -> ClassifiedTransaction {
+        ClassifiedTransaction {
+            signature: format!("sig{}", tx_index),
+            signer: "attacker".to_string(),
+            slot: 100,
+            tx_index,
+            block_height: 90,
+            instruction_type: instruction_type.to_string(),
+            sandwich_acc: "sandwich".to_string(),
+            from_mint: "mint".to_string(),
+            ..ClassifiedTransaction::new()
+        }
+    }
+
+    fn feed_sandwich(tracker: &mut PatternTracker, first_index: usize) {
+        for (offset, instruction_type) in ["CreateSandwichV2", "AutoSwapIn", "AutoSwapOut"].iter().enumerate() {
+            tracker.process_transaction(leg(instruction_type, first_index + offset));
+        }
+    }
+
+    // The pattern completed by a create followed by the given swap legs
+    fn pattern(swap_in: ClassifiedTransaction, swap_out: ClassifiedTransaction) -> Pattern {
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(leg("CreateSandwichV2", 0));
+        tracker.process_transaction(swap_in);
+        tracker.process_transaction(swap_out);
+
+        let mut completed: Vec<Pattern> = tracker.take_completed();
+        assert_eq!(completed.len(), 1);
+        completed.remove(0)
+    }
+
+    // 1,000 SOL against 1,000,000 tokens of a 6-decimal token
+    const RESERVES: PoolReserves = PoolReserves {
+        token_reserve: 1_000_000_000_000,
+        sol_reserve: 1_000_000_000_000,
+    };
+
+    #[test]
+    fn completed_patterns_hold_the_legs_exactly_as_they_were_fed() {
+        let legs: Vec<ClassifiedTransaction> = vec![
+            ClassifiedTransaction {
+                lamport_change: -5_000,
+                ..leg("CreateSandwichV2", 0)
+            },
+            ClassifiedTransaction {
+                to_mint: "mint".to_string(),
+                from_amount: 50_000,
+                to_amount: 50_000,
+                wsol_change: Some(-2_000_000_000),
+                jito_tip_amount: 1_000,
+                pool: Some("pool".to_string()),
+                ..leg("AutoSwapIn", 1)
+            },
+            ClassifiedTransaction {
+                to_mint: "mint".to_string(),
+                from_amount: 50_000,
+                to_amount: 50_000,
+                wsol_change: Some(2_100_000_000),
+                block_time: Some(1_700_000_000),
+                ..leg("AutoSwapOut", 3)
+            },
+        ];
+        let expected: Vec<serde_json::Value> = legs.iter().map(|tx| serde_json::to_value(tx).unwrap()).collect();
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        for tx in legs {
+            tracker.process_transaction(tx);
+        }
+
+        let completed: &[Pattern] = tracker.get_completed_patterns();
+        assert_eq!(completed.len(), 1);
+
+        let (create_tx, swap_in_tx, swap_out_tx) = &completed[0].transactions;
+        let stored: Vec<serde_json::Value> = [create_tx, swap_in_tx, swap_out_tx]
+            .iter()
+            .map(|tx| serde_json::to_value(tx).unwrap())
+            .collect();
+        assert_eq!(stored, expected);
+        assert_eq!(completed[0].get_sol_profit_lamports(), 100_000_000 - 1_000);
+    }
+
+    #[test]
+    fn token_profit_in_ui_units_uses_the_swap_ins_decimals() {
+        let with_decimals = |tx: ClassifiedTransaction, from_amount: u64, decimals: u8| ClassifiedTransaction {
+            to_mint: "mint".to_string(),
+            from_amount,
+            to_amount: from_amount,
+            decimals,
+            ..tx
+        };
+
+        // 1.5 tokens of a 6-decimal token gained between the legs
+        let six: Pattern = pattern(
+            with_decimals(leg("AutoSwapIn", 1), 50_000_000, 6),
+            with_decimals(leg("AutoSwapOut", 3), 51_500_000, 6),
+        );
+        assert_eq!(six.get_token_profit(), 1_500_000);
+        assert_eq!(six.get_token_profit_ui(), 1.5);
+
+        // The swap-in's decimals apply, and losses stay negative
+        let loss: Pattern = pattern(
+            with_decimals(leg("AutoSwapIn", 1), 2_000_000, 6),
+            with_decimals(leg("AutoSwapOut", 3), 1_750_000, 9),
+        );
+        assert_eq!(loss.get_token_profit_ui(), -0.25);
+    }
+
+    #[test]
+    fn constant_product_output_and_price_impact_from_known_reserves() {
+        // 10 SOL in: dy = 1,000,000 * 10 / 1,010 tokens, and the spot price moves by 1 - (1,000 / 1,010)^2
+        assert_eq!(RESERVES.amount_out(10_000_000_000, true), Some(9_900_990_099));
+
+        let impact: f64 = RESERVES.price_impact(10_000_000_000, true).unwrap();
+        assert!((impact - 0.019704).abs() < 1e-6, "impact was {}", impact);
+
+        assert_eq!(
+            RESERVES.after_swap(10_000_000_000, true),
+            Some(PoolReserves {
+                token_reserve: 990_099_009_901,
+                sol_reserve: 1_010_000_000_000,
+            })
+        );
+        assert_eq!(
+            PoolReserves {
+                sol_reserve: 0,
+                ..RESERVES
+            }
+            .price_impact(1, true),
+            None
+        );
+    }
+
+    #[test]
+    fn pattern_price_impact_uses_the_front_run_and_reserves() {
+        let swap_in: ClassifiedTransaction = ClassifiedTransaction {
+            wsol_change: Some(-10_000_000_000),
+            reserves: Some(RESERVES),
+            ..leg("AutoSwapIn", 1)
+        };
+        let mut pattern: Pattern = pattern(swap_in, leg("AutoSwapOut", 3));
+
+        let impact: f64 = pattern.get_price_impact().unwrap();
+        assert!((impact - 0.019704).abs() < 1e-6, "impact was {}", impact);
+
+        // After the front-run the victim's 1 SOL buys fewer tokens than it would have against the original reserves
+        pattern.victims.push(VictimSwap {
+            signature: "victim".to_string(),
+            signer: "victim".to_string(),
+            signers: vec!["victim".to_string()],
+            token_account: "victim-ata".to_string(),
+            sol_in: 1_000_000_000,
+            tokens_received: 980_000_000,
+        });
+
+        assert_eq!(RESERVES.amount_out(1_000_000_000, true), Some(999_000_999));
+        let victim_impact: f64 = pattern.victim_price_impact().unwrap();
+        assert!((victim_impact - (999_000_999.0 / 980_000_000.0 - 1.0)).abs() < 1e-12);
+
+        let losses: Vec<VictimLoss> = pattern.get_victim_losses();
+        assert_eq!(losses.len(), 1);
+        assert_eq!(losses[0].token_loss, 19_000_999);
+    }
+
+    #[test]
+    fn amounts_format_in_base_or_ui_units_for_a_6_decimal_token() {
+        assert_eq!(AmountUnit::BaseUnits.format(1_500_000, 6), "1500000");
+        assert_eq!(AmountUnit::Ui.format(1_500_000, 6), "1.500000");
+        assert_eq!(AmountUnit::Ui.format(-2_000_001, 6), "-2.000001");
+        assert_eq!("raw".parse::<AmountUnit>(), Ok(AmountUnit::BaseUnits));
+        assert_eq!("ui".parse::<AmountUnit>(), Ok(AmountUnit::Ui));
+        assert!("lamports".parse::<AmountUnit>().is_err());
+    }
+
+    #[test]
+    fn the_same_seed_samples_the_same_slots() {
+        let sampled = |seed: u64| -> Vec<u64> {
+            let sampling: SlotSampling = SlotSampling::new(0.1, seed).unwrap();
+            (0..10_000).filter(|slot| sampling.includes(*slot)).collect()
+        };
+
+        assert_eq!(sampled(42), sampled(42));
+        assert_ne!(sampled(42), sampled(43));
+        // Roughly the rate, and scaled back up by it
+        assert!((900..1100).contains(&sampled(42).len()), "{}", sampled(42).len());
+        assert_eq!(SlotSampling::new(0.1, 42).unwrap().estimate(3), 30.0);
+
+        assert!(SlotSampling::new(0.0, 42).is_err());
+        assert!(SlotSampling::new(1.5, 42).is_err());
+    }
+
+    #[test]
+    fn a_custom_layout_reads_decimals_at_its_offset() {
+        let layout: MintLayout = "10".parse().unwrap();
+        assert_eq!(layout, MintLayout::Custom { decimals_offset: 10 });
+
+        let mut data: Vec<u8> = vec![0; 12];
+        data[10] = 9;
+        assert_eq!(layout.parse_decimals(&data).unwrap(), 9);
+
+        // A custom layout is kept whatever program owns the account, and only needs to reach its offset
+        assert_eq!(layout.for_owner("SomeOtherProgram").unwrap(), layout);
+        assert!(matches!(
+            layout.parse_decimals(&data[..10]),
+            Err(SandwichError::MalformedAccountData(_))
+        ));
+        assert!("offset-ten".parse::<MintLayout>().is_err());
+    }
+
+    #[test]
+    fn a_token_2022_mint_with_extensions_reads_decimals_from_the_base_layout() {
+        let layout: MintLayout = MintLayout::SplToken.for_owner(TOKEN_2022_PROGRAM).unwrap();
+        assert_eq!(layout, MintLayout::Token2022);
+
+        // Padded to the token account size, tagged as a mint, then a TLV extension
+        let mut data: Vec<u8> = vec![0; TOKEN_2022_ACCOUNT_TYPE_OFFSET + 1 + 8];
+        data[44] = 9;
+        data[TOKEN_2022_ACCOUNT_TYPE_OFFSET] = TOKEN_2022_ACCOUNT_TYPE_MINT;
+        assert_eq!(layout.parse_decimals(&data).unwrap(), 9);
+
+        // The bare base layout is a mint too, but an SPL Token mint is never extended
+        assert_eq!(layout.parse_decimals(&data[..MINT_BASE_LEN]).unwrap(), 9);
+        assert!(MintLayout::SplToken.parse_decimals(&data).is_err());
+
+        // An extended token account carries the same padding with a different type byte
+        data[TOKEN_2022_ACCOUNT_TYPE_OFFSET] = 2;
+        assert!(matches!(
+            layout.parse_decimals(&data),
+            Err(SandwichError::MalformedAccountData(_))
+        ));
+        // Longer than the base layout but short of the account type byte isn't a mint either
+        assert!(layout.parse_decimals(&data[..TOKEN_2022_ACCOUNT_TYPE_OFFSET]).is_err());
+    }
+
+    #[test]
+    fn summary_presents_swap_amounts_in_the_chosen_unit() {
+        let six_decimals = |leg: ClassifiedTransaction| ClassifiedTransaction {
+            decimals: 6,
+            decimals_resolved: true,
+            ..leg
+        };
+        let swap_in: ClassifiedTransaction = six_decimals(ClassifiedTransaction {
+            from_amount: 1_500_000,
+            ..leg("AutoSwapIn", 1)
+        });
+        let swap_out: ClassifiedTransaction = six_decimals(ClassifiedTransaction {
+            from_amount: 1_250_000,
+            ..leg("AutoSwapOut", 3)
+        });
+        let pattern: Pattern = pattern(swap_in, swap_out);
+
+        let ui: String = pattern.to_summary_in(AmountUnit::Ui);
+        assert!(ui.contains("- Swap In: sig1 (amount: 1.500000)"), "{}", ui);
+        assert!(ui.contains("- Swap Out: sig3 (amount: 1.250000)"), "{}", ui);
+
+        let base: String = pattern.to_summary_in(AmountUnit::BaseUnits);
+        assert!(base.contains("- Swap In: sig1 (amount: 1500000)"), "{}", base);
+        assert!(base.contains("- Swap Out: sig3 (amount: 1250000)"), "{}", base);
+    }
+
+    #[test]
+    fn summary_prints_the_fee_payer_and_every_signer() {
+        let swap_in: ClassifiedTransaction = ClassifiedTransaction {
+            fee_payer: "payer".to_string(),
+            signers: vec!["payer".to_string(), "co-signer".to_string()],
+            ..leg("AutoSwapIn", 1)
+        };
+        let summary: String = pattern(swap_in, leg("AutoSwapOut", 3)).to_summary();
+
+        assert!(summary.contains("Fee Payer: payer\n"), "{}", summary);
+        assert!(summary.contains("Signers: payer, co-signer\n"), "{}", summary);
+    }
+
+    #[test]
+    fn summary_formats_the_create_block_time_in_utc() {
+        let mut pattern: Pattern = pattern(leg("AutoSwapIn", 1), leg("AutoSwapOut", 3));
+        let summary_time = |pattern: &Pattern| -> String {
+            let summary: String = pattern.to_summary();
+            summary
+                .lines()
+                .find_map(|line| line.strip_prefix("Time: "))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(summary_time(&pattern), "Unknown");
+
+        pattern.transactions.0.block_time = Some(1_700_000_000);
+        assert_eq!(
+            pattern.create_tx().datetime().map(|dt| dt.to_rfc3339()).as_deref(),
+            Some("2023-11-14T22:13:20+00:00")
+        );
+        assert_eq!(summary_time(&pattern), "2023-11-14 22:13:20 UTC");
+
+        // Negative timestamps are kept as is, and ones chrono can't represent are called out
+        pattern.transactions.0.block_time = Some(-86_400);
+        assert_eq!(summary_time(&pattern), "1969-12-31 00:00:00 UTC");
+        pattern.transactions.0.block_time = Some(i64::MAX);
+        assert_eq!(pattern.create_tx().datetime(), None);
+        assert_eq!(summary_time(&pattern), "Invalid timestamp");
+    }
+
+    #[test]
+    fn json_presents_the_token_profit_in_the_chosen_unit() {
+        let six_decimals = |leg: ClassifiedTransaction| ClassifiedTransaction {
+            to_mint: leg.from_mint.clone(),
+            decimals: 6,
+            decimals_resolved: true,
+            ..leg
+        };
+        let swap_in: ClassifiedTransaction = six_decimals(ClassifiedTransaction {
+            from_amount: 1_250_000,
+            ..leg("AutoSwapIn", 1)
+        });
+        let swap_out: ClassifiedTransaction = six_decimals(ClassifiedTransaction {
+            from_amount: 1_500_000,
+            ..leg("AutoSwapOut", 3)
+        });
+        let pattern: Pattern = pattern(swap_in, swap_out);
+        let json = |unit: AmountUnit| -> serde_json::Value {
+            serde_json::from_str(&pattern.to_json_in(unit).unwrap()).unwrap()
+        };
+
+        let ui: serde_json::Value = json(AmountUnit::Ui);
+        assert_eq!(ui["amount_unit"], "ui");
+        assert_eq!(ui["token_profit"], 0.25);
+
+        let base: serde_json::Value = json(AmountUnit::BaseUnits);
+        assert_eq!(base["amount_unit"], "base_units");
+        assert_eq!(base["token_profit"], 250_000);
+        assert_eq!(
+            pattern.to_json().unwrap(),
+            pattern.to_json_in(AmountUnit::BaseUnits).unwrap()
+        );
+    }
+
+    #[test]
+    fn summary_falls_back_to_base_units_without_decimals() {
+        let swap_in: ClassifiedTransaction = ClassifiedTransaction {
+            from_amount: 1_500_000,
+            ..leg("AutoSwapIn", 1)
+        };
+        let summary: String = pattern(swap_in, leg("AutoSwapOut", 3)).to_summary_in(AmountUnit::Ui);
+
+        assert!(summary.contains("- Swap In: sig1 (amount: 1500000)"), "{}", summary);
+        assert!(summary.contains("base units"), "{}", summary);
+    }
+
+    #[test]
+    fn a_second_swap_in_before_the_swap_out_joins_the_pattern() {
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(leg("CreateSandwichV2", 0));
+        tracker.process_transaction(leg("AutoSwapIn", 1));
+        tracker.process_transaction(leg("AutoSwapIn", 2));
+        tracker.process_transaction(leg("AutoSwapOut", 3));
+
+        let completed: Vec<Pattern> = tracker.take_completed();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].swap_in_tx().signature, "sig1");
+        let additional: Vec<&str> = completed[0]
+            .additional_swap_ins
+            .iter()
+            .map(|tx| tx.signature.as_str())
+            .collect();
+        assert_eq!(additional, ["sig2"]);
+        assert_eq!(tracker.pending_count(), 0);
+    }
+
+    #[test]
+    fn interleaved_creates_on_different_sandwich_accounts_give_two_patterns() {
+        let on = |sandwich_acc: &str, instruction_type: &str, tx_index: usize| ClassifiedTransaction {
+            signature: format!("{}-sig{}", sandwich_acc, tx_index),
+            sandwich_acc: sandwich_acc.to_string(),
+            ..leg(instruction_type, tx_index)
+        };
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(on("first", "CreateSandwichV2", 0));
+        tracker.process_transaction(on("second", "CreateSandwichV2", 1));
+        tracker.process_transaction(on("first", "AutoSwapIn", 2));
+        tracker.process_transaction(on("second", "AutoSwapIn", 3));
+        tracker.process_transaction(on("second", "AutoSwapOut", 5));
+        tracker.process_transaction(on("first", "AutoSwapOut", 6));
+
+        let completed: Vec<Pattern> = tracker.take_completed();
+        let legs: Vec<[&str; 3]> = completed
+            .iter()
+            .map(|pattern| {
+                [
+                    pattern.create_tx().signature.as_str(),
+                    pattern.swap_in_tx().signature.as_str(),
+                    pattern.swap_out_tx().signature.as_str(),
+                ]
+            })
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                ["second-sig1", "second-sig3", "second-sig5"],
+                ["first-sig0", "first-sig2", "first-sig6"],
+            ]
+        );
+    }
+
+    #[test]
+    fn a_swap_out_one_block_later_is_valid_within_the_block_gap() {
+        let at = |instruction_type: &str, tx_index: usize, slot: u64| ClassifiedTransaction {
+            slot,
+            block_height: slot - 10,
+            block_time: Some(1_700_000_000 + slot as i64),
+            to_mint: "mint".to_string(),
+            ..leg(instruction_type, tx_index)
+        };
+        let feed = |tracker: &mut PatternTracker| {
+            tracker.process_transaction(at("CreateSandwichV2", 0, 100));
+            tracker.process_transaction(at("AutoSwapIn", 1, 100));
+            tracker.process_transaction(at("AutoSwapOut", 0, 101));
+            tracker.take_completed()
+        };
+
+        let mut adjacent: PatternTracker = PatternTracker::new();
+        adjacent.set_max_block_gap(1);
+        let completed: Vec<Pattern> = feed(&mut adjacent);
+        assert_eq!(completed.len(), 1);
+        assert!(completed[0].is_valid());
+        assert_eq!(completed[0].swap_out_tx().slot, 101);
+
+        // The same pattern is invalid once the swap-out is further away than the gap allows
+        let mut same_block: Pattern = completed[0].clone();
+        same_block.max_block_gap = 0;
+        assert!(!same_block.is_valid());
+
+        let mut strict: PatternTracker = PatternTracker::new();
+        strict.set_max_block_gap(0);
+        assert!(feed(&mut strict).is_empty());
+    }
+
+    #[test]
+    fn completed_patterns_filter_by_profit() {
+        let on = |sandwich_acc: &str, instruction_type: &str, tx_index: usize, amount: u64, wsol: i128| {
+            ClassifiedTransaction {
+                signature: format!("{}-sig{}", sandwich_acc, tx_index),
+                sandwich_acc: sandwich_acc.to_string(),
+                from_amount: amount,
+                wsol_change: Some(wsol),
+                ..leg(instruction_type, tx_index)
+            }
+        };
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        // 0.2 SOL made, then 0.1 SOL lost
+        for (sandwich_acc, first_index, amount_out, wsol_out) in
+            [("won", 0, 110, 1_200_000_000), ("lost", 4, 90, 900_000_000)]
+        {
+            tracker.process_transaction(on(sandwich_acc, "CreateSandwichV2", first_index, 0, 0));
+            tracker.process_transaction(on(sandwich_acc, "AutoSwapIn", first_index + 1, 100, -1_000_000_000));
+            tracker.process_transaction(on(sandwich_acc, "AutoSwapOut", first_index + 3, amount_out, wsol_out));
+        }
+        assert_eq!(tracker.get_completed_patterns().len(), 2);
+
+        let accounts = |patterns: Vec<&Pattern>| -> Vec<String> {
+            patterns
+                .iter()
+                .map(|pattern| pattern.create_tx().sandwich_acc.clone())
+                .collect()
+        };
+        assert_eq!(accounts(tracker.completed_above(0.1)), ["won"]);
+        assert_eq!(accounts(tracker.completed_above(-0.5)), ["won", "lost"]);
+        assert!(tracker.completed_above(0.3).is_empty());
+        assert_eq!(accounts(tracker.profitable_only()), ["won"]);
+    }
+
+    #[test]
+    fn a_cashout_after_the_swap_out_realizes_the_pattern() {
+        let mut tracker: PatternTracker = PatternTracker::new();
+        feed_sandwich(&mut tracker, 0);
+        assert!(!tracker.get_completed_patterns()[0].is_realized());
+
+        // A cashout from another sandwich account leaves this one on paper
+        tracker.process_transaction(ClassifiedTransaction {
+            sandwich_acc: "elsewhere".to_string(),
+            ..leg("Cashout", 4)
+        });
+        assert!(!tracker.get_completed_patterns()[0].is_realized());
+
+        tracker.process_transaction(leg("Cashout", 5));
+        let pattern: &Pattern = &tracker.get_completed_patterns()[0];
+        assert!(pattern.is_realized());
+        assert_eq!(
+            pattern.cashout_tx.as_ref().map(|tx| tx.signature.as_str()),
+            Some("sig5")
+        );
+        assert!(pattern.to_summary().contains("Realized: Yes (sig5)"));
+
+        let json: serde_json::Value = serde_json::from_str(&pattern.to_json().unwrap()).unwrap();
+        assert_eq!(json["realized"], true);
+    }
+
+    #[test]
+    fn legs_with_equal_or_missing_block_times_are_ordered_by_position() {
+        let timed =
+            |instruction_type: &str, tx_index: usize, slot: u64, block_time: Option<i64>| ClassifiedTransaction {
+                slot,
+                block_time,
+                ..leg(instruction_type, tx_index)
+            };
+        let new = |times: [Option<i64>; 3], slots: [u64; 3], indices: [usize; 3]| {
+            Pattern::new(
+                timed("CreateSandwichV2", indices[0], slots[0], times[0]),
+                timed("AutoSwapIn", indices[1], slots[1], times[1]),
+                timed("AutoSwapOut", indices[2], slots[2], times[2]),
+            )
+        };
+        let same_block: Option<i64> = Some(1_700_000_000);
+
+        // Every leg in one block shares its time, so their positions decide
+        assert!(new([same_block; 3], [100; 3], [0, 1, 3]).is_some());
+        assert!(new([same_block; 3], [100; 3], [3, 1, 0]).is_none());
+
+        // A leg without a time neither sorts before the others nor stops them being ordered
+        assert!(new([None, same_block, same_block], [100; 3], [0, 1, 3]).is_some());
+        assert!(new([same_block, None, Some(1_700_000_001)], [100, 100, 101], [0, 1, 0]).is_some());
+        assert!(new([None; 3], [100; 3], [0, 1, 3]).is_some());
+        assert!(new([None, None, same_block], [101, 100, 100], [0, 1, 3]).is_none());
+
+        // Known times still have to agree with the slots
+        assert!(new([Some(1_700_000_001), None, same_block], [100, 100, 101], [0, 1, 0]).is_none());
+    }
+
+    #[test]
+    fn same_slot_legs_fed_with_out_of_order_indices_are_rejected() {
+        // All three legs share a slot and block time, and only their indices show the swap-out came first
+        assert!(Pattern::new(leg("CreateSandwichV2", 0), leg("AutoSwapIn", 5), leg("AutoSwapOut", 3)).is_none());
+        assert!(Pattern::new(leg("CreateSandwichV2", 4), leg("AutoSwapIn", 2), leg("AutoSwapOut", 6)).is_none());
+        assert!(Pattern::new(leg("CreateSandwichV2", 0), leg("AutoSwapIn", 3), leg("AutoSwapOut", 5)).is_some());
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(leg("CreateSandwichV2", 0));
+        tracker.process_transaction(leg("AutoSwapIn", 5));
+        tracker.process_transaction(leg("AutoSwapOut", 3));
+        assert!(tracker.take_completed().is_empty());
+    }
+
+    #[test]
+    fn swaps_of_different_tokens_are_rejected_as_a_mint_mismatch() {
+        let trading = |mint: &str, tx: ClassifiedTransaction| ClassifiedTransaction {
+            from_mint: mint.to_string(),
+            to_mint: mint.to_string(),
+            ..tx
+        };
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(leg("CreateSandwichV2", 0));
+        tracker.process_transaction(trading("mint", leg("AutoSwapIn", 1)));
+        tracker.process_transaction(trading("other-mint", leg("AutoSwapOut", 3)));
+
+        assert!(tracker.get_completed_patterns().is_empty());
+        let rejected: &[(Pattern, RejectReason)] = tracker.get_rejected_patterns();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].1, RejectReason::MintMismatch);
+        assert_eq!(rejected[0].0.swap_out_tx().from_mint, "other-mint");
+
+        // The same legs on one token complete instead
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.process_transaction(leg("CreateSandwichV2", 0));
+        tracker.process_transaction(trading("mint", leg("AutoSwapIn", 1)));
+        tracker.process_transaction(trading("mint", leg("AutoSwapOut", 3)));
+        assert_eq!(tracker.get_completed_patterns().len(), 1);
+        assert!(tracker.get_rejected_patterns().is_empty());
+    }
+
+    #[test]
+    fn legs_minutes_apart_are_rejected_by_the_max_time_span() {
+        let at = |instruction_type: &str, tx_index: usize, slot: u64, block_time: i64| ClassifiedTransaction {
+            slot,
+            block_time: Some(block_time),
+            ..leg(instruction_type, tx_index)
+        };
+        let feed = |swap_out_time: i64| -> PatternTracker {
+            let mut tracker: PatternTracker = PatternTracker::new();
+            tracker.set_max_time_span(Some(60));
+            tracker.set_max_block_gap(1_000);
+            tracker.process_transaction(at("CreateSandwichV2", 0, 100, 1_700_000_000));
+            tracker.process_transaction(at("AutoSwapIn", 1, 100, 1_700_000_000));
+            tracker.process_transaction(at("AutoSwapOut", 0, 600, swap_out_time));
+            tracker
+        };
+
+        // Five minutes after the create, e.g. a stale create paired with an exit after the account was reused
+        let mut stale: PatternTracker = feed(1_700_000_300);
+        assert!(stale.take_completed().is_empty());
+        let rejected: Vec<(Pattern, RejectReason)> = stale.take_rejected();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].1, RejectReason::TimeSpanExceeded);
+
+        // Within the window it completes
+        assert_eq!(feed(1_700_000_060).take_completed().len(), 1);
+    }
+
+    #[test]
+    fn builder_needs_every_leg_on_one_sandwich_account() {
+        let built: Pattern = PatternBuilder::new()
+            .with_create(leg("CreateSandwichV2", 0))
+            .add_swap_in(leg("AutoSwapIn", 1))
+            .add_swap_in(leg("AutoSwapIn", 2))
+            .with_close(leg("AutoSwapOut", 3))
+            .build()
+            .unwrap();
+        assert_eq!(built.swap_in_tx().signature, "sig1");
+        assert_eq!(built.additional_swap_ins.len(), 1);
+        assert_eq!(built.swap_out_tx().signature, "sig3");
+
+        // Missing legs
+        assert!(PatternBuilder::new()
+            .add_swap_in(leg("AutoSwapIn", 1))
+            .with_close(leg("AutoSwapOut", 3))
+            .build()
+            .is_none());
+        assert!(PatternBuilder::new()
+            .with_create(leg("CreateSandwichV2", 0))
+            .with_close(leg("AutoSwapOut", 3))
+            .build()
+            .is_none());
+        assert!(PatternBuilder::new()
+            .with_create(leg("CreateSandwichV2", 0))
+            .add_swap_in(leg("AutoSwapIn", 1))
+            .build()
+            .is_none());
+
+        // A later swap-in on another sandwich account
+        let elsewhere: ClassifiedTransaction = ClassifiedTransaction {
+            sandwich_acc: "other".to_string(),
+            ..leg("AutoSwapIn", 2)
+        };
+        assert!(PatternBuilder::new()
+            .with_create(leg("CreateSandwichV2", 0))
+            .add_swap_in(leg("AutoSwapIn", 1))
+            .add_swap_in(elsewhere)
+            .with_close(leg("AutoSwapOut", 3))
+            .build()
+            .is_none());
+
+        // Legs out of order
+        assert!(PatternBuilder::new()
+            .with_create(leg("CreateSandwichV2", 2))
+            .add_swap_in(leg("AutoSwapIn", 1))
+            .with_close(leg("AutoSwapOut", 3))
+            .build()
+            .is_none());
+
+        // Legs that match but land further apart than the max block gap are handed back with the reason
+        let later: ClassifiedTransaction = ClassifiedTransaction {
+            slot: 102,
+            block_height: 92,
+            ..leg("AutoSwapOut", 0)
+        };
+        let rejected = PatternBuilder::new()
+            .with_create(leg("CreateSandwichV2", 0))
+            .add_swap_in(leg("AutoSwapIn", 1))
+            .with_close(later)
+            .with_max_block_gap(1)
+            .build_or_reject();
+        assert!(matches!(rejected, Some(Err((_, RejectReason::BlockGapExceeded)))));
+    }
+
+    fn victim(signers: &[&str]) -> VictimSwap {
+        VictimSwap {
+            signature: "victim".to_string(),
+            signer: signers[0].to_string(),
+            signers: signers.iter().map(|signer| signer.to_string()).collect(),
+            token_account: "victim-ata".to_string(),
+            sol_in: 1_000_000_000,
+            tokens_received: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn wash_needs_a_victim_signed_by_the_attacker_and_no_profit() {
+        let co_signed = |tx: ClassifiedTransaction| ClassifiedTransaction {
+            signers: vec!["attacker".to_string(), "bot-authority".to_string()],
+            ..tx
+        };
+        let mut pattern: Pattern = pattern(co_signed(leg("AutoSwapIn", 1)), co_signed(leg("AutoSwapOut", 3)));
+
+        // Not a self-sandwich without any victim, however flat the profit
+        assert!(!pattern.detect_wash());
+
+        pattern.victims = vec![victim(&["someone-else"])];
+        assert!(!pattern.detect_wash());
+
+        // A victim co-signed by the bot's authority is the bot trading against itself
+        pattern.victims = vec![victim(&["fresh-wallet", "bot-authority"])];
+        assert!(pattern.detect_wash());
+
+        // A real profit makes it an extractive sandwich even with a related victim
+        pattern.transactions.2.wsol_change = Some(5_000_000_000);
+        assert!(!pattern.detect_wash());
+    }
+
+    // A pattern whose swap-out received the given wSOL, in lamports, on its own sandwich account
+    fn pattern_with_profit(sandwich_acc: &str, wsol_out: i128) -> Pattern {
+        let mut pattern: Pattern = sandwich_pattern(sandwich_acc);
+        pattern.transactions.2.wsol_change = Some(wsol_out);
+
+        pattern
+    }
+
+    #[test]
+    fn top_by_sol_profit_keeps_only_the_k_most_profitable() {
+        let patterns: Vec<Pattern> = [
+            ("a", 2_000_000_000),
+            ("b", -1_000_000_000),
+            ("c", 7_000_000_000),
+            ("d", 0),
+        ]
+        .into_iter()
+        .map(|(sandwich_acc, wsol_out)| pattern_with_profit(sandwich_acc, wsol_out))
+        .collect();
+
+        let top: Vec<&str> = top_by_sol_profit(&patterns, 2)
+            .into_iter()
+            .map(|pattern| pattern.create_tx().sandwich_acc.as_str())
+            .collect();
+        assert_eq!(top, ["c", "a"]);
+
+        // Asking for more than there are reports them all, still by profit
+        assert_eq!(top_by_sol_profit(&patterns, 10).len(), 4);
+        assert!(top_by_sol_profit(&patterns, 0).is_empty());
+    }
+
+    #[test]
+    fn the_leaderboard_ranks_attackers_by_their_total_sol_profit() {
+        let by = |attacker: &str, sandwich_acc: &str, wsol_out: i128, tip: u64| {
+            let mut pattern: Pattern = Pattern {
+                attacker: attacker.to_string(),
+                ..pattern_with_profit(sandwich_acc, wsol_out)
+            };
+            pattern.transactions.1.jito_tip_amount = tip;
+
+            pattern
+        };
+        // The busier attacker makes less in total than the one with a single large sandwich
+        let patterns: Vec<Pattern> = vec![
+            by("busy", "a", 1_000_000_000, 1_000),
+            by("big", "b", 5_000_000_000, 2_000),
+            by("busy", "c", 1_000_000_000, 3_000),
+            by("losing", "d", -1_000_000_000, 0),
+        ];
+
+        let profit = |sandwich_accs: &[&str]| -> f64 {
+            patterns
+                .iter()
+                .filter(|pattern| sandwich_accs.contains(&pattern.create_tx().sandwich_acc.as_str()))
+                .map(Pattern::get_sol_profit)
+                .sum()
+        };
+
+        let top: Vec<AttackerStats> = top_attackers_by_profit(&patterns, 2);
+        assert_eq!(
+            top.iter().map(|stats| stats.attacker.as_str()).collect::<Vec<&str>>(),
+            ["big", "busy"]
+        );
+        assert_eq!(top[0].pattern_count, 1);
+        assert_eq!(top[0].total_sol_profit, profit(&["b"]));
+        assert_eq!(top[0].total_tips_lamports, 2_000);
+        assert_eq!(top[1].pattern_count, 2);
+        assert_eq!(top[1].total_sol_profit, profit(&["a", "c"]));
+        assert_eq!(top[1].total_tips_lamports, 4_000);
+        assert!(top[0].to_summary().contains("Attacker: big"));
+
+        let all: Vec<AttackerStats> = top_attackers_by_profit(&patterns, 10);
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[2].attacker, "losing");
+    }
+
+    #[test]
+    fn campaigns_group_by_attacker_and_pool_and_count_distinct_victims() {
+        let on_pool = |sandwich_acc: &str, pool: Option<&str>, victims: &[&str]| Pattern {
+            pool: pool.map(str::to_string),
+            victims: victims.iter().map(|signer| victim(&[signer])).collect(),
+            ..pattern_with_profit(sandwich_acc, 1_000_000_000)
+        };
+        let patterns: Vec<Pattern> = vec![
+            on_pool("a", Some("pool-1"), &["alice"]),
+            on_pool("b", Some("pool-1"), &["alice", "bob"]),
+            on_pool("c", Some("pool-2"), &["carol"]),
+            on_pool("d", None, &["dave"]),
+        ];
+
+        let campaigns: Vec<Campaign> = aggregate_campaigns(&patterns);
+        assert_eq!(campaigns.len(), 2);
+
+        let campaign: &Campaign = &campaigns[0];
+        assert_eq!((campaign.pool.as_str(), campaign.pattern_count), ("pool-1", 2));
+        // Alice was sandwiched twice on the same pool, which is still one victim
+        assert_eq!(campaign.victim_count, 2);
+        assert_eq!(campaign.total_sol_profit, 2.0 * patterns[0].get_sol_profit());
+
+        assert_eq!((campaigns[1].pool.as_str(), campaigns[1].victim_count), ("pool-2", 1));
+    }
+
+    #[test]
+    fn registered_attackers_are_labeled() {
+        let path: std::path::PathBuf = std::env::temp_dir().join(format!("bot-registry-{}.json", std::process::id()));
+        fs::write(&path, r#"{ "attacker": "arsc" }"#).unwrap();
+        let registry: BotRegistry = BotRegistry::from_json_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut registered: Pattern = sandwich_pattern("a");
+        registry.label_pattern(&mut registered);
+        assert_eq!(registered.attacker_label.as_deref(), Some("arsc"));
+        assert!(
+            registered.to_summary().contains("attacker (arsc)"),
+            "{}",
+            registered.to_summary()
+        );
+
+        let mut unknown: Pattern = Pattern {
+            attacker: "someone-else".to_string(),
+            ..sandwich_pattern("b")
+        };
+        registry.label_pattern(&mut unknown);
+        assert_eq!(unknown.attacker_label, None);
+    }
+
+    #[test]
+    fn tip_efficiency_is_tips_per_sol_of_profit_and_ranks_the_cheapest_bidder_first() {
+        let stats = |attacker: &str, total_sol_profit: f64, total_tips_lamports: u64| AttackerStats {
+            attacker: attacker.to_string(),
+            attacker_label: None,
+            pattern_count: 1,
+            total_sol_profit,
+            total_tips_lamports,
+        };
+
+        // 0.05 SOL of tips for 2 SOL of profit
+        assert_eq!(stats("known", 2.0, 50_000_000).tip_efficiency(), Some(0.025));
+        assert_eq!(stats("broke-even", 0.0, 50_000_000).tip_efficiency(), None);
+        assert_eq!(stats("lost", -1.0, 50_000_000).tip_efficiency(), None);
+
+        let mut ranked: Vec<AttackerStats> = vec![
+            stats("lost", -1.0, 1_000),
+            stats("overpays", 1.0, 500_000_000),
+            stats("broke-even", 0.0, 0),
+            stats("efficient", 1.0, 10_000_000),
+        ];
+        rank_by_tip_efficiency(&mut ranked);
+
+        let order: Vec<&str> = ranked.iter().map(|stats| stats.attacker.as_str()).collect();
+        assert_eq!(order[..2], ["efficient", "overpays"]);
+        // Attackers without a profit sort last
+        assert!(order[2..]
+            .iter()
+            .all(|attacker| ["lost", "broke-even"].contains(attacker)));
+    }
+
+    fn tipping(tip: u64, tx: ClassifiedTransaction) -> ClassifiedTransaction {
+        ClassifiedTransaction {
+            jito_tip_amount: tip,
+            ..tx
+        }
+    }
+
+    #[test]
+    fn jito_bundle_needs_a_tip_and_every_leg_in_one_slot() {
+        let all_tip: Pattern = pattern(
+            tipping(1_000, leg("AutoSwapIn", 1)),
+            tipping(1_000, leg("AutoSwapOut", 3)),
+        );
+        assert!(all_tip.via_jito_bundle);
+
+        let none_tip: Pattern = pattern(leg("AutoSwapIn", 1), leg("AutoSwapOut", 3));
+        assert!(!none_tip.via_jito_bundle);
+
+        // A tip on a leg that landed a slot later doesn't make the legs one bundle
+        let later: ClassifiedTransaction = ClassifiedTransaction {
+            slot: 101,
+            block_height: 91,
+            ..tipping(1_000, leg("AutoSwapOut", 5))
+        };
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.set_max_block_gap(1);
+        tracker.process_transaction(leg("CreateSandwichV2", 0));
+        tracker.process_transaction(leg("AutoSwapIn", 1));
+        tracker.process_transaction(later);
+
+        let spread: Vec<Pattern> = tracker.take_completed();
+        assert_eq!(spread.len(), 1);
+        assert!(!spread[0].via_jito_bundle);
+    }
+
+    #[test]
+    fn a_tip_the_fee_payer_paid_is_only_counted_once() {
+        // The swap-in's signer paid a 1,000 lamport tip and a 5,000 lamport fee out of its own balance
+        let swap_in: ClassifiedTransaction = ClassifiedTransaction {
+            sol_equivalent_change: Some(-2_000_006_000),
+            tip_paid_by_signer: true,
+            ..tipping(1_000, leg("AutoSwapIn", 1))
+        };
+        let swap_out: ClassifiedTransaction = ClassifiedTransaction {
+            sol_equivalent_change: Some(2_099_995_000),
+            ..leg("AutoSwapOut", 3)
+        };
+        let mut funded: Pattern = pattern(swap_in.clone(), swap_out.clone());
+        funded.sol_accounting = SolAccounting::Unified;
+        assert_eq!(swap_in.tip_outside_balance(), 0);
+        assert!(
+            (funded.get_sol_profit() - 0.099989).abs() < 1e-12,
+            "{}",
+            funded.get_sol_profit()
+        );
+
+        // The same tip paid from another account isn't in the signer's delta, so it's subtracted instead
+        let unfunded_in: ClassifiedTransaction = ClassifiedTransaction {
+            sol_equivalent_change: Some(-2_000_005_000),
+            tip_paid_by_signer: false,
+            ..swap_in
+        };
+        let mut unfunded: Pattern = pattern(unfunded_in, swap_out);
+        unfunded.sol_accounting = SolAccounting::Unified;
+        assert_eq!(unfunded.get_sol_profit(), funded.get_sol_profit());
+    }
+
+    #[test]
+    fn throttled_output_aggregates_a_burst_into_one_line_per_interval() {
+        // 0.25 SOL by the signer's balance, nothing by the wSOL legs
+        let swap_in: ClassifiedTransaction = ClassifiedTransaction {
+            sol_equivalent_change: Some(-1_000_000_000),
+            ..leg("AutoSwapIn", 1)
+        };
+        let swap_out: ClassifiedTransaction = ClassifiedTransaction {
+            sol_equivalent_change: Some(1_250_000_000),
+            ..leg("AutoSwapOut", 3)
+        };
+        let burst: Pattern = pattern(swap_in, swap_out);
+        assert_eq!(burst.sol_accounting, SolAccounting::Separate);
+
+        let start: Instant = Instant::now();
+        let mut throttle: OutputThrottle = OutputThrottle::new(Duration::from_secs(10), SolAccounting::Unified);
+
+        assert_eq!(throttle.record(&burst, start), None);
+        assert_eq!(throttle.record(&burst, start + Duration::from_secs(3)), None);
+        assert_eq!(
+            throttle.record(&burst, start + Duration::from_secs(10)).as_deref(),
+            Some("3 sandwiches, 0.7500 SOL extracted in last 10s")
+        );
+
+        // A new window opens with the next pattern, and flushing reports what's left of it exactly once
+        assert_eq!(throttle.record(&burst, start + Duration::from_secs(12)), None);
+        assert_eq!(
+            throttle.flush(start + Duration::from_secs(12)).as_deref(),
+            Some("1 sandwiches, 0.2500 SOL extracted in last 1s")
+        );
+        assert_eq!(throttle.flush(start + Duration::from_secs(20)), None);
+    }
+
+    #[test]
+    fn pruning_evicts_a_stale_create_and_keeps_a_recent_one() {
+        let on = |sandwich_acc: &str, slot: u64, instruction_type: &str, tx_index: usize| ClassifiedTransaction {
+            signature: format!("{}-sig{}", sandwich_acc, tx_index),
+            sandwich_acc: sandwich_acc.to_string(),
+            slot,
+            ..leg(instruction_type, tx_index)
+        };
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.set_max_pending_slots(10);
+        // Legs may follow their create by a few slots, so only pruning keeps the stale one from completing
+        tracker.set_max_block_gap(30);
+        tracker.process_transaction(on("stale", 100, "CreateSandwichV2", 0));
+        tracker.process_transaction(on("recent", 115, "CreateSandwichV2", 0));
+
+        // Slot 120 puts the cutoff at 110, between the two creates
+        assert_eq!(tracker.prune_older_than(120), 1);
+        assert_eq!(tracker.prune_older_than(120), 0);
+
+        for sandwich_acc in ["stale", "recent"] {
+            tracker.process_transaction(on(sandwich_acc, 120, "AutoSwapIn", 1));
+            tracker.process_transaction(on(sandwich_acc, 120, "AutoSwapOut", 3));
+        }
+
+        let completed: Vec<Pattern> = tracker.take_completed();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].create_tx().sandwich_acc, "recent");
+    }
+
+    #[test]
+    fn gross_profit_less_tips_and_fees_is_the_net_profit() {
+        let swap_in: ClassifiedTransaction = ClassifiedTransaction {
+            wsol_change: Some(-2_000_000_000),
+            fee: 5_000,
+            ..tipping(10_000, leg("AutoSwapIn", 1))
+        };
+        let swap_out: ClassifiedTransaction = ClassifiedTransaction {
+            wsol_change: Some(2_100_000_000),
+            fee: 25_000,
+            ..tipping(40_000, leg("AutoSwapOut", 3))
+        };
+        let pattern: Pattern = pattern(swap_in, swap_out);
+
+        assert_eq!(pattern.get_gross_sol_profit(), 0.1);
+        assert_eq!(pattern.get_jito_tip_cost(), 0.00005);
+        assert_eq!(pattern.get_fee_cost(), 0.00003);
+        assert_eq!(pattern.get_sol_profit_lamports(), 100_000_000 - 50_000 - 30_000);
+        assert!(
+            (pattern.get_gross_sol_profit()
+                - pattern.get_jito_tip_cost()
+                - pattern.get_fee_cost()
+                - pattern.get_sol_profit())
+            .abs()
+                < 1e-12
+        );
+
+        let summary: String = pattern.to_summary();
+        assert!(summary.contains("SOL Profit: 0.099920000 SOL\n"), "{}", summary);
+        assert!(
+            summary
+                .contains("Gross SOL Profit: 0.100000000 SOL (costs: 0.000050000 SOL Jito tip, 0.000030000 SOL fees)"),
+            "{}",
+            summary
+        );
+    }
+
+    #[test]
+    fn jito_tips_are_summed_over_every_leg() {
+        let pattern: Pattern = pattern(
+            tipping(1_000, leg("AutoSwapIn", 1)),
+            tipping(2_500, leg("AutoSwapOut", 3)),
+        );
+
+        assert_eq!(pattern.get_total_jito_tips(), 3_500);
+        assert_eq!(pattern.get_jito_tip_cost(), 3_500.0 / 1e9);
+        assert!(pattern.to_summary().contains("Jito Tips Paid: 3500\n"));
+    }
+
+    #[test]
+    fn on_complete_fires_once_per_pattern_when_notified() {
+        let calls: Rc<Cell<usize>> = Rc::new(Cell::new(0));
+        let counter: Rc<Cell<usize>> = calls.clone();
+
+        let mut tracker: PatternTracker = PatternTracker::new();
+        tracker.set_on_complete(move |_| counter.set(counter.get() + 1));
+        feed_sandwich(&mut tracker, 0);
+        feed_sandwich(&mut tracker, 3);
+
+        // Nothing fires until the patterns have been enriched and handed back
+        assert_eq!(calls.get(), 0);
+
+        let completed: Vec<Pattern> = tracker.take_completed();
+        assert_eq!(completed.len(), 2);
+
+        tracker.notify_complete(&completed);
+        assert_eq!(calls.get(), 2);
+    }
 }